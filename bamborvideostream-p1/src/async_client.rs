@@ -0,0 +1,157 @@
+//! A non-blocking client for P1 devices based on `tokio`
+//!
+//! # Example
+//! ```rust no_run
+//! use bamborvideostream_p1::{AsyncConnection, ConnectOptions};
+//!
+//! # async fn example() -> Result<(), bamborvideostream_p1::Error> {
+//! let connection = AsyncConnection::connect("192.168.1.42:6000", &ConnectOptions::default()).await?;
+//! let mut session = connection.login("12345678").await?;
+//! let frame = session.frame().await?;
+//! println!("Received {} bytes", frame.jpeg.len());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    client::ConnectOptions,
+    error::Error,
+    protocol::{self, Frame, FrameHeader, FRAME_HEADER_SIZE},
+    tls::{self, AsyncTlsStream},
+};
+use futures_core::Stream;
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time,
+};
+
+/// Runs the given future with an optional timeout
+async fn with_timeout<F, T>(timeout: Option<Duration>, future: F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    match timeout {
+        Some(timeout) => time::timeout(timeout, future).await.map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?,
+        None => future.await,
+    }
+}
+
+/// A non-blocking TLS connection to a P1 device
+#[derive(Debug)]
+pub struct AsyncConnection {
+    /// The TLS connection
+    connection: AsyncTlsStream,
+    /// The read and write timeout
+    timeout: Option<Duration>,
+    /// The maximum accepted frame size
+    max_frame_size: u32,
+}
+impl AsyncConnection {
+    /// Creates a new connection to a P1 device
+    pub async fn connect(address: &str, options: &ConnectOptions) -> Result<Self, Error> {
+        // Connect to the device and perform the TLS handshake
+        let connection = with_timeout(options.timeout, async {
+            let connection = TcpStream::connect(address).await?;
+            tls::connect_async(connection, protocol::server_name(address), options).await
+        })
+        .await?;
+
+        // Init self
+        Ok(Self { connection, timeout: options.timeout, max_frame_size: options.max_frame_size })
+    }
+
+    /// Performs a login to the device to get a session
+    pub async fn login(mut self, pin: &str) -> Result<AsyncSession, Error> {
+        // Send login packet
+        let packet = protocol::login_packet(pin)?;
+        with_timeout(self.timeout, async { Ok(self.connection.write_all(&packet).await?) }).await?;
+        Ok(AsyncSession { connection: self.connection, timeout: self.timeout, max_frame_size: self.max_frame_size })
+    }
+}
+
+/// A non-blocking authenticated session to a P1 device
+#[derive(Debug)]
+pub struct AsyncSession {
+    /// The TLS connection
+    connection: AsyncTlsStream,
+    /// The read and write timeout
+    timeout: Option<Duration>,
+    /// The maximum accepted frame size
+    max_frame_size: u32,
+}
+impl AsyncSession {
+    /// Receives the next frame from the device
+    pub async fn frame(&mut self) -> Result<Frame, Error> {
+        with_timeout(self.timeout, async {
+            // Read the frame header
+            let mut header = [0; FRAME_HEADER_SIZE];
+            self.connection.read_exact(&mut header).await?;
+            let header = FrameHeader::parse(header);
+
+            // Read JPEG image
+            let mut jpeg = vec![0; header.check_size(self.max_frame_size)?];
+            self.connection.read_exact(&mut jpeg).await?;
+            Ok(Frame { header, received: Instant::now(), jpeg })
+        })
+        .await
+    }
+
+    /// Converts the session into a stream of received frames
+    ///
+    /// # Note
+    /// The stream ends after the first error, since the framing cannot be recovered afterwards.
+    pub fn into_frames(self) -> AsyncFrames {
+        AsyncFrames { session: Some(self), pending: None }
+    }
+}
+
+/// The future to receive the next frame, which hands the session back on completion
+type PendingFrame = Pin<Box<dyn Future<Output = (AsyncSession, Result<Frame, Error>)> + Send>>;
+
+/// A stream over the frames of a session
+pub struct AsyncFrames {
+    /// The idle session or `None` if a frame is pending or the stream is exhausted
+    session: Option<AsyncSession>,
+    /// The pending frame, if any
+    pending: Option<PendingFrame>,
+}
+impl Stream for AsyncFrames {
+    type Item = Result<Frame, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // Start receiving the next frame if necessary
+        if self.pending.is_none() {
+            let Some(mut session) = self.session.take() else {
+                // The stream is exhausted
+                return Poll::Ready(None);
+            };
+            self.pending = Some(Box::pin(async move {
+                let result = session.frame().await;
+                (session, result)
+            }));
+        }
+
+        // Poll the pending frame
+        let Some(pending) = self.pending.as_mut() else {
+            return Poll::Ready(None);
+        };
+        let Poll::Ready((session, result)) = pending.as_mut().poll(cx) else {
+            return Poll::Pending;
+        };
+
+        // Keep the session only if the framing is still intact
+        self.pending = None;
+        if result.is_ok() {
+            self.session = Some(session);
+        }
+        Poll::Ready(Some(result))
+    }
+}
@@ -0,0 +1,200 @@
+//! A blocking client for P1 devices
+
+use crate::{
+    error::Error,
+    protocol::{self, Frame, FrameHeader, FRAME_HEADER_SIZE},
+    tls::{self, TlsStream},
+};
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+/// The connection options, shared by the blocking and the asynchronous client
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    /// The read and write timeout of the underlying socket
+    pub timeout: Option<Duration>,
+    /// Whether to accept invalid or self-signed certificates
+    ///
+    /// # Discussion
+    /// Devices use a self-signed certificate, so this defaults to `true`.
+    pub accept_invalid_certs: bool,
+    /// The SHA-256 fingerprint of the DER-encoded device certificate to pin
+    ///
+    /// # Discussion
+    /// If set, the certificate chain is not validated and the connection is rejected unless the fingerprint matches.
+    pub pinned_sha256: Option<[u8; 32]>,
+    /// The maximum accepted frame size in bytes to limit allocations for bogus headers
+    pub max_frame_size: u32,
+}
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Some(Duration::from_secs(5)),
+            accept_invalid_certs: true,
+            pinned_sha256: None,
+            max_frame_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// A TLS connection to a P1 device
+#[derive(Debug)]
+pub struct Connection {
+    /// The TLS connection
+    connection: TlsStream,
+    /// The maximum accepted frame size
+    max_frame_size: u32,
+}
+impl Connection {
+    /// Creates a new connection to a P1 device
+    pub fn connect(address: &str, options: &ConnectOptions) -> Result<Self, Error> {
+        // Connect to the device
+        let connection = TcpStream::connect(address)?;
+        connection.set_read_timeout(options.timeout)?;
+        connection.set_write_timeout(options.timeout)?;
+
+        // Create a TLS stream from the TCP connection
+        let connection = tls::connect(connection, protocol::server_name(address), options)?;
+
+        // Init self
+        Ok(Self { connection, max_frame_size: options.max_frame_size })
+    }
+
+    /// Gets the DER-encoded certificate presented by the device, if any
+    pub fn peer_certificate(&self) -> Result<Option<Vec<u8>>, Error> {
+        tls::peer_certificate(&self.connection)
+    }
+
+    /// Performs a login to the device to get a session
+    pub fn login(mut self, pin: &str) -> Result<Session, Error> {
+        // Send login packet
+        let packet = protocol::login_packet(pin)?;
+        self.connection.write_all(&packet)?;
+        Ok(Session { connection: self.connection, max_frame_size: self.max_frame_size })
+    }
+}
+
+/// An authenticated session to a P1 device
+///
+/// # Note
+/// The session is generic over the underlying stream, so that the framing can also be used with other transports (e.g.
+/// in-memory streams); sessions from [`Connection::login`] use the TLS stream.
+#[derive(Debug)]
+pub struct Session<S = TlsStream> {
+    /// The underlying connection
+    connection: S,
+    /// The maximum accepted frame size
+    max_frame_size: u32,
+}
+impl<S> Session<S>
+where
+    S: Read,
+{
+    /// Creates a session on top of an already authenticated stream
+    pub fn with_stream(connection: S, max_frame_size: u32) -> Self {
+        Self { connection, max_frame_size }
+    }
+
+    /// Receives the next frame from the device
+    pub fn frame(&mut self) -> Result<Frame, Error> {
+        // Read the frame header
+        let mut header = [0; FRAME_HEADER_SIZE];
+        self.connection.read_exact(&mut header)?;
+        let header = FrameHeader::parse(header);
+
+        // Read JPEG image
+        let mut jpeg = vec![0; header.check_size(self.max_frame_size)?];
+        self.connection.read_exact(&mut jpeg)?;
+        Ok(Frame { header, received: Instant::now(), jpeg })
+    }
+
+    /// An iterator over the received frames
+    ///
+    /// # Note
+    /// The iterator ends after the first error, since the framing cannot be recovered afterwards.
+    pub fn frames(&mut self) -> Frames<'_, S> {
+        Frames { session: Some(self) }
+    }
+}
+impl Session {
+    /// Gracefully closes the session by sending a TLS close notification
+    pub fn close(mut self) -> Result<(), Error> {
+        tls::shutdown(&mut self.connection)
+    }
+}
+
+/// An iterator over the frames of a session
+#[derive(Debug)]
+pub struct Frames<'a, S = TlsStream> {
+    /// The session or `None` if the iterator is exhausted
+    session: Option<&'a mut Session<S>>,
+}
+impl<S> Iterator for Frames<'_, S>
+where
+    S: Read,
+{
+    type Item = Result<Frame, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Receive the next frame and fuse the iterator on error
+        let result = self.session.as_mut()?.frame();
+        if result.is_err() {
+            self.session = None;
+        }
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Creates a stream with the given frames
+    fn stream(frames: &[(FrameHeader, &[u8])]) -> Cursor<Vec<u8>> {
+        let stream = frames.iter().flat_map(|(header, payload)| [header.raw.as_slice(), payload].concat()).collect();
+        Cursor::new(stream)
+    }
+
+    /// Frames are read according to their headers
+    #[test]
+    fn frames() {
+        let stream = stream(&[(FrameHeader::new(3), b"abc"), (FrameHeader::new(0), b""), (FrameHeader::new(2), b"de")]);
+        let mut session = Session::with_stream(stream, 16);
+        let frames: Vec<_> = session.frames().take(3).map(|frame| frame.unwrap().jpeg).collect();
+        assert_eq!(frames, [b"abc".as_slice(), b"", b"de"]);
+    }
+
+    /// The iterator ends after a truncated frame
+    #[test]
+    fn frames_fuse_after_truncation() {
+        let stream = stream(&[(FrameHeader::new(3), b"abc"), (FrameHeader::new(4), b"de")]);
+        let mut session = Session::with_stream(stream, 16);
+        let mut frames = session.frames();
+        assert_eq!(frames.next().unwrap().unwrap().jpeg, b"abc");
+        assert!(matches!(frames.next(), Some(Err(Error::Io(_)))));
+        assert!(frames.next().is_none());
+        assert!(frames.next().is_none());
+    }
+
+    /// The iterator ends after an oversized frame without reading its payload
+    #[test]
+    fn frames_fuse_after_oversized_frame() {
+        let stream = stream(&[(FrameHeader::new(17), b""), (FrameHeader::new(1), b"a")]);
+        let mut session = Session::with_stream(stream, 16);
+        let mut frames = session.frames();
+        assert!(matches!(frames.next(), Some(Err(Error::FrameTooLarge { size: 17, limit: 16 }))));
+        assert!(frames.next().is_none());
+        assert_eq!(session.connection.position(), FRAME_HEADER_SIZE as u64);
+    }
+
+    /// An empty stream yields an error instead of a frame
+    #[test]
+    fn frames_empty_stream() {
+        let mut session = Session::with_stream(Cursor::new(Vec::new()), 16);
+        assert!(matches!(session.frame(), Err(Error::Io(_))));
+    }
+}
@@ -0,0 +1,98 @@
+//! Implements the crate's error type
+
+#[cfg(feature = "native-tls")]
+use std::net::TcpStream;
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+/// The crate's error type
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// An I/O error on the underlying connection
+    Io(io::Error),
+    /// A TLS error of the `native-tls` backend
+    #[cfg(feature = "native-tls")]
+    Tls(native_tls::Error),
+    /// A TLS error of the `rustls` backend
+    #[cfg(feature = "rustls")]
+    Rustls(rustls::Error),
+    /// The server name is not a valid DNS name or IP address
+    InvalidServerName {
+        /// The invalid server name
+        name: String,
+    },
+    /// The peer certificate does not match the pinned fingerprint
+    CertificatePinMismatch,
+    /// The PIN is too long to fit into the login packet
+    InvalidPin {
+        /// The length of the PIN in bytes
+        length: usize,
+    },
+    /// The device announced a frame that exceeds the configured size limit
+    FrameTooLarge {
+        /// The announced frame size
+        size: u32,
+        /// The configured size limit
+        limit: u32,
+    },
+}
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "In/out error: {e}"),
+            #[cfg(feature = "native-tls")]
+            Self::Tls(e) => write!(f, "TLS error: {e}"),
+            #[cfg(feature = "rustls")]
+            Self::Rustls(e) => write!(f, "TLS error: {e}"),
+            Self::InvalidServerName { name } => write!(f, "Invalid TLS server name: {name}"),
+            Self::CertificatePinMismatch => write!(f, "Peer certificate does not match the pinned fingerprint"),
+            Self::InvalidPin { length } => write!(f, "Invalid PIN length: {length}"),
+            Self::FrameTooLarge { size, limit } => write!(f, "Frame size {size} exceeds the limit of {limit} bytes"),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            #[cfg(feature = "native-tls")]
+            Self::Tls(e) => Some(e),
+            #[cfg(feature = "rustls")]
+            Self::Rustls(e) => Some(e),
+            Self::InvalidServerName { .. }
+            | Self::CertificatePinMismatch
+            | Self::InvalidPin { .. }
+            | Self::FrameTooLarge { .. } => None,
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+#[cfg(feature = "native-tls")]
+impl From<native_tls::Error> for Error {
+    fn from(error: native_tls::Error) -> Self {
+        Self::Tls(error)
+    }
+}
+#[cfg(feature = "native-tls")]
+impl From<native_tls::HandshakeError<TcpStream>> for Error {
+    fn from(error: native_tls::HandshakeError<TcpStream>) -> Self {
+        match error {
+            native_tls::HandshakeError::Failure(e) => Self::Tls(e),
+            // A blocking handshake can only be interrupted by a socket timeout
+            native_tls::HandshakeError::WouldBlock(_) => Self::Io(io::ErrorKind::TimedOut.into()),
+        }
+    }
+}
+#[cfg(feature = "rustls")]
+impl From<rustls::Error> for Error {
+    fn from(error: rustls::Error) -> Self {
+        Self::Rustls(error)
+    }
+}
@@ -0,0 +1,29 @@
+#![doc = include_str!("../README.md")]
+// Clippy lints
+#![warn(clippy::large_stack_arrays)]
+#![warn(clippy::arithmetic_side_effects)]
+#![warn(clippy::expect_used)]
+#![warn(clippy::unwrap_used)]
+#![warn(clippy::indexing_slicing)]
+#![warn(clippy::panic)]
+#![warn(clippy::todo)]
+#![warn(clippy::unimplemented)]
+#![warn(clippy::unreachable)]
+#![warn(clippy::missing_panics_doc)]
+#![warn(clippy::allow_attributes_without_reason)]
+#![warn(clippy::cognitive_complexity)]
+
+#[cfg(any(feature = "async", feature = "async-rustls"))]
+mod async_client;
+mod client;
+mod error;
+pub mod protocol;
+pub mod tls;
+
+#[cfg(any(feature = "async", feature = "async-rustls"))]
+pub use crate::async_client::{AsyncConnection, AsyncFrames, AsyncSession};
+pub use crate::{
+    client::{ConnectOptions, Connection, Frames, Session},
+    error::Error,
+    protocol::{Frame, FrameHeader},
+};
@@ -0,0 +1,161 @@
+//! The transport-independent parts of the P1 camera protocol
+
+use crate::error::Error;
+use std::time::Instant;
+
+/// The login packet template
+pub const LOGIN_PACKET: [u8; 80] = [
+    // v1
+    0x40, 0x00, 0x00, 0x00, 0x00, 0x30, 0x00, 0x00, // 8
+    // v2
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 8
+    // Username
+    b'b', b'b', b'l', b'p', 0x00, 0x00, 0x00, 0x00, // 8
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 16
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 24
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 32
+    // PIN
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 8
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 16
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 24
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 32
+];
+/// The offset of the PIN field in the login packet
+pub const LOGIN_PACKET_PIN: usize = 48;
+/// The size of the header that precedes each JPEG frame
+pub const FRAME_HEADER_SIZE: usize = 16;
+/// The version bytes that devices send after the frame size
+pub const FRAME_HEADER_VERSION: [u8; 12] = [0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+/// Assembles the login packet for the given PIN
+pub fn login_packet(pin: &str) -> Result<[u8; LOGIN_PACKET.len()], Error> {
+    // Validate PIN length
+    let _pin_len @ ..32 = pin.len() else {
+        // Reject invalid PIN
+        return Err(Error::InvalidPin { length: pin.len() });
+    };
+
+    // Assemble login packet
+    let mut packet = LOGIN_PACKET;
+    #[allow(clippy::indexing_slicing, reason = "Offset is always valid and PIN length is checked")]
+    packet[LOGIN_PACKET_PIN..][..pin.len()].copy_from_slice(pin.as_bytes());
+    Ok(packet)
+}
+
+/// Gets the TLS server name from a `host:port` address
+pub(crate) fn server_name(address: &str) -> &str {
+    let host = address.rsplit_once(':').map(|(host, _)| host).unwrap_or(address);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// The header that precedes each JPEG frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// The size of the JPEG payload in bytes
+    pub size: u32,
+    /// The raw header bytes as received from the device
+    pub raw: [u8; FRAME_HEADER_SIZE],
+}
+impl FrameHeader {
+    /// Creates a new header with the default version bytes for a payload of the given size
+    pub fn new(size: u32) -> Self {
+        let mut raw = [0; FRAME_HEADER_SIZE];
+        let (size_field, version_field) = raw.split_at_mut(4);
+        size_field.copy_from_slice(&size.to_le_bytes());
+        version_field.copy_from_slice(&FRAME_HEADER_VERSION);
+        Self { size, raw }
+    }
+
+    /// Parses a raw frame header
+    pub fn parse(raw: [u8; FRAME_HEADER_SIZE]) -> Self {
+        // Read JPEG size and ignore the 12 bytes version stuff
+        let [s0, s1, s2, s3, _version @ ..] = raw;
+        let size = u32::from_le_bytes([s0, s1, s2, s3]);
+        Self { size, raw }
+    }
+
+    /// Ensures that the announced payload size does not exceed `limit`
+    pub fn check_size(&self, limit: u32) -> Result<usize, Error> {
+        match self.size {
+            size if size > limit => Err(Error::FrameTooLarge { size, limit }),
+            size => Ok(size as usize),
+        }
+    }
+}
+
+/// A JPEG frame received from a device
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// The frame header
+    pub header: FrameHeader,
+    /// When the frame has been received completely
+    pub received: Instant,
+    /// The JPEG payload
+    pub jpeg: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The login packet is the template with the PIN at its offset
+    #[test]
+    fn login_packet_layout() {
+        let packet = login_packet("12345678").unwrap();
+        assert_eq!(packet.len(), 80);
+        assert_eq!(packet[..LOGIN_PACKET_PIN], LOGIN_PACKET[..LOGIN_PACKET_PIN]);
+        assert_eq!(packet[..20], [0x40, 0, 0, 0, 0, 0x30, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, b'b', b'b', b'l', b'p']);
+        assert_eq!(&packet[LOGIN_PACKET_PIN..][..8], b"12345678");
+        assert!(packet[LOGIN_PACKET_PIN + 8..].iter().all(|byte| *byte == 0));
+        assert_eq!(login_packet("").unwrap(), LOGIN_PACKET);
+    }
+
+    /// PINs must leave room for the terminating zero byte
+    #[test]
+    fn login_packet_pin_length() {
+        let longest = "9".repeat(31);
+        let packet = login_packet(&longest).unwrap();
+        assert_eq!(&packet[LOGIN_PACKET_PIN..][..31], longest.as_bytes());
+        assert_eq!(packet[79], 0);
+
+        for length in [32, 33, 100] {
+            let result = login_packet(&"9".repeat(length));
+            assert!(matches!(result, Err(Error::InvalidPin { length: actual }) if actual == length), "length {length}");
+        }
+        assert!(matches!(login_packet(&"ä".repeat(16)), Err(Error::InvalidPin { length: 32 })));
+    }
+
+    /// Headers carry the payload size as little endian followed by the version bytes
+    #[test]
+    fn frame_header() {
+        let header = FrameHeader::new(0x0102_0304);
+        assert_eq!(header.raw[..4], [0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(header.raw[4..], FRAME_HEADER_VERSION);
+        assert_eq!(FrameHeader::parse(header.raw), header);
+
+        let mut raw = [0xFF; FRAME_HEADER_SIZE];
+        raw[..4].copy_from_slice(&[0x10, 0x00, 0x00, 0x00]);
+        let parsed = FrameHeader::parse(raw);
+        assert_eq!((parsed.size, parsed.raw), (16, raw));
+    }
+
+    /// The size check accepts sizes up to and including the limit
+    #[test]
+    fn frame_header_check_size() {
+        assert_eq!(FrameHeader::new(0).check_size(0).unwrap(), 0);
+        assert_eq!(FrameHeader::new(1024).check_size(1024).unwrap(), 1024);
+        assert!(matches!(
+            FrameHeader::new(1025).check_size(1024),
+            Err(Error::FrameTooLarge { size: 1025, limit: 1024 })
+        ));
+        assert!(matches!(FrameHeader::new(u32::MAX).check_size(u32::MAX - 1), Err(Error::FrameTooLarge { .. })));
+    }
+
+    /// The server name is the host without port and brackets
+    #[test]
+    fn server_name() {
+        assert_eq!(super::server_name("192.168.1.42:6000"), "192.168.1.42");
+        assert_eq!(super::server_name("[fe80::1]:6000"), "fe80::1");
+        assert_eq!(super::server_name("printer.local"), "printer.local");
+    }
+}
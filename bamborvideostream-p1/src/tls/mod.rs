@@ -0,0 +1,50 @@
+//! The TLS backends
+//!
+//! # Note
+//! If both the `native-tls` and the `rustls` feature are enabled, `rustls` takes precedence.
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("Either the `native-tls` or the `rustls` feature must be enabled");
+#[cfg(all(feature = "async", feature = "rustls", not(feature = "async-rustls")))]
+compile_error!("The `async` feature requires `async-rustls` if the `rustls` backend is enabled");
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+mod native_tls;
+#[cfg(feature = "rustls")]
+mod rustls;
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+pub use self::native_tls::{connect, peer_certificate, shutdown, TlsStream};
+#[cfg(all(feature = "async", not(feature = "rustls")))]
+pub use self::native_tls::{connect_async, AsyncTlsStream};
+#[cfg(feature = "rustls")]
+pub use self::rustls::{connect, peer_certificate, shutdown, TlsStream};
+#[cfg(feature = "async-rustls")]
+pub use self::rustls::{connect_async, AsyncTlsStream};
+
+use crate::{client::ConnectOptions, error::Error};
+use sha2::{Digest, Sha256};
+
+/// Whether the certificate chain must not be validated because the options disable validation or pin a certificate
+fn skip_validation(options: &ConnectOptions) -> bool {
+    options.accept_invalid_certs || options.pinned_sha256.is_some()
+}
+
+/// Validates the DER-encoded peer certificate against the pinned SHA-256 fingerprint, if any
+fn verify_pin(options: &ConnectOptions, certificate: Option<&[u8]>) -> Result<(), Error> {
+    // Check whether a certificate is pinned at all
+    let Some(pinned) = &options.pinned_sha256 else {
+        // No pinning
+        return Ok(());
+    };
+
+    // Compare the fingerprint
+    let Some(certificate) = certificate else {
+        // The peer did not present a certificate
+        return Err(Error::CertificatePinMismatch);
+    };
+    match Sha256::digest(certificate).as_slice() == pinned {
+        true => Ok(()),
+        false => Err(Error::CertificatePinMismatch),
+    }
+}
@@ -0,0 +1,61 @@
+//! The `native-tls` backend
+
+use crate::{client::ConnectOptions, error::Error};
+use native_tls::{Protocol, TlsConnector};
+use std::net::TcpStream;
+
+/// A blocking TLS stream
+pub type TlsStream = native_tls::TlsStream<TcpStream>;
+
+/// Creates a connector for the given options
+fn connector(options: &ConnectOptions) -> Result<TlsConnector, Error> {
+    let skip_validation = super::skip_validation(options);
+    let connector = TlsConnector::builder()
+        .danger_accept_invalid_certs(skip_validation)
+        .danger_accept_invalid_hostnames(skip_validation)
+        .min_protocol_version(Some(Protocol::Tlsv12))
+        .build()?;
+    Ok(connector)
+}
+
+/// Performs a TLS handshake over the given TCP stream and validates the certificate pin, if any
+pub fn connect(stream: TcpStream, server_name: &str, options: &ConnectOptions) -> Result<TlsStream, Error> {
+    // Perform the handshake
+    let stream = connector(options)?.connect(server_name, stream)?;
+
+    // Validate the certificate pin
+    let certificate = peer_certificate(&stream)?;
+    super::verify_pin(options, certificate.as_deref())?;
+    Ok(stream)
+}
+
+/// Gets the DER-encoded peer certificate, if any
+pub fn peer_certificate(stream: &TlsStream) -> Result<Option<Vec<u8>>, Error> {
+    Ok(stream.peer_certificate()?.map(|certificate| certificate.to_der()).transpose()?)
+}
+
+/// Sends a TLS close notification to gracefully close the session
+pub fn shutdown(stream: &mut TlsStream) -> Result<(), Error> {
+    Ok(stream.shutdown()?)
+}
+
+/// A non-blocking TLS stream
+#[cfg(feature = "async")]
+pub type AsyncTlsStream = tokio_native_tls::TlsStream<tokio::net::TcpStream>;
+
+/// Performs a non-blocking TLS handshake over the given TCP stream and validates the certificate pin, if any
+#[cfg(feature = "async")]
+pub async fn connect_async(
+    stream: tokio::net::TcpStream,
+    server_name: &str,
+    options: &ConnectOptions,
+) -> Result<AsyncTlsStream, Error> {
+    // Perform the handshake
+    let connector = tokio_native_tls::TlsConnector::from(connector(options)?);
+    let stream = connector.connect(server_name, stream).await?;
+
+    // Validate the certificate pin
+    let certificate = stream.get_ref().peer_certificate()?.map(|certificate| certificate.to_der()).transpose()?;
+    super::verify_pin(options, certificate.as_deref())?;
+    Ok(stream)
+}
@@ -0,0 +1,142 @@
+//! The `rustls` backend
+
+use crate::{client::ConnectOptions, error::Error};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, ring, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    version::{TLS12, TLS13},
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme, StreamOwned,
+};
+use std::{io, net::TcpStream, sync::Arc};
+
+/// A blocking TLS stream
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// A certificate verifier that accepts any certificate but still validates the handshake signatures
+///
+/// # Note
+/// Devices use self-signed certificates; if a certificate is pinned, the pin is checked after the handshake.
+#[derive(Debug)]
+struct AcceptAnyCertificate {
+    /// The crypto provider to validate the signatures
+    provider: Arc<CryptoProvider>,
+}
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Creates a client config for the given options
+fn config(options: &ConnectOptions) -> Result<Arc<ClientConfig>, Error> {
+    // Create the config builder with TLS 1.2 as minimum version
+    let provider = Arc::new(ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone()).with_protocol_versions(&[&TLS13, &TLS12])?;
+
+    // Select the certificate verifier
+    let config = match super::skip_validation(options) {
+        true => {
+            let verifier = Arc::new(AcceptAnyCertificate { provider });
+            builder.dangerous().with_custom_certificate_verifier(verifier).with_no_client_auth()
+        }
+        false => {
+            let roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+            builder.with_root_certificates(roots).with_no_client_auth()
+        }
+    };
+    Ok(Arc::new(config))
+}
+
+/// Parses the server name
+fn server_name(server_name: &str) -> Result<ServerName<'static>, Error> {
+    ServerName::try_from(server_name.to_string())
+        .map_err(|_| Error::InvalidServerName { name: server_name.to_string() })
+}
+
+/// Maps I/O errors that wrap a TLS error back to the TLS error
+fn map_io_error(error: io::Error) -> Error {
+    let tls_error = error.get_ref().and_then(|error| error.downcast_ref::<rustls::Error>());
+    match tls_error {
+        Some(tls_error) => Error::Rustls(tls_error.clone()),
+        None => Error::Io(error),
+    }
+}
+
+/// Performs a TLS handshake over the given TCP stream and validates the certificate pin, if any
+pub fn connect(mut stream: TcpStream, server_name_: &str, options: &ConnectOptions) -> Result<TlsStream, Error> {
+    // Perform the handshake
+    let mut connection = ClientConnection::new(config(options)?, server_name(server_name_)?)?;
+    while connection.is_handshaking() {
+        connection.complete_io(&mut stream).map_err(map_io_error)?;
+    }
+
+    // Validate the certificate pin
+    let stream = StreamOwned::new(connection, stream);
+    super::verify_pin(options, peer_certificate(&stream)?.as_deref())?;
+    Ok(stream)
+}
+
+/// Gets the DER-encoded peer certificate, if any
+pub fn peer_certificate(stream: &TlsStream) -> Result<Option<Vec<u8>>, Error> {
+    let certificate = stream.conn.peer_certificates().and_then(|certificates| certificates.first());
+    Ok(certificate.map(|certificate| certificate.to_vec()))
+}
+
+/// Sends a TLS close notification to gracefully close the session
+pub fn shutdown(stream: &mut TlsStream) -> Result<(), Error> {
+    stream.conn.send_close_notify();
+    stream.conn.complete_io(&mut stream.sock).map_err(map_io_error)?;
+    Ok(())
+}
+
+/// A non-blocking TLS stream
+#[cfg(feature = "async-rustls")]
+pub type AsyncTlsStream = tokio_rustls::client::TlsStream<tokio::net::TcpStream>;
+
+/// Performs a non-blocking TLS handshake over the given TCP stream and validates the certificate pin, if any
+#[cfg(feature = "async-rustls")]
+pub async fn connect_async(
+    stream: tokio::net::TcpStream,
+    server_name_: &str,
+    options: &ConnectOptions,
+) -> Result<AsyncTlsStream, Error> {
+    // Perform the handshake
+    let connector = tokio_rustls::TlsConnector::from(config(options)?);
+    let stream = connector.connect(server_name(server_name_)?, stream).await.map_err(map_io_error)?;
+
+    // Validate the certificate pin
+    let (_, connection) = stream.get_ref();
+    let certificate = connection.peer_certificates().and_then(|certificates| certificates.first());
+    super::verify_pin(options, certificate.map(|certificate| certificate.as_ref()))?;
+    Ok(stream)
+}
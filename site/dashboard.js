@@ -0,0 +1,197 @@
+/**
+ * The duration in milliseconds between each refresh of the device status and thumbnails
+ */
+const DASHBOARD_REFRESH_MS = 5000;
+
+/**
+ * Builds the URL for the given API path with the given auth token
+ *
+ * @param {string} path The API path including the query string
+ * @param {string} auth The API auth token
+ * @returns {string} The URL
+ */
+function dashboard_url(path, auth) {
+    const separator = path.includes("?") ? "&" : "?";
+    return path + separator + new URLSearchParams({ auth: auth }).toString();
+}
+
+/**
+ * Displays the dashboard and starts the background-task
+ *
+ * @param {string} auth The API auth token
+ * @param {string} endpoint The device listing API endpoint
+ */
+function dashboard(auth, endpoint) {
+    // Set an on-click handler to close the full-size view
+    const close = /** @type {HTMLElement} */
+        (document.getElementById("full-view-close"));
+    close.addEventListener("click", dashboard_close);
+
+    // Show the dashboard and start the refresh
+    switch_component("loading", "dashboard");
+    dashboard_refresh(auth, endpoint);
+    setInterval(() => dashboard_refresh(auth, endpoint), DASHBOARD_REFRESH_MS);
+}
+
+/**
+ * Fetches the device list and updates the tiles
+ *
+ * @param {string} auth The API auth token
+ * @param {string} endpoint The device listing API endpoint
+ */
+function dashboard_refresh(auth, endpoint) {
+    fetch(dashboard_url(endpoint, auth), { signal: AbortSignal.timeout(LOADING_TIMEOUT_MS) })
+        .then(response => response.ok ? response.json() : fail("failed to list devices: " + response.status))
+        .then(listing => dashboard_update(auth, listing["devices"] ?? fail("no devices")))
+        .catch(e => console.log("Failed to refresh dashboard: " + e));
+}
+
+/**
+ * Updates the tiles for the given devices
+ *
+ * @param {string} auth The API auth token
+ * @param {Array<Object<string, any>>} devices The listed devices
+ */
+function dashboard_update(auth, devices) {
+    // Remove the tiles of removed devices
+    const tiles = /** @type {HTMLDivElement} */
+        (document.getElementById("dashboard-tiles"));
+    const names = devices.map(device => "tile-" + device["name"]);
+    for (const tile of Array.from(tiles.children)) {
+        if (!names.includes(tile.id)) {
+            tiles.removeChild(tile);
+        }
+    }
+
+    // Create or update the tile of each device
+    for (const device of devices) {
+        const tile = document.getElementById("tile-" + device["name"]) ?? dashboard_tile(auth, device);
+        tiles.appendChild(tile);
+
+        // Update the status
+        const status = /** @type {HTMLDivElement} */ (tile.querySelector(".tile-status"));
+        const frame_age = device["frame_age_s"] === null ? "" : ", " + Math.round(device["frame_age_s"]) + " s";
+        status.innerText = device["state"] + frame_age;
+        tile.dataset.state = device["state"];
+
+        // Update the thumbnail
+        const image = /** @type {HTMLImageElement} */ (tile.querySelector("img"));
+        if (device["thumbnail_url"] !== null) {
+            fetch(dashboard_url(device["thumbnail_url"], auth), { signal: AbortSignal.timeout(LOADING_TIMEOUT_MS) })
+                .then(response => response.blob())
+                .then(image_blob => dashboard_show(image, image_blob))
+                .catch(() => dashboard_show(image, null));
+        }
+    }
+}
+
+/**
+ * Creates the tile for the given device
+ *
+ * @param {string} auth The API auth token
+ * @param {Object<string, any>} device The listed device
+ * @returns {HTMLDivElement} The tile
+ */
+function dashboard_tile(auth, device) {
+    // Create the tile with the loading frame
+    const tile = document.createElement("div");
+    tile.id = "tile-" + device["name"];
+    tile.className = "tile";
+    const image = document.createElement("img");
+    // @ts-ignore - is from `loading.js`
+    image.src = LOADING_FRAME_URL;
+    tile.appendChild(image);
+
+    // Add the device name and the status line
+    const name = document.createElement("div");
+    name.className = "tile-name";
+    name.innerText = device["model"] === null ? device["name"] : device["name"] + " (" + device["model"] + ")";
+    tile.appendChild(name);
+    const status = document.createElement("div");
+    status.className = "tile-status";
+    tile.appendChild(status);
+
+    // Open the full-size view on click
+    tile.addEventListener("click", () => dashboard_open(auth, device));
+    return tile;
+}
+
+/**
+ * Shows the given image in the given image element
+ *
+ * @param {HTMLImageElement} image The image element
+ * @param {Blob|null} image_blob The image to show
+ */
+function dashboard_show(image, image_blob) {
+    // Keep the last image if there is no new one
+    if (image_blob === null || image_blob.size === 0) {
+        return;
+    }
+
+    // Set image to BLOB and release the previous one
+    const previous = image.src;
+    image.src = URL.createObjectURL(image_blob);
+    if (previous.startsWith("blob:")) {
+        URL.revokeObjectURL(previous);
+    }
+}
+
+/**
+ * Opens the full-size view for the given device
+ *
+ * @param {string} auth The API auth token
+ * @param {Object<string, any>} device The listed device
+ */
+function dashboard_open(auth, device) {
+    // Set the device name
+    const name = /** @type {HTMLDivElement} */
+        (document.getElementById("full-view-name"));
+    name.innerText = device["name"];
+
+    // Play the MJPEG stream; H.264 streams cannot be shown by browsers, so link them instead
+    const image = /** @type {HTMLImageElement} */
+        (document.getElementById("full-view-image"));
+    const link = /** @type {HTMLAnchorElement} */
+        (document.getElementById("full-view-link"));
+    if (device["thumbnail_url"] !== null) {
+        image.src = dashboard_url(device["stream_url"], auth);
+        image.style.display = "block";
+        link.style.display = "none";
+    } else {
+        link.href = dashboard_url(device["stream_url"], auth);
+        image.style.display = "none";
+        link.style.display = "block";
+    }
+    switch_component("dashboard", "full-view");
+}
+
+/**
+ * Closes the full-size view and returns to the dashboard
+ */
+function dashboard_close() {
+    // Stop the stream
+    const image = /** @type {HTMLImageElement} */
+        (document.getElementById("full-view-image"));
+    // @ts-ignore - is from `loading.js`
+    image.src = LOADING_FRAME_URL;
+    switch_component("full-view", "dashboard");
+}
+
+/**
+ * Initializes the dashboard page
+ */
+function dashboard_init() {
+    try {
+        // If there is a session, then load it
+        const session = load_session();
+        const endpoint = session["endpoint"] ?? fail("no session endpoint");
+        const auth = session["auth"] ?? '';
+
+        // Display the dashboard
+        dashboard(auth, endpoint);
+    } catch (e) {
+        // Init session
+        console.log("Failed to recover session: " + e);
+        init_session();
+    }
+}
@@ -58,16 +58,23 @@ function init_session_onsubmit(event) {
     // Prevent the browser from submitting the form
     event.preventDefault();
 
-    // Load login info from form
-    const address = /** @type {HTMLInputElement} */
-        (document.getElementById("init-session-address"));
-    const pin =  /** @type {HTMLInputElement} */
-        (document.getElementById("init-session-pin"));
+    // Load the endpoint and the API key from the form
+    const form = /** @type {HTMLFormElement} */
+        (document.getElementById("init-session-form"));
     const auth =  /** @type {HTMLInputElement} */
         (document.getElementById("init-session-auth"));
+    const endpoint = form.dataset.endpoint ?? fail("no form endpoint");
+
+    // Load the device fields from the form
+    /** @type {Object<string, string>} */
+    const query = {};
+    for (const field of form.querySelectorAll("input[name], select[name]")) {
+        const input = /** @type {HTMLInputElement|HTMLSelectElement} */ (field);
+        query[input.name] = input.value;
+    }
 
     // Build and encode the login info
-    const login_object = { "address": address.value, "pin": pin.value, "auth": auth.value };
+    const login_object = { "endpoint": endpoint, "query": query, "auth": auth.value };
     const login_json = JSON.stringify(login_object);
     const login = btoa(login_json);
 
@@ -80,10 +87,10 @@ function init_session_onsubmit(event) {
  * Displays the component to play the images and starts the background-task
  * 
  * @param {string} auth The API auth token
- * @param {string} address The device address
- * @param {string} pin The device PIN
+ * @param {string} endpoint The device API endpoint
+ * @param {Object<string, string>} query The device query fields
  */
-function play_images(auth, address, pin) {
+function play_images(auth, endpoint, query) {
     // Set loading image
     const image = /** @type {HTMLImageElement} */
         (document.getElementById("play-images-image"));
@@ -93,28 +100,28 @@ function play_images(auth, address, pin) {
     // Set the device name
     const deviceaddress = /** @type {HTMLDivElement} */
         (document.getElementById("play-images-deviceaddress"));
-    deviceaddress.innerText = address;
+    deviceaddress.innerText = query["address"] ?? query["url"] ?? endpoint;
 
     // Show the playback div and start the playback
     switch_component("loading", "play-images")
-    setInterval(() => play_images_fetch(auth, address, pin, play_images_show), FRAME_DURATION_MS);
+    setInterval(() => play_images_fetch(auth, endpoint, query, play_images_show), FRAME_DURATION_MS);
 }
 
 /**
  * Displays the component to play the images and starts the background-task
  * 
  * @param {string} auth The API auth token
- * @param {string} address The device address
- * @param {string} pin The device PIN
+ * @param {string} endpoint The device API endpoint
+ * @param {Object<string, string>} query The device query fields
  * @param {function} onCompletion The callback function for when the task is completed
  */
-function play_images_fetch(auth, address, pin, onCompletion) {
+function play_images_fetch(auth, endpoint, query, onCompletion) {
     // Build query string
-    const query_string_obj = new URLSearchParams({ auth: auth, address: address, pin: pin });
+    const query_string_obj = new URLSearchParams({ ...query, auth: auth });
     const query_string = query_string_obj.toString();
     
     // Fetch the new image
-    fetch("/v1/p1?" + query_string, { method: "POST", signal: AbortSignal.timeout(5000) })
+    fetch(endpoint + "?" + query_string, { method: "POST", signal: AbortSignal.timeout(5000) })
         .then(response => response.blob())
         .then(image_blob => onCompletion(image_blob))
         .catch(() => onCompletion(null));
@@ -139,28 +146,36 @@ function play_images_show(image_blob) {
     }
 }
 
+/**
+ * Recovers the session from the URL hash
+ * 
+ * @returns {Object<string, any>} The session object
+ */
+function load_session() {
+    // Try to get session
+    const session_hash = window.location.hash;
+    if (!session_hash.startsWith("#")) {
+        fail("no session available");
+    }
+
+    // Recover session object
+    const session_object = atob(session_hash.substring(1));
+    return JSON.parse(session_object);
+}
+
 /**
  * Initializes the page
  */
 function init() {
     try {
-        // Try to get session
-        const session_hash = window.location.hash;
-        if (!session_hash.startsWith("#")) {
-            fail("no session available");
-        }
-
-        // Recover session object
-        const session_object = atob(session_hash.substring(1));
-        const session = JSON.parse(session_object);
-
         // If there is a session, then load it
-        const address = session["address"] ?? fail("no session address");
-        const pin = session["pin"] ?? fail("no session PIN");
+        const session = load_session();
+        const endpoint = session["endpoint"] ?? fail("no session endpoint");
+        const query = session["query"] ?? fail("no session query");
         const auth = session["auth"] ?? '';
 
         // Display images
-        play_images(auth, address, pin);
+        play_images(auth, endpoint, query);
     } catch (e) {
         // Init session
         console.log("Failed to recover session: " + e);
@@ -0,0 +1,26 @@
+//! Generates a random API key
+
+use crate::{error, error::Error, secret};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+use sha2::{Digest, Sha256};
+
+/// The amount of random bytes per API key
+const APIKEY_SIZE: usize = 32;
+
+/// Generates a random API key and prints it together with the hash for `BAMBORVIDEOSTREAM_APIKEYSHA256`
+pub fn main<I>(args: I) -> Result<(), Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    // Validate the arguments
+    if let Some(arg) = args.into_iter().next() {
+        return Err(error!("Unexpected argument: {arg}"));
+    }
+
+    // Generate the key; it is URL-safe, so that it can be passed via `?auth=<key>` as-is
+    let apikey = BASE64.encode(secret::random::<APIKEY_SIZE>()?);
+    let apikeysha256 = format!("{:x}", Sha256::digest(&apikey));
+    println!("API key: {apikey}");
+    println!("BAMBORVIDEOSTREAM_APIKEYSHA256={apikeysha256}");
+    Ok(())
+}
@@ -0,0 +1,41 @@
+//! Checks the health of a running server, e.g. for a container `HEALTHCHECK`
+
+use crate::{
+    cli, error,
+    error::Error,
+    services::{config::Config, listener::Probe},
+};
+use std::time::Duration;
+
+/// Checks whether all configured listeners of a running server respond
+///
+/// # Note
+/// The listeners are taken from the same config as the server, so the check must run with the same config file and
+/// environment (which is the case for `HEALTHCHECK` within the server's container).
+pub fn main<I>(args: I) -> Result<(), Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    // Parse the arguments
+    let mut timeout = Duration::from_secs(5);
+    let (positional, config_args) = cli::parse_args(args, |arg, value| {
+        match arg {
+            "--timeout-ms" => timeout = Duration::from_millis(value.parse()?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    if let Some(arg) = positional.first() {
+        return Err(error!("Unexpected argument: {arg}"));
+    }
+
+    // Check all listeners
+    let config = Config::load(config_args)?;
+    for listener in &config.BAMBORVIDEOSTREAM_LISTENERS {
+        let probe = Probe::resolve(&listener.address)?;
+        if let Err(e) = probe.check(timeout) {
+            return Err(error!(with: e, "Health check failed for {probe:?}"));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+//! The operational subcommands
+//!
+//! Besides `serve`, these subcommands operate and debug a deployment: they talk to the devices directly, check the
+//! config or the health of a running server, and generate API keys.
+
+pub mod apikey;
+pub mod healthcheck;
+pub mod probe;
+pub mod snapshot;
+pub mod stream;
+
+use crate::{
+    error,
+    error::Error,
+    secret::Secret,
+    services::{
+        config::{Config, DeviceKind},
+        p1,
+    },
+};
+use bamborvideostream_p1::ConnectOptions;
+
+/// The usage text
+pub const USAGE: &str = "\
+Usage: bamborvideostream [<command>] [<options>]
+
+Commands:
+  serve [--config <path>] [--<setting> <value>]...
+      Runs the server; this is the default if no command is given
+  check-config [--config <path>] [--<setting> <value>]...
+      Validates the config and prints the effective settings with all secrets redacted
+  snapshot <device> -o <file> [--pin <pin>] [--fingerprint <sha256>] [--config <path>]
+      Saves a single JPEG frame of a P1 device to <file> (or to stdout if <file> is -)
+  stream <device> [-o <file>] [--format raw|multipart] [--max-fps <n>] [--duration-s <s>] [--reconnect-ms <ms>]
+      Writes the live stream of a P1 device to <file> or a named pipe (default: stdout) as concatenated JPEGs or as
+      multipart MJPEG
+  probe <device> [--pin <pin>] [--fingerprint <sha256>] [--config <path>]
+      Checks the TLS connection, the certificate identity and, if a PIN is known, the login of a P1 device
+  gen-apikey
+      Generates a random API key and prints it together with its BAMBORVIDEOSTREAM_APIKEYSHA256 hash
+  healthcheck [--timeout-ms <ms>] [--config <path>] [--<setting> <value>]...
+      Checks whether the configured listeners of a running server respond; exits with 1 otherwise
+  mock-p1 [--listen <sockaddr>] [--pin <pin>] [--interval-ms <ms>] ...
+      Runs a mock P1 printer for development and tests
+  help
+      Prints this help
+
+<device> is either the name of a device from the config file or an address like 192.168.1.42:6000.
+";
+
+/// A P1 device to talk to directly
+#[derive(Debug, Clone)]
+pub struct Target {
+    /// The device address
+    pub address: String,
+    /// The device PIN, if known
+    pub pin: Option<Secret>,
+    /// The SHA-256 fingerprint of the device certificate to pin, if any
+    pub fingerprint: Option<[u8; 32]>,
+}
+impl Target {
+    /// Resolves a device from the config file by name or takes `device` as address
+    ///
+    /// # Note
+    /// `pin` and `fingerprint` override the values from the config file; `config_args` are the `--config <path>` and
+    /// `--<setting> <value>` flags to load the config file with.
+    pub fn resolve(
+        device: &str,
+        pin: Option<String>,
+        fingerprint: Option<String>,
+        config_args: Vec<String>,
+    ) -> Result<Self, Error> {
+        // Look up the device by name
+        let devices = Config::load_devices(config_args)?;
+        let mut target = match devices.get(device) {
+            Some(device_) if device_.kind != DeviceKind::P1 => {
+                return Err(error!("Device {device} has kind {}; only p1 devices are supported", device_.kind));
+            }
+            Some(device) => {
+                Self { address: device.address.clone(), pin: device.pin.clone(), fingerprint: device.fingerprint }
+            }
+            None if device.contains(':') => Self { address: device.to_string(), pin: None, fingerprint: None },
+            None => {
+                return Err(error!(
+                    "Unknown device {device}; expected a device name from the config file or an address like \
+                     192.168.1.42:6000"
+                ))
+            }
+        };
+
+        // Apply the overrides
+        if let Some(pin) = pin {
+            bamborvideostream_p1::protocol::login_packet(&pin)?;
+            target.pin = Some(Secret::new(pin));
+        }
+        if let Some(fingerprint) = fingerprint {
+            let fingerprint = p1::parse_fingerprint(&fingerprint)
+                .ok_or_else(|| error!("Invalid fingerprint: expected 32 hex-encoded bytes"))?;
+            target.fingerprint = Some(fingerprint);
+        }
+        Ok(target)
+    }
+
+    /// The PIN or an error if the PIN is unknown
+    pub fn pin(&self) -> Result<&Secret, Error> {
+        self.pin.as_ref().ok_or_else(|| error!("Missing PIN for {}; use --pin <pin>", self.address))
+    }
+
+    /// The connection options with the pinned certificate, if any
+    pub fn options(&self) -> ConnectOptions {
+        ConnectOptions { pinned_sha256: self.fingerprint, ..Default::default() }
+    }
+}
+
+/// Parses the command line arguments of a subcommand
+///
+/// # Note
+/// `option` is called for each `--<option> <value>` pair and returns `false` for unknown options, which are collected
+/// as `--<setting> <value>` flags for the config. All other arguments are returned as positional arguments.
+pub fn parse_args<I, F>(args: I, mut option: F) -> Result<(Vec<String>, Vec<String>), Error>
+where
+    I: IntoIterator<Item = String>,
+    F: FnMut(&str, String) -> Result<bool, Error>,
+{
+    let (mut positional, mut config_args) = (Vec::new(), Vec::new());
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // Collect positional arguments
+        if !arg.starts_with('-') || arg == "-" {
+            positional.push(arg);
+            continue;
+        }
+
+        // Apply the option or pass it on to the config
+        let value = args.next().ok_or_else(|| error!("Missing value for argument {arg}"))?;
+        if !option(&arg, value.clone())? {
+            config_args.extend([arg, value]);
+        }
+    }
+    Ok((positional, config_args))
+}
@@ -0,0 +1,87 @@
+//! Diagnoses the connection to a P1 device step by step
+
+use crate::{
+    cli::{self, Target},
+    error,
+    error::Error,
+    services::p1::{self, connection::P1Connection},
+};
+use bamborvideostream_p1::ConnectOptions;
+use sha2::{Digest, Sha256};
+use std::{str, time::Instant};
+
+/// The DER-encoded object identifier of the X.509 common name attribute (`2.5.4.3`)
+const COMMON_NAME_OID: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+
+/// Checks the TLS connection, the certificate identity and the login of the given device
+pub fn main<I>(args: I) -> Result<(), Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    // Parse the arguments
+    let (mut pin, mut fingerprint) = (None, None);
+    let (positional, config_args) = cli::parse_args(args, |arg, value| {
+        match arg {
+            "--pin" => pin = Some(value),
+            "--fingerprint" => fingerprint = Some(value),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    let [device] = positional.as_slice() else {
+        return Err(error!("Usage: probe <device>"));
+    };
+    let target = Target::resolve(device, pin, fingerprint, config_args)?;
+
+    // Connect without pinning, so that we can report the actual certificate
+    let start = Instant::now();
+    let connection = P1Connection::new(&target.address, &ConnectOptions::default())
+        .map_err(|e| error!(with: e, "TLS connection to {} failed", target.address))?;
+    println!("TLS:         connected to {} in {} ms", target.address, start.elapsed().as_millis());
+
+    // Report the certificate identity
+    let certificate = connection.peer_certificate()?.ok_or_else(|| error!("The device sent no certificate"))?;
+    let sha256: [u8; 32] = Sha256::digest(&certificate).into();
+    println!("Certificate: {}", common_name(&certificate).unwrap_or_else(|| "<no common name>".to_string()));
+    println!("SHA-256:     {}", p1::format_fingerprint(&sha256));
+    match target.fingerprint {
+        Some(pinned) if pinned == sha256 => println!("Pinning:     fingerprint matches"),
+        Some(pinned) => {
+            let pinned = p1::format_fingerprint(&pinned);
+            return Err(error!("Certificate fingerprint does not match the pinned fingerprint {pinned}"));
+        }
+        None => println!("Pinning:     no fingerprint pinned"),
+    }
+
+    // Log in and receive a frame
+    let Some(pin) = &target.pin else {
+        println!("Login:       skipped; use --pin <pin> to check the login");
+        return Ok(());
+    };
+    let start = Instant::now();
+    let mut session = connection.login(pin)?;
+    let jpeg = session.jpeg().map_err(|e| error!(with: e, "Login failed or no frame received; is the PIN correct?"))?;
+    println!("Login:       received a {}-byte frame in {} ms", jpeg.len(), start.elapsed().as_millis());
+    // The probe has succeeded, so a failed close notification does not matter
+    let _ = session.close();
+    Ok(())
+}
+
+/// Extracts the subject common name from a DER-encoded certificate
+///
+/// # Note
+/// This is a best-effort lookup for diagnostics: since the subject follows the issuer within the certificate, the last
+/// common name attribute is taken as subject common name.
+fn common_name(certificate: &[u8]) -> Option<String> {
+    // Find the last common name attribute
+    let position = certificate.windows(COMMON_NAME_OID.len()).rposition(|window| window == COMMON_NAME_OID)?;
+    let value = certificate.get(position.saturating_add(COMMON_NAME_OID.len())..)?;
+
+    // Decode the string value with a short-form length
+    let (tag, len, value) = (*value.first()?, *value.get(1)?, value.get(2..)?);
+    let (0x0c | 0x13 | 0x16, 0..0x80) = (tag, len) else {
+        return None;
+    };
+    let value = value.get(..usize::from(len))?;
+    str::from_utf8(value).ok().map(|value| format!("CN={value}"))
+}
@@ -0,0 +1,56 @@
+//! Saves a single frame of a P1 device without starting the server
+
+use crate::{
+    cli::{self, Target},
+    error,
+    error::Error,
+    services::p1::connection::P1Connection,
+};
+use std::{
+    fs,
+    io::{self, Write},
+};
+
+/// Saves a single JPEG frame of the given device to a file or to stdout
+pub fn main<I>(args: I) -> Result<(), Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    // Parse the arguments
+    let (mut output, mut pin, mut fingerprint) = (None, None, None);
+    let (positional, config_args) = cli::parse_args(args, |arg, value| {
+        match arg {
+            "-o" | "--output" => output = Some(value),
+            "--pin" => pin = Some(value),
+            "--fingerprint" => fingerprint = Some(value),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    let [device] = positional.as_slice() else {
+        return Err(error!("Usage: snapshot <device> -o <file>"));
+    };
+    let output = output.ok_or_else(|| error!("Missing output file; use -o <file> or -o - for stdout"))?;
+    let target = Target::resolve(device, pin, fingerprint, config_args)?;
+
+    // Receive a single frame
+    let connection = P1Connection::new(&target.address, &target.options())?;
+    let mut session = connection.login(target.pin()?)?;
+    let jpeg = session.jpeg().map_err(|e| error!(with: e, "Failed to receive a frame; is the PIN correct?"))?;
+    // The frame has been received, so a failed close notification does not matter
+    let _ = session.close();
+
+    // Write the frame
+    match output.as_str() {
+        "-" => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&jpeg)?;
+            stdout.flush()?;
+        }
+        path => {
+            fs::write(path, &jpeg).map_err(|e| error!(with: e, "Failed to write snapshot to {path}"))?;
+            eprintln!("Saved {} bytes from {} to {path}", jpeg.len(), target.address);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,224 @@
+//! Writes the live stream of a P1 device to stdout, a file or a named pipe without starting the server
+
+use crate::{
+    cli::{self, Target},
+    error,
+    error::Error,
+    log::Level,
+    services::{
+        p1::connection::{P1Connection, P1Session},
+        shutdown,
+    },
+    v1::stream::FrameStream,
+};
+use std::{
+    fs::OpenOptions,
+    io::{self, ErrorKind, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The output format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Raw concatenated JPEGs (e.g. for `ffmpeg -f mjpeg`)
+    Raw,
+    /// A `multipart/x-mixed-replace` MJPEG stream like the HTTP API (e.g. for `ffmpeg -f mpjpeg`)
+    Multipart,
+}
+
+/// Why a session has ended
+#[derive(Debug)]
+enum End {
+    /// The duration has elapsed or a shutdown has been requested
+    Done,
+    /// The device connection has failed
+    Device(Error),
+    /// The output cannot be written
+    Output(io::Error),
+}
+
+/// Relays the frames of a device into the output
+struct Pipe {
+    /// The device
+    target: Target,
+    /// The output
+    output: Box<dyn Write>,
+    /// The output format
+    format: Format,
+    /// The minimum interval between two written frames, if the frame rate is limited
+    min_interval: Option<Duration>,
+    /// The time to stop streaming, if any
+    deadline: Option<Instant>,
+    /// The time the last frame has been written
+    last: Option<Instant>,
+}
+impl Pipe {
+    /// Relays the frames until the duration has elapsed, a shutdown has been requested or the reader has gone away
+    ///
+    /// # Note
+    /// If `reconnect` is set, failed device connections are retried after this delay; otherwise they are fatal.
+    fn run(&mut self, reconnect: Option<Duration>) -> Result<(), Error> {
+        loop {
+            match self.session() {
+                End::Done => return self.finish(),
+                // The reader has gone away (e.g. `ffmpeg` has exited), so there is nothing left to do
+                End::Output(e) if e.kind() == ErrorKind::BrokenPipe => return Ok(()),
+                End::Output(e) => return Err(error!(with: e, "Failed to write the stream")),
+                End::Device(e) => {
+                    let Some(delay) = reconnect else {
+                        return Err(e);
+                    };
+                    let address = &self.target.address;
+                    let delay_ms = delay.as_millis();
+                    error!(with: e, "Stream from {address} failed; reconnecting in {delay_ms} ms").log_as(Level::Warn);
+                    thread::sleep(delay);
+                    if self.is_done() {
+                        return self.finish();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the duration has elapsed or a shutdown has been requested
+    fn is_done(&self) -> bool {
+        let is_expired = self.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        is_expired || shutdown::is_requested()
+    }
+
+    /// Relays the frames of a single session
+    fn session(&mut self) -> End {
+        // Connect to the device
+        let session = self.target.pin().and_then(|pin| {
+            let connection = P1Connection::new(&self.target.address, &self.target.options())?;
+            connection.login(pin)
+        });
+        let mut session = match session {
+            Ok(session) => session,
+            Err(e) => return End::Device(e),
+        };
+
+        // Relay the frames
+        let end = self.relay(&mut session);
+        // The session is over anyway, so a failed close notification does not matter
+        let _ = session.close();
+        end
+    }
+
+    /// Relays the frames of the given session until the session or the output fails or we are done
+    fn relay(&mut self, session: &mut P1Session) -> End {
+        loop {
+            // Check whether we are done
+            if self.is_done() {
+                return End::Done;
+            }
+
+            // Receive the next frame
+            let jpeg = match session.jpeg() {
+                Ok(jpeg) => jpeg,
+                Err(e) => return End::Device(error!(with: e, "Failed to receive a frame; is the PIN correct?")),
+            };
+
+            // Drop the frame if it exceeds the frame rate
+            let last = self.last.map(|last| last.elapsed());
+            if self.min_interval.zip(last).is_some_and(|(min_interval, last)| last < min_interval) {
+                continue;
+            }
+
+            // Write the frame
+            self.last = Some(Instant::now());
+            if let Err(e) = self.write(&jpeg) {
+                return End::Output(e);
+            }
+        }
+    }
+
+    /// Writes a frame in the output format
+    fn write(&mut self, jpeg: &[u8]) -> io::Result<()> {
+        if self.format == Format::Multipart {
+            let header = format!(
+                "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                FrameStream::BOUNDARY,
+                jpeg.len()
+            );
+            self.output.write_all(header.as_bytes())?;
+        }
+        self.output.write_all(jpeg)?;
+        if self.format == Format::Multipart {
+            self.output.write_all(b"\r\n")?;
+        }
+        self.output.flush()
+    }
+
+    /// Writes the final multipart boundary, if any
+    fn finish(&mut self) -> Result<(), Error> {
+        let result = match self.format {
+            Format::Raw => self.output.flush(),
+            Format::Multipart => {
+                let boundary = format!("--{}--\r\n", FrameStream::BOUNDARY);
+                self.output.write_all(boundary.as_bytes()).and_then(|_| self.output.flush())
+            }
+        };
+        match result {
+            Err(e) if e.kind() != ErrorKind::BrokenPipe => Err(error!(with: e, "Failed to write the stream")),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Writes the live stream of the given device to stdout, a file or a named pipe
+pub fn main<I>(args: I) -> Result<(), Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    // Parse the arguments
+    let (mut output, mut format, mut max_fps, mut duration, mut reconnect) =
+        ("-".to_string(), Format::Raw, None, None, None);
+    let (mut pin, mut fingerprint) = (None, None);
+    let (positional, config_args) = cli::parse_args(args, |arg, value| {
+        match arg {
+            "-o" | "--output" => output = value,
+            "--format" if value == "raw" => format = Format::Raw,
+            "--format" if value == "multipart" => format = Format::Multipart,
+            "--format" => return Err(error!("Invalid format {value:?}; expected \"raw\" or \"multipart\"")),
+            "--max-fps" => max_fps = Some(value.parse::<u32>()?),
+            "--duration-s" => duration = Some(Duration::from_secs(value.parse()?)),
+            "--reconnect-ms" => reconnect = Some(Duration::from_millis(value.parse()?)),
+            "--pin" => pin = Some(value),
+            "--fingerprint" => fingerprint = Some(value),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    let [device] = positional.as_slice() else {
+        return Err(error!("Usage: stream <device> [-o <file>]"));
+    };
+    let target = Target::resolve(device, pin, fingerprint, config_args)?;
+    // A missing PIN cannot be fixed by reconnecting
+    target.pin()?;
+    let min_interval = match max_fps {
+        Some(0) => return Err(error!("Invalid --max-fps: must be greater than 0")),
+        Some(max_fps) => Duration::from_secs(1).checked_div(max_fps),
+        None => None,
+    };
+
+    // Open the output; opening a named pipe blocks until a reader has opened it
+    let output: Box<dyn Write> = match output.as_str() {
+        "-" => Box::new(io::stdout().lock()),
+        path => Box::new(
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .map_err(|e| error!(with: e, "Failed to open {path}"))?,
+        ),
+    };
+
+    // Relay the frames until we are done
+    shutdown::register_signals()?;
+    let deadline = duration.and_then(|duration| Instant::now().checked_add(duration));
+    let mut pipe = Pipe { target, output, format, min_interval, deadline, last: None };
+    pipe.run(reconnect)
+}
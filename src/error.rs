@@ -1,5 +1,6 @@
 //! Implements the crate's error type
 
+use crate::log::{self, Field, Level};
 use std::{
     backtrace::{Backtrace, BacktraceStatus},
     fmt::{self, Debug, Display, Formatter},
@@ -42,14 +43,47 @@ impl Error {
         self.backtrace.status() == BacktraceStatus::Captured
     }
 
-    /// Logs `self` to stderr
+    /// Logs `self` as error event
     pub fn log(&self) {
-        // Print error
-        eprintln!("{self}");
-        if self.has_backtrace() {
-            // Print backtrace if available
-            eprintln!("{}", self.backtrace)
+        self.log_as(Level::Error);
+    }
+
+    /// Logs `self` with the given level; the backtrace is only included at debug level
+    pub fn log_as(&self, level: Level) {
+        self.log_with(level, &[]);
+    }
+
+    /// Logs `self` with the given level and additional fields
+    pub fn log_with(&self, level: Level, fields: &[Field]) {
+        // Collect the fields
+        let mut fields = fields.to_vec();
+        if let Some(cause) = self.cause() {
+            fields.push(("cause", cause.into()));
         }
+        if self.has_backtrace() && log::enabled(Level::Debug) {
+            fields.push(("backtrace", self.backtrace.to_string().into()));
+        }
+        log::log(level, &self.error, &fields);
+    }
+
+    /// The chain of underlying errors as single line, if any
+    fn cause(&self) -> Option<String> {
+        let mut causes: Vec<String> = Vec::new();
+        let mut source = std::error::Error::source(self);
+        while let Some(error) = source {
+            // Use the plain description of our own errors, since their display includes their causes
+            let cause = match error.downcast_ref::<Self>() {
+                Some(error) => error.error.clone(),
+                None => error.to_string(),
+            };
+
+            // Skip causes that are already part of the previous description
+            if !causes.last().is_some_and(|previous| previous.contains(&cause)) {
+                causes.push(cause);
+            }
+            source = error.source();
+        }
+        (!causes.is_empty()).then(|| causes.join(": "))
     }
 }
 impl Display for Error {
@@ -70,16 +104,23 @@ impl std::error::Error for Error {
         Some(error)
     }
 }
+impl From<bamborvideostream_p1::Error> for Error {
+    fn from(error: bamborvideostream_p1::Error) -> Self {
+        error!(with: error, "P1 protocol error")
+    }
+}
 impl From<ehttpd::error::Error> for Error {
     fn from(error: ehttpd::error::Error) -> Self {
         error!(with: error, "HTTP server error")
     }
 }
+#[cfg(feature = "native-tls")]
 impl From<native_tls::Error> for Error {
     fn from(error: native_tls::Error) -> Self {
         error!(with: error, "TLS error")
     }
 }
+#[cfg(feature = "native-tls")]
 impl<T> From<native_tls::HandshakeError<T>> for Error
 where
     T: Debug + Send + 'static,
@@ -88,11 +129,43 @@ where
         error!(with: error, "TLS handshake error")
     }
 }
+impl From<jpeg_encoder::EncodingError> for Error {
+    fn from(error: jpeg_encoder::EncodingError) -> Self {
+        error!(with: error, "JPEG encoding error")
+    }
+}
+impl From<jpeg_decoder::Error> for Error {
+    fn from(error: jpeg_decoder::Error) -> Self {
+        error!(with: error, "JPEG decoding error")
+    }
+}
+#[cfg(feature = "rustls")]
+impl From<rustls::Error> for Error {
+    fn from(error: rustls::Error) -> Self {
+        error!(with: error, "TLS error")
+    }
+}
+#[cfg(feature = "rustls")]
+impl From<rustls::pki_types::pem::Error> for Error {
+    fn from(error: rustls::pki_types::pem::Error) -> Self {
+        error!(with: error, "PEM error")
+    }
+}
+impl From<std::str::ParseBoolError> for Error {
+    fn from(error: std::str::ParseBoolError) -> Self {
+        error!(with: error, "Parsing error")
+    }
+}
 impl From<std::num::ParseIntError> for Error {
     fn from(error: std::num::ParseIntError) -> Self {
         error!(with: error, "Parsing error")
     }
 }
+impl From<std::num::TryFromIntError> for Error {
+    fn from(error: std::num::TryFromIntError) -> Self {
+        error!(with: error, "Integer conversion error")
+    }
+}
 impl From<std::str::Utf8Error> for Error {
     fn from(error: std::str::Utf8Error) -> Self {
         error!(with: error, "UTF-8 error")
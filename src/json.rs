@@ -0,0 +1,90 @@
+//! Minimal JSON encoding for API responses and log events
+
+use std::fmt::Write;
+
+/// Appends the given string as JSON string literal
+pub fn push_string(out: &mut String, string: &str) {
+    out.push('"');
+    for char in string.chars() {
+        match char {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            char if char.is_control() => {
+                // Writing to a string cannot fail
+                let _ = write!(out, "\\u{:04x}", u32::from(char));
+            }
+            char => out.push(char),
+        }
+    }
+    out.push('"');
+}
+
+/// Encodes the given values as JSON array
+pub fn array<I>(values: I) -> String
+where
+    I: IntoIterator<Item = String>,
+{
+    format!("[{}]", values.into_iter().collect::<Vec<_>>().join(","))
+}
+
+/// A JSON object builder
+#[derive(Debug, Default)]
+pub struct Object {
+    /// The encoded fields
+    json: String,
+}
+impl Object {
+    /// Creates an empty object
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a string field
+    pub fn string(self, key: &str, value: &str) -> Self {
+        let mut json = String::new();
+        push_string(&mut json, value);
+        self.raw(key, &json)
+    }
+
+    /// Adds an unsigned number field
+    pub fn number(self, key: &str, value: u64) -> Self {
+        self.raw(key, &value.to_string())
+    }
+
+    /// Adds a floating point number field; non-finite values are encoded as `null`
+    pub fn float(self, key: &str, value: f64) -> Self {
+        match value.is_finite() {
+            true => self.raw(key, &value.to_string()),
+            false => self.null(key),
+        }
+    }
+
+    /// Adds a boolean field
+    pub fn bool(self, key: &str, value: bool) -> Self {
+        self.raw(key, &value.to_string())
+    }
+
+    /// Adds a `null` field
+    pub fn null(self, key: &str) -> Self {
+        self.raw(key, "null")
+    }
+
+    /// Adds a field with an already encoded JSON value (e.g. a nested object or array)
+    pub fn raw(mut self, key: &str, json: &str) -> Self {
+        if !self.json.is_empty() {
+            self.json.push(',');
+        }
+        push_string(&mut self.json, key);
+        self.json.push(':');
+        self.json.push_str(json);
+        self
+    }
+
+    /// Finishes the object
+    pub fn finish(self) -> String {
+        format!("{{{}}}", self.json)
+    }
+}
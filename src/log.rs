@@ -0,0 +1,312 @@
+//! A minimal structured logger with levels and human-readable, JSON or journald output
+
+use crate::{error, error::Error, json};
+use std::{
+    borrow::Cow,
+    fmt::Write as _,
+    io::{self, Write},
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering::SeqCst},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The current maximum level
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+/// The current output format
+static FORMAT: AtomicU8 = AtomicU8::new(Format::Human as u8);
+
+/// A log level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    /// Failures that need attention
+    Error = 0,
+    /// Recoverable problems, e.g. failed device sessions or rejected requests
+    Warn = 1,
+    /// Regular operation, e.g. the access log and device lifecycle events
+    Info = 2,
+    /// Diagnostics, e.g. error backtraces
+    Debug = 3,
+}
+impl Level {
+    /// The level name
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+        }
+    }
+
+    /// The syslog priority of the level
+    const fn priority(self) -> u8 {
+        match self {
+            Self::Error => 3,
+            Self::Warn => 4,
+            Self::Info => 6,
+            Self::Debug => 7,
+        }
+    }
+
+    /// Restores the level from its raw value
+    const fn from_raw(raw: u8) -> Self {
+        match raw {
+            0 => Self::Error,
+            1 => Self::Warn,
+            2 => Self::Info,
+            _ => Self::Debug,
+        }
+    }
+}
+impl FromStr for Level {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            "info" => Ok(Self::Info),
+            "debug" => Ok(Self::Debug),
+            _ => Err(error!("Invalid log level; expected \"error\", \"warn\", \"info\" or \"debug\"")),
+        }
+    }
+}
+
+/// A log output format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Format {
+    /// One human-readable `key=value` line per event on stderr
+    Human = 0,
+    /// One JSON object per event on stderr
+    Json = 1,
+    /// Native structured journald entries; falls back to `Human` if the journal is not available
+    Journald = 2,
+}
+impl Format {
+    /// Restores the format from its raw value
+    const fn from_raw(raw: u8) -> Self {
+        match raw {
+            0 => Self::Human,
+            1 => Self::Json,
+            _ => Self::Journald,
+        }
+    }
+}
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            "journald" => Ok(Self::Journald),
+            _ => Err(error!("Invalid log format; expected \"human\", \"json\" or \"journald\"")),
+        }
+    }
+}
+
+/// A field value
+#[derive(Debug, Clone)]
+pub enum Value<'a> {
+    /// A string
+    Text(Cow<'a, str>),
+    /// An unsigned number
+    Number(u64),
+}
+impl<'a> From<&'a str> for Value<'a> {
+    fn from(value: &'a str) -> Self {
+        Self::Text(Cow::Borrowed(value))
+    }
+}
+impl<'a> From<Cow<'a, str>> for Value<'a> {
+    fn from(value: Cow<'a, str>) -> Self {
+        Self::Text(value)
+    }
+}
+impl From<String> for Value<'_> {
+    fn from(value: String) -> Self {
+        Self::Text(Cow::Owned(value))
+    }
+}
+impl From<u64> for Value<'_> {
+    fn from(value: u64) -> Self {
+        Self::Number(value)
+    }
+}
+impl From<usize> for Value<'_> {
+    fn from(value: usize) -> Self {
+        Self::Number(u64::try_from(value).unwrap_or(u64::MAX))
+    }
+}
+impl From<u128> for Value<'_> {
+    fn from(value: u128) -> Self {
+        Self::Number(u64::try_from(value).unwrap_or(u64::MAX))
+    }
+}
+
+/// A log field
+pub type Field<'a> = (&'static str, Value<'a>);
+
+/// Sets the maximum level and the output format
+pub fn configure(level: Level, format: Format) {
+    LEVEL.store(level as u8, SeqCst);
+    FORMAT.store(format as u8, SeqCst);
+}
+
+/// Whether events with the given level are logged
+pub fn enabled(level: Level) -> bool {
+    level <= Level::from_raw(LEVEL.load(SeqCst))
+}
+
+/// Logs a warning event
+pub fn warn(message: &str, fields: &[Field]) {
+    log(Level::Warn, message, fields);
+}
+
+/// Logs an informational event
+pub fn info(message: &str, fields: &[Field]) {
+    log(Level::Info, message, fields);
+}
+
+/// Logs an event with the given fields if the level is enabled
+pub fn log(level: Level, message: &str, fields: &[Field]) {
+    // Check the level
+    if !enabled(level) {
+        return;
+    }
+
+    // Format and write the event
+    match Format::from_raw(FORMAT.load(SeqCst)) {
+        Format::Human => stderr(&human(level, message, fields)),
+        Format::Json => stderr(&json(level, message, fields)),
+        Format::Journald => {
+            if journald(level, message, fields).is_err() {
+                stderr(&human(level, message, fields));
+            }
+        }
+    }
+}
+
+/// Writes a line to stderr
+fn stderr(line: &str) {
+    // There is nowhere to report a failed log write
+    let _ = writeln!(io::stderr().lock(), "{line}");
+}
+
+/// Formats an event as `<timestamp> <LEVEL> <message> key=value ...`
+fn human(level: Level, message: &str, fields: &[Field]) -> String {
+    // Format the header
+    let mut line = format!("{} {:<5} {message}", timestamp(), level.name().to_uppercase());
+
+    // Append the fields; strings are quoted if necessary to keep the line parseable
+    for (key, value) in fields {
+        let _ = match value {
+            Value::Number(number) => write!(line, " {key}={number}"),
+            Value::Text(text) if text.is_empty() || text.contains([' ', '"', '=', '\n', '\r', '\t']) => {
+                write!(line, " {key}={text:?}")
+            }
+            Value::Text(text) => write!(line, " {key}={text}"),
+        };
+    }
+    line
+}
+
+/// Formats an event as single-line JSON object
+fn json(level: Level, message: &str, fields: &[Field]) -> String {
+    // Format the header
+    let mut line = format!("{{\"ts\":\"{}\",\"level\":\"{}\",\"msg\":", timestamp(), level.name());
+    json::push_string(&mut line, message);
+
+    // Append the fields
+    for (key, value) in fields {
+        line.push(',');
+        json::push_string(&mut line, key);
+        line.push(':');
+        match value {
+            Value::Number(number) => line.push_str(&number.to_string()),
+            Value::Text(text) => json::push_string(&mut line, text),
+        }
+    }
+    line.push('}');
+    line
+}
+
+/// Sends an event to the native journald socket
+#[cfg(unix)]
+fn journald(level: Level, message: &str, fields: &[Field]) -> io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    /// The journald socket
+    const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+    // Assemble the entry
+    let mut entry = Vec::new();
+    journald_field(&mut entry, "MESSAGE", message);
+    journald_field(&mut entry, "PRIORITY", &level.priority().to_string());
+    journald_field(&mut entry, "SYSLOG_IDENTIFIER", env!("CARGO_PKG_NAME"));
+    for (key, value) in fields {
+        // Field names must consist of uppercase letters, digits and underscores
+        let key: String = key.chars().map(|char| char.to_ascii_uppercase()).collect();
+        let key = key.replace(|char: char| !char.is_ascii_alphanumeric(), "_");
+        match value {
+            Value::Number(number) => journald_field(&mut entry, &key, &number.to_string()),
+            Value::Text(text) => journald_field(&mut entry, &key, text),
+        }
+    }
+
+    // Send the entry
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to(&entry, JOURNALD_SOCKET)?;
+    Ok(())
+}
+/// Sends an event to the native journald socket
+#[cfg(not(unix))]
+fn journald(_level: Level, _message: &str, _fields: &[Field]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "journald is not supported on this platform"))
+}
+
+/// Appends a field to a journald entry
+#[cfg(unix)]
+fn journald_field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    if value.contains('\n') {
+        // Multi-line values use the binary form: `KEY\n<little-endian u64 length><value>\n`
+        let len = u64::try_from(value.len()).unwrap_or(u64::MAX);
+        entry.extend_from_slice(key.as_bytes());
+        entry.push(b'\n');
+        entry.extend_from_slice(&len.to_le_bytes());
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    } else {
+        // Single-line values use the simple form `KEY=value\n`
+        entry.extend_from_slice(key.as_bytes());
+        entry.push(b'=');
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+}
+
+/// Formats the current time as RFC 3339 UTC timestamp with millisecond precision
+#[allow(clippy::arithmetic_side_effects, reason = "all values are bounded by the calendar arithmetic")]
+fn timestamp() -> String {
+    // Split the time into days and the time of day
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (secs, millis) = (now.as_secs(), now.subsec_millis());
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Convert the days into a civil date (see Howard Hinnant's `civil_from_days`)
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    // Format the timestamp
+    let (hour, minute, second) = (secs_of_day / 3600, secs_of_day % 3600 / 60, secs_of_day % 60);
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{millis:03}Z")
+}
@@ -13,44 +13,174 @@
 #![warn(clippy::allow_attributes_without_reason)]
 #![warn(clippy::cognitive_complexity)]
 
+mod cli;
 mod error;
+mod json;
+mod log;
+mod mock;
+mod secret;
 mod services;
 mod v1;
 
-use crate::{error::Error, services::config::Config};
+use crate::{
+    error::Error,
+    services::{
+        accesslog::{Access, CountingWriter},
+        config::{self, Config, ConfigHandle},
+        devicestore,
+        listener::{self, AuthPolicy, ListenerSpec, Probe, Socket},
+        metrics,
+        p1::{mosaic, P1Service},
+        shutdown, systemd,
+        tls::ReloadingAcceptor,
+    },
+};
 use ehttpd::{
-    http::{Request, Response, ResponseExt},
+    bytes::{Sink, Source},
+    http::{Request, RequestExt, Response, ResponseExt},
     Server,
 };
-use std::{process, sync::Arc};
+use std::{
+    env,
+    io::{self, Write},
+    iter, process, str,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Reads a request, handles it and writes the response, and records the request in the metrics and the access log
+///
+/// # Note
+/// This is [`ehttpd::reqresp`] with bookkeeping: the request is logged after the response has been written, so that the
+/// log contains the actual amount of bytes and the duration of streams.
+fn reqresp<F>(source: &mut Source, sink: &mut Sink, config: &Config, policy: Option<AuthPolicy>, handler: F) -> bool
+where
+    F: FnOnce(Request) -> (&'static str, Response),
+{
+    // Read request
+    let Ok(Some(request)) = Request::from_stream(source) else {
+        return false;
+    };
+    let (start, client) = (Instant::now(), listener::current_peer());
+    let method = request.method.to_vec();
+    let target = secret::redact_target(&String::from_utf8_lossy(&request.target));
+
+    // Handle request and write response
+    let (route, mut response) = handler(request);
+    let mut sink = CountingWriter::new(sink);
+    let is_written = response.to_stream(&mut sink).is_ok();
+
+    // Record the request
+    metrics::record_request(route, &response.status);
+    if config.BAMBORVIDEOSTREAM_ACCESSLOG {
+        let (status, bytes, duration) = (&response.status, sink.bytes, start.elapsed());
+        let access =
+            Access { method: &method, target: &target, route, status, bytes, duration, client: &client, policy };
+        access.log();
+    }
+
+    // Mark connection as to-be-rescheduled
+    is_written && !response.has_connection_close()
+}
 
 /// Routes incoming requests
-fn route(request: Request, config: &Arc<Config>) -> Response {
+fn route(request: Request, config: &Arc<Config>, policy: AuthPolicy) -> (&'static str, Response) {
     // Route request
     let is_head = request.method == b"HEAD";
-    let maybe_response: Result<Response, Error> = match (request.method.as_ref(), request.target.as_ref()) {
-        // Authed endpoints
-        (b"POST", target) if target.starts_with(b"/v1/p1") => {
-            // Call endpoint via auth bridge
-            v1::authed::call(v1::authed::p1::post, request, config)
-        }
+    let (route, maybe_response): (&'static str, Result<Response, Error>) =
+        match (request.method.as_ref(), request.target.as_ref()) {
+            // Authed endpoints
+            (b"HEAD" | b"GET", target) if target.starts_with(b"/v1/p1/stream") => {
+                // Call endpoint via auth bridge
+                ("/v1/p1/stream", v1::authed::call(v1::authed::p1::stream, request, config, policy))
+            }
+            (b"HEAD" | b"GET", target) if target.starts_with(b"/v1/p1/snapshot") => {
+                // Call endpoint via auth bridge
+                ("/v1/p1/snapshot", v1::authed::call(v1::authed::p1::post, request, config, policy))
+            }
+            (b"HEAD" | b"GET", target) if target.starts_with(b"/v1/mosaic/stream") => {
+                // Call endpoint via auth bridge
+                ("/v1/mosaic/stream", v1::authed::call(v1::authed::mosaic::stream, request, config, policy))
+            }
+            (b"HEAD" | b"GET", target) if target == b"/v1/mosaic" || target.starts_with(b"/v1/mosaic?") => {
+                // Call endpoint via auth bridge
+                ("/v1/mosaic", v1::authed::call(v1::authed::mosaic::get, request, config, policy))
+            }
+            (b"HEAD" | b"GET", target) if target == b"/v1/devices" || target.starts_with(b"/v1/devices?") => {
+                // Call endpoint via auth bridge
+                ("/v1/devices", v1::authed::call(v1::authed::dashboard::devices, request, config, policy))
+            }
+            (b"HEAD" | b"GET", target) if target.starts_with(b"/v1/x1/stream") => {
+                // Call endpoint via auth bridge
+                ("/v1/x1/stream", v1::authed::call(v1::authed::x1::stream, request, config, policy))
+            }
+            (b"HEAD" | b"GET", target) if target.starts_with(b"/v1/http/stream") => {
+                // Call endpoint via auth bridge
+                ("/v1/http/stream", v1::authed::call(v1::authed::http::stream, request, config, policy))
+            }
+            (b"HEAD" | b"GET", target) if target.starts_with(b"/v1/http/snapshot") => {
+                // Call endpoint via auth bridge
+                ("/v1/http/snapshot", v1::authed::call(v1::authed::http::post, request, config, policy))
+            }
+            (b"HEAD" | b"GET", target) if target.starts_with(b"/v1/replay/stream") => {
+                // Call endpoint via auth bridge
+                ("/v1/replay/stream", v1::authed::call(v1::authed::replay::stream, request, config, policy))
+            }
+            (b"POST", target) if target.starts_with(b"/v1/p1") => {
+                // Call endpoint via auth bridge
+                ("/v1/p1", v1::authed::call(v1::authed::p1::post, request, config, policy))
+            }
+            (b"POST", target) if target.starts_with(b"/v1/http") => {
+                // Call endpoint via auth bridge
+                ("/v1/http", v1::authed::call(v1::authed::http::post, request, config, policy))
+            }
+            (b"POST", target) if target.starts_with(b"/v1/replay") => {
+                // Call endpoint via auth bridge
+                ("/v1/replay", v1::authed::call(v1::authed::replay::post, request, config, policy))
+            }
+            (_, target) if target.starts_with(b"/v1/admin/") => {
+                // Route admin endpoint
+                route_admin(request, config, policy)
+            }
+            (b"HEAD" | b"GET", target) if target == b"/metrics" || target.starts_with(b"/metrics?") => {
+                // Call endpoint via auth bridge
+                ("/metrics", v1::authed::call(v1::authed::metrics::get, request, config, policy))
+            }
+            (b"HEAD" | b"GET", target) if target.starts_with(b"/readyz?") && v1::health::is_verbose(&request) => {
+                // Call endpoint via auth bridge
+                ("/readyz?verbose", v1::authed::call(v1::authed::health::readyz, request, config, policy))
+            }
 
-        // Site URLs
-        (b"HEAD" | b"GET", target) if target.starts_with(b"/site/") => {
-            // Call endpoint directly
-            v1::site::handle(request)
-        }
+            // Probe URLs
+            (b"HEAD" | b"GET", target) if target == b"/healthz" || target.starts_with(b"/healthz?") => {
+                // Call endpoint directly
+                ("/healthz", v1::health::healthz(request))
+            }
+            (b"HEAD" | b"GET", target) if target == b"/readyz" || target.starts_with(b"/readyz?") => {
+                // Call endpoint directly
+                ("/readyz", v1::health::readyz(request, config))
+            }
 
-        // Fallback URLs
-        (b"HEAD" | b"GET", b"/") => {
-            // Redirect to main site URL
-            Ok(Response::new_307_temporaryredirect(b"/site/app.html"))
-        }
-        _ => {
-            // Deliver a good old 404
-            Ok(Response::new_404_notfound())
-        }
-    };
+            // Site URLs
+            (b"HEAD" | b"GET", target) if target.starts_with(b"/site/") => {
+                // Call endpoint directly
+                ("/site", v1::site::handle(request))
+            }
+
+            // Fallback URLs
+            (b"HEAD" | b"GET", b"/") => {
+                // Redirect to main site URL
+                ("/", Ok(Response::new_307_temporaryredirect(b"/site/app.html")))
+            }
+            _ => {
+                // Deliver a good old 404
+                ("other", Ok(Response::new_404_notfound()))
+            }
+        };
 
     // Log server error and create appropriate response
     let mut response = maybe_response.unwrap_or_else(|error| {
@@ -69,30 +199,275 @@ fn route(request: Request, config: &Arc<Config>) -> Response {
         response.set_content_length(0);
         response.set_connection_close();
     }
-    response
+    (route, response)
 }
 
-/// A fallible main function
-#[allow(clippy::unreachable, reason = "The server can only return in case of an error, but rust does not know this")]
-fn try_main() -> Result<(), Error> {
-    // Load config and init video services
-    let config = Config::from_env()?;
-
-    // Create server
-    let config_ = Arc::new(config.clone());
-    let server: Server<_> = Server::new(config.BAMBORVIDEOSTREAM_CONNMAX, move |source, sink| {
-        // Route the request
-        let config_ = config_.clone();
-        ehttpd::reqresp(source, sink, move |request| route(request, &config_))
+/// Routes requests to the admin endpoints
+fn route_admin(request: Request, config: &Arc<Config>, policy: AuthPolicy) -> (&'static str, Result<Response, Error>) {
+    match (request.method.as_ref(), request.target.as_ref()) {
+        (b"HEAD" | b"GET", target) if target == b"/v1/admin/services" || target.starts_with(b"/v1/admin/services?") => {
+            // Call endpoint via auth bridge
+            ("/v1/admin/services", v1::authed::call(v1::authed::admin::services, request, config, policy))
+        }
+        (b"POST", target) if target.starts_with(b"/v1/admin/services/start") => {
+            // Call endpoint via auth bridge
+            ("/v1/admin/services/start", v1::authed::call(v1::authed::admin::start, request, config, policy))
+        }
+        (b"POST", target) if target.starts_with(b"/v1/admin/services/reconnect") => {
+            // Call endpoint via auth bridge
+            let endpoint = v1::authed::admin::reconnect;
+            ("/v1/admin/services/reconnect", v1::authed::call(endpoint, request, config, policy))
+        }
+        (b"POST", target) if target.starts_with(b"/v1/admin/services/stop") => {
+            // Call endpoint via auth bridge
+            ("/v1/admin/services/stop", v1::authed::call(v1::authed::admin::stop, request, config, policy))
+        }
+        (b"POST", target) if target.starts_with(b"/v1/admin/services/pin") => {
+            // Call endpoint via auth bridge
+            ("/v1/admin/services/pin", v1::authed::call(v1::authed::admin::pin, request, config, policy))
+        }
+        (b"HEAD" | b"GET", target) if target == b"/v1/admin/devices" || target.starts_with(b"/v1/admin/devices?") => {
+            // Call endpoint via auth bridge
+            ("/v1/admin/devices", v1::authed::call(v1::authed::devices::list, request, config, policy))
+        }
+        (b"POST", target) if target.starts_with(b"/v1/admin/devices/put") => {
+            // Call endpoint via auth bridge
+            ("/v1/admin/devices/put", v1::authed::call(v1::authed::devices::put, request, config, policy))
+        }
+        (b"POST", target) if target.starts_with(b"/v1/admin/devices/delete") => {
+            // Call endpoint via auth bridge
+            ("/v1/admin/devices/delete", v1::authed::call(v1::authed::devices::delete, request, config, policy))
+        }
+        _ => {
+            // Deliver a good old 404
+            ("other", Ok(Response::new_404_notfound()))
+        }
+    }
+}
+
+/// Redirects plain HTTP requests to the HTTPS listener
+fn redirect(request: Request, config: &Arc<Config>) -> (&'static str, Response) {
+    // Get the HTTPS port and the requested host without port
+    let tls_sockaddr = config.https_sockaddr().unwrap_or_default();
+    let tls_port = tls_sockaddr.rsplit_once(':').map(|(_, port)| port).unwrap_or("443");
+    let host = request.field("Host").and_then(|host| str::from_utf8(host).ok()).unwrap_or_default();
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => host,
+        _ => host,
+    };
+    if host.is_empty() {
+        // We cannot redirect without a host
+        return ("redirect", Response::new_400_badrequest());
+    }
+
+    // Assemble the redirect location
+    let target = str::from_utf8(&request.target).unwrap_or("/");
+    let location = match tls_port {
+        "443" => format!("https://{host}{target}"),
+        port => format!("https://{host}:{port}{target}"),
+    };
+
+    // Create the response
+    let mut response = Response::new_status_reason(308, "Permanent Redirect");
+    response.set_field("Location", location);
+    response.set_content_length(0);
+    response.set_connection_close();
+    ("redirect", response)
+}
+
+/// Runs a listener in a separate thread and reports its error via the channel
+fn spawn_listener<F>(errors: &mpsc::Sender<Error>, listener: F)
+where
+    F: FnOnce() -> Result<(), Error> + Send + 'static,
+{
+    let errors = errors.clone();
+    thread::spawn(move || {
+        if let Err(e) = listener() {
+            let _ = errors.send(e);
+        }
     });
+}
+
+/// Starts the given listener in a separate thread
+fn start_listener(
+    spec: &ListenerSpec,
+    socket: Socket,
+    config: &Arc<ConfigHandle>,
+    acceptor: Option<Arc<ReloadingAcceptor>>,
+    errors: &mpsc::Sender<Error>,
+) {
+    let (address, config_, connmax) =
+        (spec.address.clone(), config.clone(), config.current().BAMBORVIDEOSTREAM_CONNMAX);
+    if spec.redirect {
+        // Redirect all requests to HTTPS
+        let server = Arc::new(Server::new(connmax, move |source, sink| {
+            let config_ = config_.current();
+            reqresp(source, sink, &config_, None, |request| redirect(request, &config_))
+        }));
+        spawn_listener(errors, move || listener::serve(server, &address, socket, acceptor, connmax));
+    } else {
+        // Serve the API with the listener's auth policy
+        let policy = spec.auth;
+        let server = Arc::new(Server::new(connmax, move |source, sink| {
+            let config_ = config_.current();
+            reqresp(source, sink, &config_, Some(policy), |request| route(request, &config_, policy))
+        }));
+        spawn_listener(errors, move || listener::serve(server, &address, socket, acceptor, connmax));
+    }
+}
+
+/// Applies a reloaded config to the running services
+fn reload(previous: &Config, config: &Config) {
+    // Restart the services of changed or removed devices; all other sessions are kept. A changed device config also
+    // supersedes PINs that have been changed via the admin API.
+    let changed_devices = previous.changed_devices(config);
+    for device in &changed_devices {
+        config::clear_pin_override(&device.service_key());
+        P1Service::stop(&device.service_key());
+    }
+    if !changed_devices.is_empty() {
+        mosaic::stop_all();
+    }
+
+    // Report changes that cannot be applied at runtime
+    let restart_required = previous.restart_required(config);
+    log::configure(config.BAMBORVIDEOSTREAM_LOGLEVEL, config.BAMBORVIDEOSTREAM_LOGFORMAT);
+    match restart_required.is_empty() {
+        true => log::info("Reloaded config", &[]),
+        false => log::warn(
+            "Reloaded config, but some changes require a restart",
+            &[("settings", restart_required.join(",").into())],
+        ),
+    }
+}
 
-    // Start the server and dispatch connections
-    server.accept(config.BAMBORVIDEOSTREAM_SOCKADDR.as_ref())?;
-    unreachable!("`server.accept` can only return in case of an error");
+/// Prints the effective config with all secrets redacted
+fn check_config<I>(args: I) -> Result<(), Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    let config = Config::load(args)?;
+    print!("{}", config.redacted());
+    Ok(())
+}
+
+/// A fallible main function
+fn try_main<I>(args: I) -> Result<(), Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    // Load config and reload it on changes
+    let config_handle = Arc::new(ConfigHandle::load(args)?);
+    config_handle.watch(reload)?;
+    let config = config_handle.current();
+    log::configure(config.BAMBORVIDEOSTREAM_LOGLEVEL, config.BAMBORVIDEOSTREAM_LOGFORMAT);
+
+    // Open the device store if configured
+    if let (Some(path), Some(store_key)) = (&config.BAMBORVIDEOSTREAM_DEVICESTORE, &config.BAMBORVIDEOSTREAM_STOREKEY) {
+        devicestore::open(path, store_key)?;
+    }
+
+    // Load the certificate if there is an HTTPS listener
+    let acceptor = match config.https_sockaddr() {
+        Some(_) => {
+            let cert = config.BAMBORVIDEOSTREAM_TLSCERT.as_deref().unwrap_or_default();
+            let key = config.BAMBORVIDEOSTREAM_TLSKEY.as_deref().unwrap_or_default();
+            Some(Arc::new(ReloadingAcceptor::new(cert, key)?))
+        }
+        None => None,
+    };
+
+    // Bind the listeners, preferring socket-activated sockets
+    let mut activated = systemd::listen_fds()?;
+    let mut sockets = Vec::new();
+    for spec in &config.BAMBORVIDEOSTREAM_LISTENERS {
+        let socket = Socket::take_or_bind(&spec.address, &mut activated)?;
+        sockets.push((spec, socket));
+    }
+    if let Some(socket) = activated.first() {
+        return Err(error!("Socket-activated socket does not match any listener: {socket:?}"));
+    }
+
+    // Start the listeners and the watchdog
+    shutdown::register_signals()?;
+    let (errors, errors_rx) = mpsc::channel();
+    let mut probes = Vec::new();
+    for (spec, socket) in sockets {
+        probes.push(Probe::new(&spec.address, &socket)?);
+        start_listener(spec, socket, &config_handle, acceptor.clone(), &errors);
+    }
+    systemd::spawn_watchdog(probes.clone())?;
+    systemd::notify_or_log(systemd::READY);
+
+    // Wait until a listener fails or a shutdown is requested
+    drop(errors);
+    while !shutdown::is_requested() {
+        match errors_rx.recv_timeout(SHUTDOWN_POLL) {
+            Ok(error) => {
+                systemd::notify_or_log(systemd::STOPPING);
+                return Err(error);
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                systemd::notify_or_log(systemd::STOPPING);
+                return Err(error!("All listeners have terminated unexpectedly"));
+            }
+        }
+    }
+
+    // Shut down gracefully
+    graceful_shutdown(&config_handle.current(), &probes)
+}
+
+/// The interval to check whether a shutdown has been requested
+const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+/// Stops the listeners and waits until all streams and device sessions have been closed or the grace period expires
+fn graceful_shutdown(config: &Config, probes: &[Probe]) -> Result<(), Error> {
+    // Stop accepting new connections
+    systemd::notify_or_log(systemd::STOPPING);
+    for probe in probes {
+        probe.wake(SHUTDOWN_POLL);
+    }
+
+    // Wait for the running streams and sessions
+    let grace = Duration::from_secs(config.BAMBORVIDEOSTREAM_SHUTDOWNGRACE);
+    let active = shutdown::wait_idle(grace);
+    if active > 0 {
+        log::warn("Grace period expired with streams or sessions still running", &[("active", active.into())]);
+    }
+
+    // Flush the logs
+    io::stdout().flush()?;
+    io::stderr().flush()?;
+    Ok(())
 }
 
 pub fn main() {
-    if let Err(error) = try_main() {
+    // Select the command
+    let mut args = env::args().skip(1);
+    let command = args.next();
+    let result = match command.as_deref() {
+        Some("help" | "-h" | "--help") => {
+            print!("{}", cli::USAGE);
+            Ok(())
+        }
+        // Flags without a command are passed to `serve` for compatibility
+        None => try_main(args),
+        Some(flag) if flag.starts_with("--") => try_main(iter::once(flag.to_string()).chain(args)),
+        Some("serve") => try_main(args),
+        Some("check-config") => check_config(args),
+        Some("snapshot") => cli::snapshot::main(args),
+        Some("stream") => cli::stream::main(args),
+        Some("probe") => cli::probe::main(args),
+        Some("gen-apikey") => cli::apikey::main(args),
+        Some("healthcheck") => cli::healthcheck::main(args),
+        Some("mock-p1") => mock::main(args),
+        Some(command) => Err(error!("Unknown command: {command}\n\n{}", cli::USAGE)),
+    };
+
+    // Log the error if any
+    if let Err(error) = result {
         error.log();
         process::exit(1);
     }
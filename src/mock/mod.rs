@@ -0,0 +1,310 @@
+//! A mock P1 printer for development and tests
+//!
+//! The mock accepts TLS connections, validates the `bblp` login packet against the configured access code and streams
+//! JPEG frames with the same 16-byte header framing as a real P1 device.
+
+mod pattern;
+
+use crate::{
+    error,
+    error::Error,
+    log::{self, Level},
+    mock::pattern::Frames,
+    services::tls::Acceptor,
+};
+use bamborvideostream_p1::{
+    protocol::{LOGIN_PACKET, LOGIN_PACKET_PIN},
+    FrameHeader,
+};
+use std::{
+    convert::Infallible,
+    fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+/// Faults to inject into the mock sessions
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Faults {
+    /// Reject every login as if the PIN was wrong
+    pub reject_login: bool,
+    /// Stop sending frames after the given amount of frames but keep the connection open
+    pub stall_after: Option<u64>,
+    /// Send a truncated frame after the given amount of frames and close the connection
+    pub truncate_after: Option<u64>,
+    /// Close the connection after the given amount of frames
+    pub disconnect_after: Option<u64>,
+}
+
+/// The mock printer options
+#[derive(Debug, Clone)]
+pub struct MockOptions {
+    /// The socket address to listen on
+    pub listen: String,
+    /// The access code to accept
+    pub pin: String,
+    /// A directory with JPEG files to serve instead of the test pattern
+    pub frames: Option<PathBuf>,
+    /// The interval between two frames
+    pub interval: Duration,
+    /// A custom PEM certificate and PKCS#8 key to use instead of the built-in identity
+    pub identity: Option<(PathBuf, PathBuf)>,
+    /// The faults to inject
+    pub faults: Faults,
+}
+impl MockOptions {
+    /// Parses the options from the command line arguments
+    pub fn from_args<I>(args: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        // Setup the defaults
+        let mut options = Self {
+            listen: "127.0.0.1:6000".to_string(),
+            pin: "12345678".to_string(),
+            frames: None,
+            interval: Duration::from_secs(1),
+            identity: None,
+            faults: Faults::default(),
+        };
+
+        // Parse the arguments
+        let (mut cert, mut key) = (None, None);
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| error!("Missing value for argument {arg}"));
+            match arg.as_str() {
+                "--listen" => options.listen = value()?,
+                "--pin" => options.pin = value()?,
+                "--frames" => options.frames = Some(PathBuf::from(value()?)),
+                "--interval-ms" => options.interval = Duration::from_millis(value()?.parse()?),
+                "--cert" => cert = Some(PathBuf::from(value()?)),
+                "--key" => key = Some(PathBuf::from(value()?)),
+                "--reject-login" => options.faults.reject_login = true,
+                "--stall-after" => options.faults.stall_after = Some(value()?.parse()?),
+                "--truncate-after" => options.faults.truncate_after = Some(value()?.parse()?),
+                "--disconnect-after" => options.faults.disconnect_after = Some(value()?.parse()?),
+                _ => return Err(error!("Unknown argument: {arg}")),
+            }
+        }
+
+        // Validate the identity
+        options.identity = match (cert, key) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => return Err(error!("--cert and --key must be specified together")),
+        };
+        Ok(options)
+    }
+}
+
+/// A mock P1 printer
+pub struct MockPrinter {
+    /// The options
+    options: MockOptions,
+    /// The TLS acceptor
+    acceptor: Acceptor,
+    /// The frames to serve
+    frames: Frames,
+}
+impl MockPrinter {
+    /// The built-in self-signed certificate
+    const CERT: &'static [u8] = include_bytes!("identity.crt");
+    /// The built-in private key for the self-signed certificate
+    ///
+    /// # Important
+    /// This key is public; it is only meant to provide a TLS endpoint for the mock.
+    const KEY: &'static [u8] = include_bytes!("identity.key");
+    /// The duration of a stall
+    const STALL: Duration = Duration::from_secs(60);
+
+    /// Creates a new mock printer
+    pub fn new(options: MockOptions) -> Result<Self, Error> {
+        // Load the identity
+        let acceptor = match &options.identity {
+            Some((cert, key)) => Acceptor::new(&fs::read(cert)?, &fs::read(key)?)?,
+            None => Acceptor::new(Self::CERT, Self::KEY)?,
+        };
+
+        // Load the frames
+        let frames = match &options.frames {
+            Some(dir) => Frames::from_dir(dir)?,
+            None => Frames::TestPattern,
+        };
+        Ok(Self { options, acceptor, frames })
+    }
+
+    /// Listens on the configured address and serves each connection in a separate thread
+    ///
+    /// # Note
+    /// The actual address is logged, so that tests can listen on an ephemeral port (e.g. `127.0.0.1:0`).
+    pub fn serve(self) -> Result<Infallible, Error> {
+        let socket = TcpListener::bind(&self.options.listen)?;
+        let address = socket.local_addr()?.to_string();
+        log::info("Mock printer listening", &[("address", address.into())]);
+        self.serve_on(socket)
+    }
+
+    /// Serves each connection from the given socket in a separate thread
+    fn serve_on(self, socket: TcpListener) -> Result<Infallible, Error> {
+        let this = Arc::new(self);
+        loop {
+            // Accept the next connection
+            let (stream, peer) = socket.accept()?;
+            let this = this.clone();
+            thread::spawn(move || {
+                if let Err(e) = this.handle(stream) {
+                    e.log_with(Level::Warn, &[("peer", peer.to_string().into())]);
+                }
+            });
+        }
+    }
+
+    /// Handles a single connection
+    fn handle(&self, stream: TcpStream) -> Result<(), Error> {
+        // Perform the TLS handshake and validate the login
+        let mut stream = self.acceptor.accept(stream)?;
+        let mut login = [0; LOGIN_PACKET.len()];
+        stream.read_exact(&mut login)?;
+        if self.options.faults.reject_login || !self.is_valid_login(&login) {
+            // Close the connection like a real device does
+            return Err(error!("Rejected login"));
+        }
+
+        // Stream the frames
+        for index in 0.. {
+            let faults = &self.options.faults;
+            if faults.disconnect_after == Some(index) {
+                // Close the connection
+                return Ok(());
+            }
+            if faults.stall_after == Some(index) {
+                // Stall and close the connection afterwards
+                thread::sleep(Self::STALL);
+                return Ok(());
+            }
+
+            // Send the frame
+            let jpeg = self.frames.frame(index)?;
+            let truncate = faults.truncate_after == Some(index);
+            Self::send(&mut stream, &jpeg, truncate)?;
+            if truncate {
+                return Ok(());
+            }
+            thread::sleep(self.options.interval);
+        }
+        Ok(())
+    }
+
+    /// Validates the login packet against the configured PIN
+    fn is_valid_login(&self, login: &[u8; LOGIN_PACKET.len()]) -> bool {
+        // Split the packet into the header and username template and the PIN field
+        let (Some(template), Some(pin)) = (login.get(..LOGIN_PACKET_PIN), login.get(LOGIN_PACKET_PIN..)) else {
+            return false;
+        };
+        let expected_template = LOGIN_PACKET.get(..LOGIN_PACKET_PIN).unwrap_or_default();
+
+        // Compare the template and the zero-padded PIN
+        let pin_len = pin.iter().position(|byte| *byte == 0).unwrap_or(pin.len());
+        let pin = pin.get(..pin_len).unwrap_or_default();
+        template == expected_template && pin == self.options.pin.as_bytes()
+    }
+
+    /// Sends a frame with the P1 frame header
+    fn send<T>(stream: &mut T, jpeg: &[u8], truncate: bool) -> Result<(), Error>
+    where
+        T: Write,
+    {
+        // Assemble the header for the full frame size
+        let header = FrameHeader::new(u32::try_from(jpeg.len())?);
+
+        // Send the header and the (possibly truncated) frame
+        let jpeg = match truncate {
+            true => jpeg.get(..jpeg.len() / 2).unwrap_or_default(),
+            false => jpeg,
+        };
+        stream.write_all(&header.raw)?;
+        stream.write_all(jpeg)?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+/// Runs the mock printer with the given command line arguments
+pub fn main<I>(args: I) -> Result<(), Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    // Load the options and serve forever
+    let options = MockOptions::from_args(args)?;
+    let printer = MockPrinter::new(options)?;
+    printer.serve()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        secret::Secret,
+        services::p1::{P1Service, P1Source},
+    };
+    use std::time::Instant;
+
+    /// Starts a mock printer with the given faults on an ephemeral port and returns its address
+    fn start(faults: Faults) -> String {
+        let options = MockOptions::from_args(["--interval-ms", "20"].map(String::from)).unwrap();
+        let printer = MockPrinter::new(MockOptions { faults, ..options }).unwrap();
+        let socket = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        thread::spawn(move || printer.serve_on(socket));
+        address
+    }
+
+    /// Starts a device service for the mock printer with the given address
+    fn service(address: &str) -> Arc<P1Service> {
+        P1Service::with_source(address, P1Source::new(address, &Secret::new("12345678")))
+    }
+
+    /// Waits until the service has terminated
+    fn wait_terminated(service: &P1Service) {
+        let start = Instant::now();
+        while !service.is_terminated() {
+            assert!(start.elapsed() < Duration::from_secs(10), "Service did not terminate");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// The service logs in and publishes the JPEGs of the mock
+    #[test]
+    fn service_frames() {
+        let service = service(&start(Faults::default()));
+        let (sequence, frame) = service.latest_frame(None, Duration::from_secs(10)).unwrap();
+        assert_eq!(frame.content_type, "image/jpeg");
+        assert!(frame.data.starts_with(&[0xff, 0xd8]));
+
+        let (next, _) = service.next_frame(Some(sequence), Duration::from_secs(10)).unwrap();
+        assert!(next > sequence);
+    }
+
+    /// The service terminates without frames if the login is rejected
+    #[test]
+    fn service_rejected_login() {
+        let service = service(&start(Faults { reject_login: true, ..Faults::default() }));
+        assert!(service.latest_frame(None, Duration::from_secs(2)).is_none());
+        wait_terminated(&service);
+    }
+
+    /// The service terminates after a truncated frame and keeps the last complete frame
+    #[test]
+    fn service_truncated_frame() {
+        let service = service(&start(Faults { truncate_after: Some(1), ..Faults::default() }));
+        assert!(service.latest_frame(None, Duration::from_secs(10)).is_some());
+        wait_terminated(&service);
+        assert!(service.latest_frame(None, Duration::ZERO).is_some());
+    }
+}
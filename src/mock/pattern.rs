@@ -0,0 +1,85 @@
+//! The frames served by the mock printer
+
+use crate::{error, error::Error, services::image::Canvas};
+use std::{fs, path::Path};
+
+/// The frames served by the mock printer
+#[derive(Debug, Clone)]
+pub enum Frames {
+    /// A synthetic test pattern with a moving bar and a frame counter
+    TestPattern,
+    /// A fixed set of JPEG files that are served in a loop
+    Files(Vec<Vec<u8>>),
+}
+impl Frames {
+    /// The width of the test pattern
+    const WIDTH: usize = 640;
+    /// The height of the test pattern
+    const HEIGHT: usize = 360;
+    /// The color bars of the test pattern
+    const BARS: [[u8; 3]; 8] = [
+        [0xC0, 0xC0, 0xC0],
+        [0xC0, 0xC0, 0x00],
+        [0x00, 0xC0, 0xC0],
+        [0x00, 0xC0, 0x00],
+        [0xC0, 0x00, 0xC0],
+        [0xC0, 0x00, 0x00],
+        [0x00, 0x00, 0xC0],
+        [0x10, 0x10, 0x10],
+    ];
+
+    /// Loads all `.jpg`/`.jpeg` files from the given directory in lexical order
+    pub fn from_dir(dir: &Path) -> Result<Self, Error> {
+        // Collect the JPEG paths
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+            if extension.eq_ignore_ascii_case("jpg") || extension.eq_ignore_ascii_case("jpeg") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        // Load the files
+        let files: Vec<_> = paths.iter().map(fs::read).collect::<Result<_, _>>()?;
+        if files.is_empty() {
+            return Err(error!("No JPEG files in {}", dir.display()));
+        }
+        Ok(Self::Files(files))
+    }
+
+    /// Gets the frame with the given index
+    pub fn frame(&self, index: u64) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::TestPattern => Self::test_pattern(index),
+            Self::Files(files) => {
+                // Select the file in a loop
+                let index = index.checked_rem(files.len() as u64).unwrap_or_default();
+                let file = files.get(index as usize).ok_or_else(|| error!("Invalid frame index"))?;
+                Ok(file.clone())
+            }
+        }
+    }
+
+    /// Renders the test pattern for the given frame index
+    fn test_pattern(index: u64) -> Result<Vec<u8>, Error> {
+        // Draw the color bars
+        let mut canvas = Canvas::new(Self::WIDTH, Self::HEIGHT, [0, 0, 0]);
+        let bar_width = Self::WIDTH.div_ceil(Self::BARS.len());
+        for (bar, color) in Self::BARS.iter().enumerate() {
+            canvas.fill_rect(bar.saturating_mul(bar_width), 0, bar_width, Self::HEIGHT, *color);
+        }
+
+        // Draw the moving bar
+        let position = index.saturating_mul(16).checked_rem(Self::WIDTH as u64).unwrap_or_default();
+        canvas.fill_rect(position as usize, 0, 8, Self::HEIGHT, [0xFF, 0xFF, 0xFF]);
+
+        // Draw the frame counter
+        let label = format!("MOCK P1 #{index}");
+        let (label_width, label_height) = Canvas::text_size(&label, 4);
+        canvas.fill_rect(16, 16, label_width.saturating_add(24), label_height.saturating_add(24), [0, 0, 0]);
+        canvas.text(28, 28, 4, [0xFF, 0xFF, 0xFF], &label);
+        canvas.to_jpeg(80)
+    }
+}
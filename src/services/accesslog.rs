@@ -0,0 +1,96 @@
+//! The access log with one line per request
+//!
+//! Secrets within the request target (the API key, device PINs and credentials within device URLs) are redacted before
+//! the target is logged (see [`crate::secret::redact_target`]).
+
+use crate::{log, services::listener::AuthPolicy};
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+/// A request to log
+#[derive(Debug)]
+pub struct Access<'a> {
+    /// The request method
+    pub method: &'a [u8],
+    /// The request target with all secrets redacted
+    pub target: &'a str,
+    /// The matched route (see [`crate::services::metrics::record_request`])
+    pub route: &'static str,
+    /// The response status
+    pub status: &'a [u8],
+    /// The amount of response bytes written
+    pub bytes: u64,
+    /// The time from reading the request until the response has been written
+    pub duration: Duration,
+    /// The peer address
+    pub client: &'a str,
+    /// The auth policy of the listener, or `None` for redirect listeners
+    pub policy: Option<AuthPolicy>,
+}
+impl Access<'_> {
+    /// Logs the request at info level
+    pub fn log(&self) {
+        let (method, status) = (String::from_utf8_lossy(self.method), String::from_utf8_lossy(self.status));
+        log::info(
+            "request",
+            &[
+                ("method", method.into()),
+                ("path", self.target.into()),
+                ("status", status.into()),
+                ("bytes", self.bytes.into()),
+                ("duration_ms", self.duration.as_millis().into()),
+                ("client", self.client.into()),
+                ("auth", self.auth().into()),
+            ],
+        );
+    }
+
+    /// The API key label: `-` for unauthed routes, `trusted` for requests that need no API key on their listener,
+    /// otherwise whether the API key has been accepted (`apikey` or `adminkey` for the admin API) or rejected
+    /// (`rejected`)
+    fn auth(&self) -> &'static str {
+        let is_authed = self.route.starts_with("/v1/") || matches!(self.route, "/metrics" | "/readyz?verbose");
+        let is_admin = self.route.starts_with("/v1/admin/");
+        match (is_authed, self.policy) {
+            (false, _) | (_, None) => "-",
+            (true, Some(AuthPolicy::Admin)) => "trusted",
+            // The admin API requires the admin key on `auth=none` listeners
+            (true, Some(AuthPolicy::Trusted)) if !is_admin => "trusted",
+            // The auth bridges are the only source of `403`
+            (true, Some(_)) if self.status == b"403" => "rejected",
+            (true, Some(_)) if is_admin => "adminkey",
+            (true, Some(_)) => "apikey",
+        }
+    }
+}
+
+/// A writer that counts the written bytes
+#[derive(Debug)]
+pub struct CountingWriter<'a, T> {
+    /// The underlying writer
+    inner: &'a mut T,
+    /// The amount of bytes written
+    pub bytes: u64,
+}
+impl<'a, T> CountingWriter<'a, T> {
+    /// Wraps the given writer
+    pub fn new(inner: &'a mut T) -> Self {
+        Self { inner, bytes: 0 }
+    }
+}
+impl<T> Write for CountingWriter<'_, T>
+where
+    T: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes = self.bytes.saturating_add(u64::try_from(written).unwrap_or(u64::MAX));
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,51 @@
+//! A camera source abstraction to plug different device families into the image service
+
+use crate::{error::Error, secret::Secret};
+use std::{fmt::Debug, sync::Arc};
+
+/// A single frame received from a camera
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// The MIME type of the frame (e.g. `image/jpeg` or `video/h264`)
+    pub content_type: &'static str,
+    /// The frame data
+    pub data: Arc<Vec<u8>>,
+    /// Whether the frame can be decoded on its own (always `true` for JPEGs; `true` for H.264 IDR access units)
+    pub keyframe: bool,
+}
+impl Frame {
+    /// Creates a new JPEG frame
+    pub fn jpeg(jpeg: Vec<u8>) -> Self {
+        Self { content_type: "image/jpeg", data: Arc::new(jpeg), keyframe: true }
+    }
+
+    /// Creates a new H.264 access unit in Annex B format
+    pub fn h264(access_unit: Vec<u8>, keyframe: bool) -> Self {
+        Self { content_type: "video/h264", data: Arc::new(access_unit), keyframe }
+    }
+}
+
+/// A camera source which can connect to a device
+pub trait Source: Debug + Send + Sync {
+    /// The MIME type of the frames yielded by this source
+    fn content_type(&self) -> &'static str;
+
+    /// Connects to the device and returns a stream of frames
+    fn connect(&self) -> Result<Box<dyn Stream>, Error>;
+
+    /// Creates a copy of this source which logs in with the given PIN, or `None` if the source does not use a PIN
+    fn with_pin(&self, _pin: &Secret) -> Option<Arc<dyn Source>> {
+        None
+    }
+}
+
+/// A connected stream of camera frames
+pub trait Stream: Send {
+    /// Receives the next frame from the device
+    fn frame(&mut self) -> Result<Frame, Error>;
+
+    /// Gracefully closes the connection to the device
+    fn close(self: Box<Self>) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+//! A persistent store for devices that are managed via the admin API
+//!
+//! The store is a TOML file with the same `[devices.<name>]` tables as the config file, except that the PIN is stored
+//! sealed as `pin_sealed` (see [`seal`]). Devices from the config file take precedence over stored devices with the
+//! same name.
+
+pub mod seal;
+
+use crate::{
+    error,
+    error::Error,
+    secret::Secret,
+    services::{
+        config::{self, Device},
+        devicestore::seal::SealingKey,
+    },
+};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+use toml::{Table, Value};
+
+/// The opened device store, if any
+static STORE: Mutex<Option<DeviceStore>> = Mutex::new(None);
+
+/// The name of the sealed PIN field
+const PIN_SEALED_FIELD: &str = "pin_sealed";
+
+/// A persistent device store
+#[derive(Debug)]
+pub struct DeviceStore {
+    /// The path to the store file
+    path: PathBuf,
+    /// The key to seal the PINs
+    key: SealingKey,
+    /// The stored devices
+    pub devices: BTreeMap<String, Device>,
+}
+impl DeviceStore {
+    /// Loads the store from the given file; a missing file is an empty store
+    pub fn load<T>(path: T, store_key: &Secret) -> Result<Self, Error>
+    where
+        T: AsRef<Path>,
+    {
+        // Read the store file
+        let (path, key) = (path.as_ref().to_path_buf(), SealingKey::derive(store_key)?);
+        let toml = match fs::read_to_string(&path) {
+            Ok(toml) => toml,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self { path, key, devices: BTreeMap::new() }),
+            Err(e) => return Err(error!(with: e, "Failed to read device store {}", path.display())),
+        };
+        let table: Table = toml.parse().map_err(|e| error!(with: e, "Invalid device store {}", path.display()))?;
+
+        // Parse the devices
+        let mut devices = BTreeMap::new();
+        let stored = match table.get("devices") {
+            Some(Value::Table(stored)) => stored.clone(),
+            Some(_) => return Err(error!("Invalid device store {}: devices must be a table", path.display())),
+            None => Table::new(),
+        };
+        for (name, device) in stored {
+            let origin = format!("devices.{name} in {}", path.display());
+            let Value::Table(device) = device else {
+                return Err(error!("Invalid {origin}: expected a table"));
+            };
+
+            // Collect the fields and open the sealed PIN
+            let (mut fields, mut pin) = (Vec::new(), None);
+            for (key_, value) in device {
+                let Some(value) = config::device_field(value) else {
+                    return Err(error!("Invalid {origin}: {key_} must be a string, number or boolean"));
+                };
+                match key_.as_str() {
+                    PIN_SEALED_FIELD => {
+                        pin = Some(key.open(&value, &name).map_err(|e| error!(with: e, "Invalid {origin}"))?);
+                    }
+                    "pin" => {
+                        return Err(error!("Invalid {origin}: plaintext PINs are not allowed in the device store"))
+                    }
+                    _ => fields.push((key_, value)),
+                }
+            }
+            devices.insert(name.clone(), Device::from_fields(&name, fields, pin, &origin)?);
+        }
+        Ok(Self { path, key, devices })
+    }
+
+    /// Writes the store file atomically
+    pub fn save(&self) -> Result<(), Error> {
+        // Render the store with sealed PINs
+        let mut toml =
+            String::from("# Managed by bamborvideostream via /v1/admin/devices; PINs are sealed with the store key\n");
+        for (name, device) in &self.devices {
+            let pin_sealed = device.pin.as_ref().map(|pin| self.key.seal(pin, name)).transpose()?;
+            toml.push('\n');
+            toml.push_str(
+                &device.to_toml(name, pin_sealed.as_deref().map(|pin_sealed| (PIN_SEALED_FIELD, pin_sealed))),
+            );
+        }
+
+        // Write a temporary file and replace the store file
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file: File = options
+            .open(&temp_path)
+            .map_err(|e| error!(with: e, "Failed to write device store {}", self.path.display()))?;
+        file.write_all(toml.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, &self.path)
+            .map_err(|e| error!(with: e, "Failed to replace device store {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+/// Locks the opened device store
+fn store() -> MutexGuard<'static, Option<DeviceStore>> {
+    STORE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Opens the device store at the given path for the server
+pub fn open<T>(path: T, store_key: &Secret) -> Result<(), Error>
+where
+    T: AsRef<Path>,
+{
+    let store_ = DeviceStore::load(path, store_key)?;
+    *store() = Some(store_);
+    Ok(())
+}
+
+/// Whether a device store has been opened
+pub fn is_open() -> bool {
+    store().is_some()
+}
+
+/// Gets the stored device with the given name
+pub fn get(name: &str) -> Option<Device> {
+    store().as_ref()?.devices.get(name).cloned()
+}
+
+/// Gets all stored devices
+pub fn devices() -> BTreeMap<String, Device> {
+    store().as_ref().map(|store| store.devices.clone()).unwrap_or_default()
+}
+
+/// Adds or replaces a device and persists the store; returns the previous device, if any
+pub fn put(name: &str, device: Device) -> Result<Option<Device>, Error> {
+    let mut store = store();
+    let store = store.as_mut().ok_or_else(|| error!("No device store configured"))?;
+    let previous = store.devices.insert(name.to_string(), device);
+    if let Err(e) = store.save() {
+        // Restore the previous state
+        match &previous {
+            Some(previous) => store.devices.insert(name.to_string(), previous.clone()),
+            None => store.devices.remove(name),
+        };
+        return Err(e);
+    }
+    Ok(previous)
+}
+
+/// Removes a device and persists the store; returns the removed device, if any
+pub fn remove(name: &str) -> Result<Option<Device>, Error> {
+    let mut store = store();
+    let store = store.as_mut().ok_or_else(|| error!("No device store configured"))?;
+    let Some(previous) = store.devices.remove(name) else {
+        return Ok(None);
+    };
+    if let Err(e) = store.save() {
+        // Restore the previous state
+        store.devices.insert(name.to_string(), previous);
+        return Err(e);
+    }
+    Ok(Some(previous))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    /// Stored devices with non-ASCII and control characters as well as HTTP cameras without PIN survive a save and a
+    /// load
+    #[test]
+    fn round_trip() {
+        // Create a store with an unusual device
+        let path = env::temp_dir().join(format!("bamborvideostream-devicestore-{}.toml", process::id()));
+        let store_key = Secret::new("correct horse battery staple");
+        let mut store = DeviceStore::load(&path, &store_key).unwrap();
+        let fields = [
+            ("model", "Cafe\u{301} \u{1b}[31m\"P1S\"\\\t\u{7f}"),
+            ("address", "drucker-ä.local:6000\u{0}\r\n"),
+            ("rotate", "90"),
+            ("brightness", "0.1"),
+        ];
+        let fields = fields.map(|(key, value)| (key.to_string(), value.to_string()));
+        let device = Device::from_fields("workshop", fields, Some(Secret::new("1234\"\n5678")), "test").unwrap();
+        store.devices.insert("workshop".to_string(), device.clone());
+
+        // Add an HTTP camera without PIN
+        let fields = [("kind", "mjpeg"), ("url", "http://user:p%40ss@[fe80::1]:8080/?action=stream&ä=\u{1b}")];
+        let fields = fields.map(|(key, value)| (key.to_string(), value.to_string()));
+        let camera = Device::from_fields("door", fields, None, "test").unwrap();
+        store.devices.insert("door".to_string(), camera.clone());
+
+        // Save and load the store
+        store.save().unwrap();
+        let loaded = DeviceStore::load(&path, &store_key);
+        fs::remove_file(&path).unwrap();
+        let expected = BTreeMap::from([("workshop".to_string(), device), ("door".to_string(), camera)]);
+        assert_eq!(loaded.unwrap().devices, expected);
+    }
+}
@@ -0,0 +1,190 @@
+//! Seals access codes at rest with a key derived from the store key
+//!
+//! # Construction
+//! The sealed value is `v2:<base64(nonce || ciphertext || tag)>`, sealed with XChaCha20-Poly1305 under a random 24-byte
+//! nonce. The key is derived from the store key via HKDF-SHA256, and the context (e.g. the device name) is authenticated
+//! as associated data, so sealed access codes cannot be swapped between devices.
+
+use crate::{error, error::Error, secret, secret::Secret};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    KeyInit, XChaCha20Poly1305, XNonce,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::fmt::{self, Debug, Formatter};
+use zeroize::Zeroize;
+
+/// The format version prefix
+const VERSION: &str = "v2";
+/// The HKDF info to derive the sealing key from the store key
+const KEY_INFO: &[u8] = b"bamborvideostream devicestore v2 xchacha20poly1305";
+/// The nonce size
+const NONCE_SIZE: usize = 24;
+/// The tag size
+const TAG_SIZE: usize = 16;
+
+/// Derives the raw sealing key from the given store key
+fn derive_key(store_key: &[u8]) -> Result<[u8; 32], Error> {
+    let mut key = [0; 32];
+    let hkdf = Hkdf::<Sha256>::new(None, store_key);
+    hkdf.expand(KEY_INFO, &mut key).map_err(|_| error!("Failed to derive the sealing key"))?;
+    Ok(key)
+}
+
+/// The key to seal and open access codes
+pub struct SealingKey {
+    /// The cipher; it erases its key on drop
+    cipher: XChaCha20Poly1305,
+}
+impl SealingKey {
+    /// Derives the key from the given store key
+    pub fn derive(store_key: &Secret) -> Result<Self, Error> {
+        let mut key = derive_key(store_key.expose().as_bytes())?;
+        let cipher = XChaCha20Poly1305::new(&key.into());
+        key.zeroize();
+        Ok(Self { cipher })
+    }
+
+    /// Seals the given plaintext for the given context (e.g. the device name)
+    pub fn seal(&self, plaintext: &Secret, context: &str) -> Result<String, Error> {
+        let nonce = secret::random::<NONCE_SIZE>()?;
+        self.seal_with_nonce(plaintext, context, nonce)
+    }
+
+    /// Seals the given plaintext for the given context with the given nonce
+    fn seal_with_nonce(&self, plaintext: &Secret, context: &str, nonce: [u8; NONCE_SIZE]) -> Result<String, Error> {
+        let payload = Payload { msg: plaintext.expose().as_bytes(), aad: context.as_bytes() };
+        let ciphertext =
+            (self.cipher.encrypt(XNonce::from_slice(&nonce), payload)).map_err(|_| error!("Failed to seal value"))?;
+        let sealed = [nonce.as_slice(), &ciphertext].concat();
+        Ok(format!("{VERSION}:{}", BASE64.encode(sealed)))
+    }
+
+    /// Opens the given sealed value for the given context
+    pub fn open(&self, sealed: &str, context: &str) -> Result<Secret, Error> {
+        // Decode the sealed value
+        let Some(sealed) = sealed.strip_prefix(VERSION).and_then(|sealed| sealed.strip_prefix(':')) else {
+            return Err(error!("Unsupported sealed value format; add the device again via the admin API"));
+        };
+        let sealed = BASE64.decode(sealed).map_err(|e| error!(with: e, "Invalid sealed value"))?;
+        let (nonce, ciphertext) =
+            sealed.split_at_checked(NONCE_SIZE).ok_or_else(|| error!("Truncated sealed value"))?;
+        if ciphertext.len() < TAG_SIZE {
+            return Err(error!("Truncated sealed value"));
+        }
+
+        // Authenticate and decrypt the plaintext
+        let payload = Payload { msg: ciphertext, aad: context.as_bytes() };
+        let plaintext = (self.cipher.decrypt(XNonce::from_slice(nonce), payload)).map_err(|_| {
+            error!("Failed to authenticate sealed value; the store key is wrong or the value was modified")
+        })?;
+        match String::from_utf8(plaintext) {
+            Ok(plaintext) => Ok(Secret::new(plaintext)),
+            Err(e) => {
+                // Erase the plaintext before reporting the error
+                e.into_bytes().zeroize();
+                Err(error!("Invalid sealed value: the plaintext is not valid UTF-8"))
+            }
+        }
+    }
+}
+impl Debug for SealingKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(secret::REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The store key for the tests
+    const STORE_KEY: &str = "correct horse battery staple";
+    /// A value sealed for `workshop` with [`STORE_KEY`] and the nonce `0x00..=0x17`, as computed by an independent
+    /// implementation
+    const SEALED: &str = "v2:AAECAwQFBgcICQoLDA0ODxAREhMUFRYXQeWEd9pw1CevHEUih7A_LfBvsTd-LyLz";
+
+    /// Gets the sealing key for the given store key
+    fn key(store_key: &str) -> SealingKey {
+        SealingKey::derive(&Secret::new(store_key)).unwrap()
+    }
+
+    /// Decodes a sealed value into its raw bytes
+    fn decode(sealed: &str) -> Vec<u8> {
+        BASE64.decode(sealed.strip_prefix("v2:").unwrap()).unwrap()
+    }
+
+    /// Encodes raw bytes as sealed value
+    fn encode(raw: &[u8]) -> String {
+        format!("v2:{}", BASE64.encode(raw))
+    }
+
+    /// The derived key matches HKDF-SHA256 as computed by an independent implementation
+    #[test]
+    fn derive_known_answer() {
+        let expected = "25adaa62a80bde44c9c1262076c70f3b02d401d9a237424711b29ad4de1ef854";
+        let key = derive_key(STORE_KEY.as_bytes()).unwrap();
+        assert_eq!(key.iter().map(|byte| format!("{byte:02x}")).collect::<String>(), expected);
+    }
+
+    /// Sealing with a fixed nonce yields the pinned value, so existing stores stay readable
+    #[test]
+    fn known_answer() {
+        let nonce = std::array::from_fn(|index| index as u8);
+        let sealed = key(STORE_KEY).seal_with_nonce(&Secret::new("12345678"), "workshop", nonce).unwrap();
+        assert_eq!(sealed, SEALED);
+        assert_eq!(key(STORE_KEY).open(SEALED, "workshop").unwrap().expose(), "12345678");
+    }
+
+    /// Sealed values can be opened again, and each seal uses a fresh nonce
+    #[test]
+    fn round_trip() {
+        let key = key(STORE_KEY);
+        let (first, second) =
+            (key.seal(&Secret::new("1234"), "a").unwrap(), key.seal(&Secret::new("1234"), "a").unwrap());
+        assert_ne!(first, second);
+        assert_eq!(key.open(&first, "a").unwrap().expose(), "1234");
+        assert_eq!(key.open(&second, "a").unwrap().expose(), "1234");
+        assert_eq!(key.open(&key.seal(&Secret::new(""), "a").unwrap(), "a").unwrap().expose(), "");
+    }
+
+    /// Modifying any byte of the nonce, the ciphertext or the tag is detected
+    #[test]
+    fn tampered() {
+        let (key, raw) = (key(STORE_KEY), decode(SEALED));
+        let ciphertext_start = NONCE_SIZE;
+        let tag_start = raw.len() - TAG_SIZE;
+        for index in [0, NONCE_SIZE - 1, ciphertext_start, tag_start - 1, tag_start, raw.len() - 1] {
+            let mut tampered = raw.clone();
+            tampered[index] ^= 0x01;
+            assert!(key.open(&encode(&tampered), "workshop").is_err(), "byte {index}");
+        }
+    }
+
+    /// A value sealed for another device cannot be opened
+    #[test]
+    fn wrong_context() {
+        assert!(key(STORE_KEY).open(SEALED, "workshop2").is_err());
+        assert!(key(STORE_KEY).open(SEALED, "").is_err());
+    }
+
+    /// A value sealed with another store key cannot be opened
+    #[test]
+    fn wrong_store_key() {
+        assert!(key("correct horse battery stapler").open(SEALED, "workshop").is_err());
+    }
+
+    /// Truncated and malformed values are rejected
+    #[test]
+    fn malformed() {
+        let (key, raw) = (key(STORE_KEY), decode(SEALED));
+        for len in [0, 1, NONCE_SIZE - 1, NONCE_SIZE, NONCE_SIZE + TAG_SIZE - 1, raw.len() - 1] {
+            assert!(key.open(&encode(&raw[..len]), "workshop").is_err(), "length {len}");
+        }
+        assert!(key.open(&SEALED.replacen("v2:", "v1:", 1), "workshop").is_err());
+        assert!(key.open("v2:not base64!", "workshop").is_err());
+        assert!(key.open("", "workshop").is_err());
+    }
+}
@@ -0,0 +1,84 @@
+//! The readiness of the server and its configured devices
+
+use crate::{
+    json,
+    services::{
+        config::{Config, DeviceKind},
+        metrics::{self, DeviceStatus},
+    },
+};
+use std::time::Duration;
+
+/// The readiness of a configured device
+#[derive(Debug)]
+pub struct DeviceReadiness {
+    /// The device name from the config file
+    pub name: String,
+    /// The device family
+    pub kind: DeviceKind,
+    /// The upstream status, if the device has been started before
+    pub status: Option<DeviceStatus>,
+    /// The maximum time since the last frame
+    pub max_staleness: Duration,
+}
+impl DeviceReadiness {
+    /// Whether the device is idle or has delivered a frame within its maximum staleness
+    pub fn is_ready(&self) -> bool {
+        match self.status {
+            Some(status) if status.is_active => status.staleness <= self.max_staleness,
+            _ => true,
+        }
+    }
+
+    /// Encodes the readiness as JSON object
+    fn to_json(&self) -> String {
+        let object = json::Object::new().string("name", &self.name).string("kind", &self.kind.to_string());
+        let object = match self.status {
+            Some(status) => object.string("state", status.state),
+            None => object.string("state", "idle"),
+        };
+        let object = match self.status.and_then(|status| status.frame_age) {
+            Some(frame_age) => object.float("frame_age_s", frame_age.as_secs_f64()),
+            None => object.null("frame_age_s"),
+        };
+        object.number("max_staleness_s", self.max_staleness.as_secs()).bool("ready", self.is_ready()).finish()
+    }
+}
+
+/// The readiness of the server
+#[derive(Debug)]
+pub struct Readiness {
+    /// The configured devices
+    pub devices: Vec<DeviceReadiness>,
+}
+impl Readiness {
+    /// Checks the readiness for the given config
+    ///
+    /// # Note
+    /// Since the config is validated on load and invalid reloads are rejected, a loaded config is always usable; so
+    /// the server is ready once all configured devices are idle or fresh.
+    pub fn check(config: &Config) -> Self {
+        let devices = config.all_devices().into_iter().map(|(name, device)| DeviceReadiness {
+            name,
+            kind: device.kind,
+            status: metrics::device_status(&device.service_key()),
+            max_staleness: Duration::from_secs(device.max_staleness.unwrap_or(config.BAMBORVIDEOSTREAM_MAXSTALENESS)),
+        });
+        Self { devices: devices.collect() }
+    }
+
+    /// Whether all configured devices are ready
+    pub fn is_ready(&self) -> bool {
+        self.devices.iter().all(DeviceReadiness::is_ready)
+    }
+
+    /// Encodes the readiness as JSON object
+    pub fn to_json(&self) -> String {
+        let config = json::Object::new()
+            .bool("loaded", true)
+            .number("devices", u64::try_from(self.devices.len()).unwrap_or(u64::MAX))
+            .finish();
+        let devices = json::array(self.devices.iter().map(DeviceReadiness::to_json));
+        json::Object::new().bool("ready", self.is_ready()).raw("config", &config).raw("devices", &devices).finish()
+    }
+}
@@ -0,0 +1,147 @@
+//! Camera sources for generic HTTP cameras (e.g. USB cameras behind `mjpg-streamer`)
+
+mod client;
+mod multipart;
+
+use crate::{
+    error,
+    error::Error,
+    services::{
+        camera::{Frame, Source, Stream},
+        http::{
+            client::{HttpResponse, Url},
+            multipart::Multipart,
+        },
+    },
+};
+use std::{
+    io::{BufReader, Read},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Validates that the given URL is an `http://` or `https://` URL with a host
+pub fn check_url(url: &str) -> Result<(), Error> {
+    Url::parse(url).map(drop)
+}
+
+/// The key of the service for the given MJPEG stream URL in the service registry
+pub fn mjpeg_key(url: &str) -> String {
+    format!("mjpeg+{url}")
+}
+
+/// The key of the service for the given snapshot URL and polling interval in the service registry
+pub fn snapshot_key(url: &str, interval: Duration) -> String {
+    format!("snapshot+{}ms+{url}", interval.max(SnapshotSource::MIN_INTERVAL).as_millis())
+}
+
+/// A camera source that polls a JPEG snapshot URL
+#[derive(Debug, Clone)]
+pub struct SnapshotSource {
+    /// The snapshot URL
+    url: Url,
+    /// The polling interval
+    interval: Duration,
+}
+impl SnapshotSource {
+    /// The default polling interval
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+    /// The minimum polling interval
+    const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Creates a new snapshot source
+    pub fn new(url: &str, interval: Duration) -> Result<Self, Error> {
+        let url = Url::parse(url)?;
+        Ok(Self { url, interval: interval.max(Self::MIN_INTERVAL) })
+    }
+}
+impl Source for SnapshotSource {
+    fn content_type(&self) -> &'static str {
+        "image/jpeg"
+    }
+
+    fn connect(&self) -> Result<Box<dyn Stream>, Error> {
+        Ok(Box::new(SnapshotStream { source: self.clone(), last_frame: None }))
+    }
+}
+
+/// A frame stream that polls a JPEG snapshot URL
+#[derive(Debug)]
+struct SnapshotStream {
+    /// The source configuration
+    source: SnapshotSource,
+    /// When the last frame has been fetched
+    last_frame: Option<Instant>,
+}
+impl SnapshotStream {
+    /// The maximum size of a snapshot
+    const SNAPSHOT_LIMIT: u64 = 16 * 1024 * 1024;
+}
+impl Stream for SnapshotStream {
+    fn frame(&mut self) -> Result<Frame, Error> {
+        // Pause for the remaining interval
+        if let Some(last_frame) = self.last_frame {
+            let remaining = self.source.interval.saturating_sub(last_frame.elapsed());
+            thread::sleep(remaining);
+        }
+        self.last_frame = Some(Instant::now());
+
+        // Fetch the snapshot
+        let mut response = HttpResponse::get(&self.source.url)?;
+        if response.status != 200 {
+            return Err(error!("Unexpected HTTP status for snapshot: {}", response.status));
+        }
+
+        // Read the snapshot
+        let mut jpeg = Vec::new();
+        (&mut response.body).take(Self::SNAPSHOT_LIMIT).read_to_end(&mut jpeg)?;
+        if !jpeg.starts_with(&[0xFF, 0xD8]) {
+            return Err(error!("Snapshot is not a JPEG image"));
+        }
+        Ok(Frame::jpeg(jpeg))
+    }
+}
+
+/// A camera source that reads a `multipart/x-mixed-replace` MJPEG stream
+#[derive(Debug, Clone)]
+pub struct MjpegSource {
+    /// The stream URL
+    url: Url,
+}
+impl MjpegSource {
+    /// Creates a new MJPEG source
+    pub fn new(url: &str) -> Result<Self, Error> {
+        let url = Url::parse(url)?;
+        Ok(Self { url })
+    }
+}
+impl Source for MjpegSource {
+    fn content_type(&self) -> &'static str {
+        "image/jpeg"
+    }
+
+    fn connect(&self) -> Result<Box<dyn Stream>, Error> {
+        // Open the stream
+        let response = HttpResponse::get(&self.url)?;
+        if response.status != 200 {
+            return Err(error!("Unexpected HTTP status for MJPEG stream: {}", response.status));
+        }
+
+        // Create the multipart parser
+        let content_type = response.field("Content-Type").unwrap_or_default().to_string();
+        let multipart = Multipart::new(&content_type, response.body)?;
+        Ok(Box::new(MjpegStream { multipart }))
+    }
+}
+
+/// A frame stream from a `multipart/x-mixed-replace` MJPEG stream
+struct MjpegStream {
+    /// The multipart parser
+    multipart: Multipart<BufReader<Box<dyn Read + Send>>>,
+}
+impl Stream for MjpegStream {
+    fn frame(&mut self) -> Result<Frame, Error> {
+        let jpeg = self.multipart.part()?;
+        Ok(Frame::jpeg(jpeg))
+    }
+}
@@ -0,0 +1,196 @@
+//! A parser for `multipart/x-mixed-replace` MJPEG streams
+
+use crate::{error, error::Error};
+use std::{
+    io::{BufRead, Read},
+    str,
+};
+
+/// A parser for `multipart/x-mixed-replace` streams
+#[derive(Debug)]
+pub struct Multipart<T> {
+    /// The underlying body
+    body: T,
+    /// The boundary without the leading dashes
+    boundary: String,
+    /// Whether the boundary delimiter of the next part has already been consumed
+    at_boundary: bool,
+}
+impl<T> Multipart<T>
+where
+    T: BufRead,
+{
+    /// The maximum length of a single header line
+    const LINE_LIMIT: u64 = 8 * 1024;
+    /// The maximum size of a single part
+    const PART_LIMIT: u64 = 16 * 1024 * 1024;
+
+    /// Creates a new multipart parser from the `Content-Type` header value and the body
+    pub fn new(content_type: &str, body: T) -> Result<Self, Error> {
+        // Validate the content type
+        let mut params = content_type.split(';').map(str::trim);
+        let mime_type = params.next().unwrap_or_default();
+        if !mime_type.eq_ignore_ascii_case("multipart/x-mixed-replace") {
+            return Err(error!("Unsupported stream content type: {mime_type}"));
+        }
+
+        // Get the boundary
+        let boundary = params.find_map(|param| param.strip_prefix("boundary="));
+        let Some(boundary) = boundary.map(|boundary| boundary.trim_matches('"')) else {
+            return Err(error!("Missing multipart boundary"));
+        };
+
+        // Some servers erroneously include the leading dashes into the boundary parameter
+        let boundary = boundary.strip_prefix("--").unwrap_or(boundary).to_string();
+        Ok(Self { body, boundary, at_boundary: false })
+    }
+
+    /// Reads the next part
+    pub fn part(&mut self) -> Result<Vec<u8>, Error> {
+        // Seek to the next boundary delimiter
+        while !self.at_boundary {
+            let line = self.line(Self::LINE_LIMIT)?;
+            self.at_boundary = self.is_delimiter(&line)?;
+        }
+
+        // Parse the part header fields
+        let mut content_length = None;
+        loop {
+            let line = self.line(Self::LINE_LIMIT)?;
+            let line = str::from_utf8(&line)?.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((key, value)) = line.split_once(':') {
+                if key.trim().eq_ignore_ascii_case("Content-Length") {
+                    content_length = Some(value.trim().parse::<u64>()?);
+                }
+            }
+        }
+
+        // Read the part body
+        match content_length {
+            Some(content_length) if content_length > Self::PART_LIMIT => Err(error!("Multipart part is too large")),
+            Some(content_length) => {
+                // Read the exact length
+                let mut part = Vec::new();
+                (&mut self.body).take(content_length).read_to_end(&mut part)?;
+                if part.len() as u64 != content_length {
+                    return Err(error!("Truncated multipart part"));
+                }
+
+                // The delimiter of the next part has not been consumed yet
+                self.at_boundary = false;
+                Ok(part)
+            }
+            None => {
+                // Read until the next delimiter
+                let mut part = Vec::new();
+                loop {
+                    let remaining = Self::PART_LIMIT.saturating_sub(part.len() as u64);
+                    let line = self.line(remaining)?;
+                    if self.is_delimiter(&line)? {
+                        break;
+                    }
+                    part.extend_from_slice(&line);
+                }
+
+                // Strip the CRLF that belongs to the delimiter
+                let stripped = part.strip_suffix(b"\r\n").or_else(|| part.strip_suffix(b"\n")).map(<[u8]>::len);
+                part.truncate(stripped.unwrap_or(part.len()));
+                self.at_boundary = true;
+                Ok(part)
+            }
+        }
+    }
+
+    /// Checks whether the line is a boundary delimiter and fails if it is the closing delimiter
+    fn is_delimiter(&self, line: &[u8]) -> Result<bool, Error> {
+        let Some(delimiter) = line.trim_ascii().strip_prefix(b"--") else {
+            return Ok(false);
+        };
+        let Some(suffix) = delimiter.strip_prefix(self.boundary.as_bytes()) else {
+            return Ok(false);
+        };
+        match suffix {
+            b"" => Ok(true),
+            b"--" => Err(error!("Multipart stream has been closed")),
+            _ => Ok(false),
+        }
+    }
+
+    /// Reads a single line including the line break
+    fn line(&mut self, limit: u64) -> Result<Vec<u8>, Error> {
+        let mut line = Vec::new();
+        (&mut self.body).take(limit).read_until(b'\n', &mut line)?;
+        if !line.ends_with(b"\n") {
+            return Err(error!("Truncated or oversized multipart line"));
+        }
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a parser for the given content type and body
+    fn multipart(content_type: &str, body: &'static [u8]) -> Result<Multipart<&'static [u8]>, Error> {
+        Multipart::new(content_type, body)
+    }
+
+    /// The boundary is taken from the content type with or without quotes and leading dashes
+    #[test]
+    fn boundary() {
+        let cases = [
+            ("multipart/x-mixed-replace;boundary=frame", "frame"),
+            ("multipart/x-mixed-replace; boundary=\"frame\"", "frame"),
+            ("Multipart/X-Mixed-Replace; charset=utf-8; boundary=--frame", "frame"),
+            ("multipart/x-mixed-replace; boundary=\"--my frame\"", "my frame"),
+        ];
+        for (content_type, boundary) in cases {
+            assert_eq!(multipart(content_type, b"").unwrap().boundary, boundary, "{content_type}");
+        }
+        assert!(multipart("image/jpeg", b"").is_err());
+        assert!(multipart("multipart/x-mixed-replace", b"").is_err());
+        assert!(multipart("multipart/x-mixed-replace; charset=utf-8", b"").is_err());
+    }
+
+    /// Parts are read via their content length or up to the next delimiter
+    #[test]
+    fn parts() {
+        let body =
+            b"preamble\r\n--frame\r\nContent-Type: image/jpeg\r\nContent-Length: 4\r\n\r\n\xFF\xD8\r\n\r\n--frame\r\n\
+            content-length:  3 \r\n\r\nabc\r\n--frame\n\nno length\r\nline two\r\n--frame\r\n\r\n\r\n--frame\r\n--frame--\r\n";
+        let mut multipart = multipart("multipart/x-mixed-replace; boundary=frame", body).unwrap();
+        assert_eq!(multipart.part().unwrap(), b"\xFF\xD8\r\n");
+        assert_eq!(multipart.part().unwrap(), b"abc");
+        assert_eq!(multipart.part().unwrap(), b"no length\r\nline two");
+        assert_eq!(multipart.part().unwrap(), b"");
+        assert!(multipart.part().is_err());
+    }
+
+    /// Lines that only start with the boundary are no delimiters
+    #[test]
+    fn boundary_prefix() {
+        let body = b"--frame\r\n\r\n--framed\r\n--frame \r\n";
+        let mut multipart = multipart("multipart/x-mixed-replace; boundary=frame", body).unwrap();
+        assert_eq!(multipart.part().unwrap(), b"--framed");
+    }
+
+    /// Truncated, oversized and malformed parts are rejected
+    #[test]
+    fn malformed() {
+        let content_type = "multipart/x-mixed-replace; boundary=frame";
+        let bodies: [&'static [u8]; 5] = [
+            b"--frame\r\nContent-Length: 10\r\n\r\nabc",
+            b"--frame\r\nContent-Length: 99999999999\r\n\r\n",
+            b"--frame\r\nContent-Length: abc\r\n\r\n",
+            b"--frame\r\n\r\nno delimiter\r\n",
+            b"",
+        ];
+        for body in bodies {
+            assert!(multipart(content_type, body).unwrap().part().is_err(), "{}", body.escape_ascii());
+        }
+    }
+}
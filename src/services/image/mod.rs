@@ -0,0 +1,257 @@
+//! Simple image helpers to render synthetic frames, overlays and composed frames
+
+pub mod font;
+pub mod rendition;
+pub mod transform;
+
+use crate::{error, error::Error, services::image::font::GLYPH_HEIGHT};
+use jpeg_decoder::{Decoder, PixelFormat};
+use jpeg_encoder::{ColorType, Encoder};
+
+/// An RGB canvas
+#[derive(Debug, Clone)]
+pub struct Canvas {
+    /// The width in pixels
+    width: usize,
+    /// The height in pixels
+    height: usize,
+    /// The packed RGB pixels
+    pixels: Vec<u8>,
+}
+impl Canvas {
+    /// Creates a new canvas filled with the given color
+    pub fn new(width: usize, height: usize, color: [u8; 3]) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        let pixels = color.repeat(width.saturating_mul(height));
+        Self { width, height, pixels }
+    }
+
+    /// Decodes the given JPEG
+    ///
+    /// # Note
+    /// If a size hint is given, the JPEG is decoded at the smallest DCT scale (1/8, 1/4, 1/2 or 1) that still covers
+    /// the hinted size, which is much faster than decoding the full image and scaling it down afterwards.
+    pub fn from_jpeg(jpeg: &[u8], hint: Option<(usize, usize)>) -> Result<Self, Error> {
+        // Read the header and select the DCT scale
+        let mut decoder = Decoder::new(jpeg);
+        if let Some((width, height)) = hint {
+            let (width, height) = (u16::try_from(width).unwrap_or(u16::MAX), u16::try_from(height).unwrap_or(u16::MAX));
+            decoder.scale(width, height)?;
+        }
+
+        // Decode the pixels and convert them to RGB
+        let pixels = decoder.decode()?;
+        let info = decoder.info().ok_or_else(|| error!("Missing JPEG header"))?;
+        let pixels = match info.pixel_format {
+            PixelFormat::RGB24 => pixels,
+            PixelFormat::L8 => pixels.into_iter().flat_map(|luma| [luma; 3]).collect(),
+            format => return Err(error!("Unsupported JPEG pixel format: {format:?}")),
+        };
+        Ok(Self { width: usize::from(info.width), height: usize::from(info.height), pixels })
+    }
+
+    /// The width in pixels
+    pub const fn width(&self) -> usize {
+        self.width
+    }
+
+    /// The height in pixels
+    pub const fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Scales the canvas to the given size by averaging the covered source pixels
+    pub fn resized(&self, width: usize, height: usize) -> Self {
+        // Map each target row and column to its range of source rows and columns
+        let (width, height) = (width.max(1), height.max(1));
+        let span = |index: usize, target: usize, source: usize| {
+            let start = index.saturating_mul(source).checked_div(target).unwrap_or_default();
+            let end = index.saturating_add(1).saturating_mul(source).div_ceil(target);
+            (start, end.max(start.saturating_add(1)).min(source))
+        };
+
+        // Average the source pixels for each target pixel
+        let mut pixels = Vec::with_capacity(width.saturating_mul(height).saturating_mul(3));
+        for y in 0..height {
+            let (y_start, y_end) = span(y, height, self.height);
+            for x in 0..width {
+                let (x_start, x_end) = span(x, width, self.width);
+                let (mut sum, mut count) = ([0u64; 3], 0u64);
+                for row in self.pixels.chunks_exact(self.width.saturating_mul(3)).take(y_end).skip(y_start) {
+                    for pixel in row.chunks_exact(3).take(x_end).skip(x_start) {
+                        for (sum, channel) in sum.iter_mut().zip(pixel) {
+                            *sum = sum.saturating_add(u64::from(*channel));
+                        }
+                        count = count.saturating_add(1);
+                    }
+                }
+                pixels
+                    .extend(sum.map(|sum| u8::try_from(sum.checked_div(count).unwrap_or_default()).unwrap_or(u8::MAX)));
+            }
+        }
+        Self { width, height, pixels }
+    }
+
+    /// Draws the given canvas with its top-left corner at `x`/`y`; the drawn canvas is clipped to this canvas
+    pub fn draw(&mut self, x: usize, y: usize, canvas: &Self) {
+        let width = canvas.width.min(self.width.saturating_sub(x));
+        let (stride, source_stride) = (self.width.saturating_mul(3), canvas.width.saturating_mul(3));
+        let rows = self.pixels.chunks_exact_mut(stride).skip(y).zip(canvas.pixels.chunks_exact(source_stride));
+        for (row, source_row) in rows {
+            let target = row.iter_mut().skip(x.saturating_mul(3)).take(width.saturating_mul(3));
+            for (pixel, source) in target.zip(source_row) {
+                *pixel = *source;
+            }
+        }
+    }
+
+    /// Crops the canvas to the given rectangle; the rectangle is clipped to the canvas
+    pub fn cropped(&self, x: usize, y: usize, width: usize, height: usize) -> Self {
+        // Clip the rectangle
+        let (x, y) = (x.min(self.width.saturating_sub(1)), y.min(self.height.saturating_sub(1)));
+        let width = width.min(self.width.saturating_sub(x)).max(1);
+        let height = height.min(self.height.saturating_sub(y)).max(1);
+
+        // Copy the pixels within the rectangle
+        let mut pixels = Vec::with_capacity(width.saturating_mul(height).saturating_mul(3));
+        for row in self.pixels.chunks_exact(self.width.saturating_mul(3)).skip(y).take(height) {
+            pixels.extend(row.iter().skip(x.saturating_mul(3)).take(width.saturating_mul(3)));
+        }
+        Self { width, height, pixels }
+    }
+
+    /// Rotates the canvas clockwise by the given amount of quarter turns and mirrors it horizontally afterwards
+    ///
+    /// # Note
+    /// This only remaps the pixels, so the image is not resampled.
+    pub fn oriented(&self, quarter_turns: u8, mirror: bool) -> Self {
+        // Swap the width and height for odd quarter turns
+        let (width, height) = match quarter_turns % 2 {
+            1 => (self.height, self.width),
+            _ => (self.width, self.height),
+        };
+
+        // Copy each target pixel from its source position
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for y in 0..height {
+            for x in 0..width {
+                // Undo the mirroring, then the rotation
+                let x = match mirror {
+                    true => width.saturating_sub(1).saturating_sub(x),
+                    false => x,
+                };
+                let (source_x, source_y) = match quarter_turns % 4 {
+                    1 => (y, self.height.saturating_sub(1).saturating_sub(x)),
+                    2 => (
+                        self.width.saturating_sub(1).saturating_sub(x),
+                        self.height.saturating_sub(1).saturating_sub(y),
+                    ),
+                    3 => (self.width.saturating_sub(1).saturating_sub(y), x),
+                    _ => (x, y),
+                };
+                let index = source_y.saturating_mul(self.width).saturating_add(source_x).saturating_mul(3);
+                pixels.extend_from_slice(self.pixels.get(index..index.saturating_add(3)).unwrap_or(&[0; 3]));
+            }
+        }
+        Self { width, height, pixels }
+    }
+
+    /// Maps each channel value via the given lookup table
+    pub fn map_channels(&mut self, table: &[u8; 256]) {
+        for channel in &mut self.pixels {
+            *channel = table.get(usize::from(*channel)).copied().unwrap_or(*channel);
+        }
+    }
+
+    /// Fills the given rectangle; the rectangle is clipped to the canvas
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 3]) {
+        let x_end = x.saturating_add(width).min(self.width);
+        let y_end = y.saturating_add(height).min(self.height);
+        for row in self.pixels.chunks_exact_mut(self.width.saturating_mul(3)).take(y_end).skip(y) {
+            for pixel in row.chunks_exact_mut(3).take(x_end).skip(x) {
+                pixel.copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// Renders the given text with the top-left corner at `x`/`y`; each font pixel is scaled to `scale` pixels
+    pub fn text(&mut self, x: usize, y: usize, scale: usize, color: [u8; 3], text: &str) {
+        let advance = font::GLYPH_ADVANCE.saturating_mul(scale);
+        for (index, char_) in text.chars().enumerate() {
+            let glyph_x = x.saturating_add(index.saturating_mul(advance));
+            for (row, bits) in font::glyph(char_).into_iter().enumerate() {
+                for column in (0..font::GLYPH_WIDTH).filter(|column| bits & (0x10 >> column) != 0) {
+                    let pixel_x = glyph_x.saturating_add(column.saturating_mul(scale));
+                    let pixel_y = y.saturating_add(row.saturating_mul(scale));
+                    self.fill_rect(pixel_x, pixel_y, scale, scale, color);
+                }
+            }
+        }
+    }
+
+    /// The width and height of the given text in pixels
+    pub fn text_size(text: &str, scale: usize) -> (usize, usize) {
+        let width = text.chars().count().saturating_mul(font::GLYPH_ADVANCE).saturating_mul(scale);
+        (width, GLYPH_HEIGHT.saturating_mul(scale))
+    }
+
+    /// Encodes the canvas as JPEG with the given quality (`1..=100`)
+    pub fn to_jpeg(&self, quality: u8) -> Result<Vec<u8>, Error> {
+        let (width, height) = (u16::try_from(self.width)?, u16::try_from(self.height)?);
+        let mut jpeg = Vec::new();
+        Encoder::new(&mut jpeg, quality).encode(&self.pixels, width, height, ColorType::Rgb)?;
+        Ok(jpeg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a canvas where each pixel holds its own coordinates as red and green channel
+    fn labelled(width: u8, height: u8) -> Canvas {
+        let pixels = (0..height).flat_map(|y| (0..width).flat_map(move |x| [x, y, 0])).collect();
+        Canvas { width: usize::from(width), height: usize::from(height), pixels }
+    }
+
+    /// Gets the source coordinates of each pixel row by row
+    fn labels(canvas: &Canvas) -> Vec<Vec<(u8, u8)>> {
+        let rows = canvas.pixels.chunks_exact(canvas.width.saturating_mul(3));
+        rows.map(|row| row.chunks_exact(3).map(|pixel| (pixel[0], pixel[1])).collect()).collect()
+    }
+
+    /// The source coordinates of the pixels of a canvas, row by row
+    type Labels<'a> = &'a [&'a [(u8, u8)]];
+
+    /// Rotations and mirroring map each target pixel to the expected source pixel
+    #[test]
+    fn oriented() {
+        let canvas = labelled(3, 2);
+        let cases: [(u8, bool, Labels); 8] = [
+            (0, false, &[&[(0, 0), (1, 0), (2, 0)], &[(0, 1), (1, 1), (2, 1)]]),
+            (1, false, &[&[(0, 1), (0, 0)], &[(1, 1), (1, 0)], &[(2, 1), (2, 0)]]),
+            (2, false, &[&[(2, 1), (1, 1), (0, 1)], &[(2, 0), (1, 0), (0, 0)]]),
+            (3, false, &[&[(2, 0), (2, 1)], &[(1, 0), (1, 1)], &[(0, 0), (0, 1)]]),
+            (0, true, &[&[(2, 0), (1, 0), (0, 0)], &[(2, 1), (1, 1), (0, 1)]]),
+            (1, true, &[&[(0, 0), (0, 1)], &[(1, 0), (1, 1)], &[(2, 0), (2, 1)]]),
+            (2, true, &[&[(0, 1), (1, 1), (2, 1)], &[(0, 0), (1, 0), (2, 0)]]),
+            (3, true, &[&[(2, 1), (2, 0)], &[(1, 1), (1, 0)], &[(0, 1), (0, 0)]]),
+        ];
+        for (quarter_turns, mirror, expected) in cases {
+            let oriented = canvas.oriented(quarter_turns, mirror);
+            assert_eq!((oriented.width, oriented.height), (expected[0].len(), expected.len()));
+            assert_eq!(labels(&oriented), expected, "{quarter_turns} quarter turns, mirror {mirror}");
+        }
+    }
+
+    /// Crops copy the pixels within the rectangle, which is clipped to the canvas
+    #[test]
+    fn cropped() {
+        let canvas = labelled(4, 3);
+        assert_eq!(labels(&canvas.cropped(1, 1, 2, 2)), [[(1, 1), (2, 1)], [(1, 2), (2, 2)]]);
+        assert_eq!(labels(&canvas.cropped(2, 0, 10, 1)), [[(2, 0), (3, 0)]]);
+        assert_eq!(labels(&canvas.cropped(3, 2, 5, 5)), [[(3, 2)]]);
+        assert_eq!(labels(&canvas.cropped(10, 10, 2, 2)), [[(3, 2)]]);
+        assert_eq!(labels(&canvas.cropped(0, 0, 0, 0)), [[(0, 0)]]);
+    }
+}
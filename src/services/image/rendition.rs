@@ -0,0 +1,167 @@
+//! Transformed, scaled and re-encoded renditions of JPEG frames
+
+use crate::{
+    error,
+    error::Error,
+    services::{camera::Frame, image::transform::Transform},
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    thread::{self, Builder},
+    time::Instant,
+};
+
+/// The transform, size and quality of a rendition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Rendition {
+    /// The maximum width, if any
+    width: Option<usize>,
+    /// The maximum height, if any
+    height: Option<usize>,
+    /// The JPEG quality (`1..=100`), if given
+    quality: Option<u8>,
+    /// The transform that is applied before the image is scaled
+    transform: Transform,
+}
+impl Rendition {
+    /// The default JPEG quality if only the size is given
+    pub const DEFAULT_QUALITY: u8 = 80;
+    /// The minimum width and height
+    const MIN_SIZE: usize = 16;
+    /// The maximum width and height
+    const MAX_SIZE: usize = 3840;
+
+    /// Creates a new rendition, or returns `None` if neither a size nor a quality nor a transform is given
+    ///
+    /// # Note
+    /// The transformed image is scaled to fit into the given width and/or height while keeping its aspect ratio; it is
+    /// never scaled up.
+    pub fn new(
+        width: Option<usize>,
+        height: Option<usize>,
+        quality: Option<u8>,
+        transform: Transform,
+    ) -> Result<Option<Self>, Error> {
+        // Validate the size and quality
+        for size in [width, height].into_iter().flatten() {
+            if !(Self::MIN_SIZE..=Self::MAX_SIZE).contains(&size) {
+                return Err(error!("Invalid rendition size: {size}"));
+            }
+        }
+        if let Some(quality) = quality.filter(|quality| !(1..=100).contains(quality)) {
+            return Err(error!("Invalid rendition quality: {quality}"));
+        }
+
+        // Create the rendition
+        if width.is_none() && height.is_none() && quality.is_none() && transform.is_identity() {
+            return Ok(None);
+        }
+        Ok(Some(Self { width, height, quality, transform }))
+    }
+
+    /// Renders the given JPEG
+    ///
+    /// # Note
+    /// Renditions that only rotate and/or mirror are applied losslessly if possible (see
+    /// [`Transform::orient_lossless`]). Otherwise, the JPEG codecs need more stack than the HTTP worker threads provide,
+    /// so the JPEG is rendered on a separate thread.
+    pub fn render(&self, jpeg: &[u8]) -> Result<Vec<u8>, Error> {
+        /// The stack size of the render thread (the default stack size of Rust threads)
+        const STACK_SIZE: usize = 2 * 1024 * 1024;
+
+        // Orient the JPEG losslessly if it is neither scaled nor re-encoded with a given quality
+        if (self.width, self.height, self.quality) == (None, None, None) {
+            if let Some(oriented) = self.transform.orient_lossless(jpeg) {
+                return Ok(oriented);
+            }
+        }
+
+        thread::scope(|scope| {
+            let builder = Builder::new().name("rendition".to_string()).stack_size(STACK_SIZE);
+            let thread = builder.spawn_scoped(scope, || self.render_inline(jpeg))?;
+            thread.join().map_err(|_| error!("Failed to render rendition"))?
+        })
+    }
+
+    /// Renders the given JPEG on the current thread
+    fn render_inline(&self, jpeg: &[u8]) -> Result<Vec<u8>, Error> {
+        // Decode the JPEG at the smallest scale that still covers the target size and transform it
+        let hint = match (self.width, self.height) {
+            (None, None) => None,
+            (width, height) => Some((width.unwrap_or(usize::MAX), height.unwrap_or(usize::MAX))),
+        };
+        let canvas = self.transform.decode(jpeg, hint)?;
+
+        // Fit the image into the target size without scaling it up
+        let (width, height) = (canvas.width(), canvas.height());
+        let (max_width, max_height) =
+            (self.width.unwrap_or(width).min(width), self.height.unwrap_or(height).min(height));
+        let (target_width, target_height) = match width.saturating_mul(max_height) <= max_width.saturating_mul(height) {
+            true => (width.saturating_mul(max_height).checked_div(height).unwrap_or(max_width), max_height),
+            false => (max_width, height.saturating_mul(max_width).checked_div(width).unwrap_or(max_height)),
+        };
+
+        // Scale and encode the image
+        let canvas = match (target_width, target_height) == (width, height) {
+            true => canvas,
+            false => canvas.resized(target_width, target_height),
+        };
+        canvas.to_jpeg(self.quality.unwrap_or(Self::DEFAULT_QUALITY))
+    }
+}
+
+/// A cached rendition of the most recent frame
+#[derive(Debug)]
+struct CacheEntry {
+    /// The data of the source frame
+    source: Arc<Vec<u8>>,
+    /// The rendered frame
+    frame: Frame,
+}
+
+/// The cache entry of a rendition, which is locked while the rendition is rendered
+type CacheSlot = Arc<Mutex<Option<CacheEntry>>>;
+
+/// A cache that holds the renditions of the most recent frame, so that concurrent consumers share a single encode
+#[derive(Debug, Default)]
+pub struct RenditionCache {
+    /// The cache entry and the last access for each rendition
+    entries: Mutex<BTreeMap<Rendition, (CacheSlot, Instant)>>,
+}
+impl RenditionCache {
+    /// The maximum amount of cached renditions
+    const MAX_ENTRIES: usize = 16;
+
+    /// Gets the given rendition of the given frame, rendering it if it is not cached yet
+    pub fn get(&self, frame: &Frame, rendition: &Rendition) -> Result<Frame, Error> {
+        // Get the entry for the rendition and release the cache, so that other renditions can be rendered in parallel
+        let entry = {
+            let mut entries = self.entries();
+            let (entry, last_access) = entries.entry(*rendition).or_insert_with(|| (Arc::default(), Instant::now()));
+            *last_access = Instant::now();
+            let entry = entry.clone();
+
+            // Evict the least recently used rendition
+            if entries.len() > Self::MAX_ENTRIES {
+                let oldest = entries.iter().min_by_key(|(_, (_, last_access))| *last_access).map(|(key, _)| *key);
+                entries.retain(|key, _| Some(*key) != oldest);
+            }
+            entry
+        };
+
+        // Render the frame unless it has already been rendered; concurrent consumers wait for the first one
+        let mut entry = entry.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(cached) = entry.as_ref().filter(|cached| Arc::ptr_eq(&cached.source, &frame.data)) {
+            return Ok(cached.frame.clone());
+        }
+        let rendered = Frame::jpeg(rendition.render(&frame.data)?);
+        *entry = Some(CacheEntry { source: frame.data.clone(), frame: rendered.clone() });
+        Ok(rendered)
+    }
+
+    /// Locks the cache entries
+    fn entries(&self) -> MutexGuard<'_, BTreeMap<Rendition, (CacheSlot, Instant)>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,369 @@
+//! Per-device image transforms, e.g. to straighten a tilted camera or to brighten a dark chamber
+//!
+//! The transforms are applied in a fixed order: crop (in the coordinates of the original frame), rotate, mirror and
+//! finally the tone adjustments (contrast, brightness and gamma).
+//!
+//! # Note
+//! Transforms that only rotate and/or mirror are applied losslessly via an EXIF orientation tag if the frame is not
+//! scaled or re-encoded anyway (see [`Transform::orient_lossless`]); all other transforms are applied to the decoded
+//! pixels, so the frame is re-encoded.
+
+use crate::{error, error::Error, json, services::image::Canvas};
+
+/// A crop rectangle in pixels of the original frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Crop {
+    /// The left edge
+    pub x: usize,
+    /// The top edge
+    pub y: usize,
+    /// The width
+    pub width: usize,
+    /// The height
+    pub height: usize,
+}
+
+/// The transforms of a frame
+///
+/// # Note
+/// The tone adjustments are stored in thousandths, so that transforms can be compared and used as cache keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Transform {
+    /// The clockwise rotation in quarter turns (`0..=3`)
+    quarter_turns: u8,
+    /// Whether to mirror the frame horizontally after the rotation
+    mirror: bool,
+    /// The crop rectangle, if any
+    crop: Option<Crop>,
+    /// The brightness offset relative to the full range in thousandths (`-1000..=1000`)
+    brightness: i32,
+    /// The contrast factor in thousandths (`0..=4000`)
+    contrast: i32,
+    /// The gamma in thousandths (`100..=10000`)
+    gamma: i32,
+}
+impl Transform {
+    /// The transform that leaves frames unchanged
+    pub const IDENTITY: Self =
+        Self { quarter_turns: 0, mirror: false, crop: None, brightness: 0, contrast: 1000, gamma: 1000 };
+    /// The names of the transform fields
+    pub const FIELDS: [&'static str; 6] = ["rotate", "mirror", "crop", "brightness", "contrast", "gamma"];
+
+    /// Whether the transform leaves frames unchanged
+    pub fn is_identity(&self) -> bool {
+        *self == Self::IDENTITY
+    }
+
+    /// Sets the transform field with the given name
+    ///
+    /// # Note
+    /// `rotate` is one of `0`, `90`, `180` or `270` degrees clockwise, `mirror` is `true` or `false`, `crop` is
+    /// `<x>,<y>,<width>,<height>` or `none`, `brightness` is an offset between `-1` and `1`, `contrast` a factor between
+    /// `0` and `4`, and `gamma` is between `0.1` and `10`, where values above `1` brighten the midtones.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        match name {
+            "rotate" => {
+                self.quarter_turns = match value {
+                    "0" => 0,
+                    "90" => 1,
+                    "180" => 2,
+                    "270" => 3,
+                    _ => return Err(error!("Invalid rotate value {value:?}; expected 0, 90, 180 or 270")),
+                }
+            }
+            "mirror" => {
+                self.mirror = value.parse().map_err(|e| error!(with: e, "Invalid mirror value {value:?}"))?;
+            }
+            "crop" if value == "none" => self.crop = None,
+            "crop" => {
+                let values: Vec<usize> = value.split(',').map(str::parse).collect::<Result<_, _>>().unwrap_or_default();
+                let [x, y, width, height] = values.as_slice() else {
+                    return Err(error!("Invalid crop value {value:?}; expected <x>,<y>,<width>,<height>"));
+                };
+                if *width == 0 || *height == 0 {
+                    return Err(error!("Invalid crop value {value:?}; the crop rectangle must not be empty"));
+                }
+                self.crop = Some(Crop { x: *x, y: *y, width: *width, height: *height });
+            }
+            "brightness" => self.brightness = Self::parse_thousandths(name, value, -1000..=1000)?,
+            "contrast" => self.contrast = Self::parse_thousandths(name, value, 0..=4000)?,
+            "gamma" => self.gamma = Self::parse_thousandths(name, value, 100..=10000)?,
+            name => return Err(error!("Unknown transform field {name}")),
+        }
+        Ok(())
+    }
+
+    /// Parses a decimal value into thousandths within the given range
+    fn parse_thousandths(name: &str, value: &str, range: std::ops::RangeInclusive<i32>) -> Result<i32, Error> {
+        let decimal: f64 = value.parse().map_err(|e| error!(with: e, "Invalid {name} value {value:?}"))?;
+        let thousandths = (decimal * 1000.0).round();
+        if !decimal.is_finite() || !(f64::from(*range.start())..=f64::from(*range.end())).contains(&thousandths) {
+            return Err(error!(
+                "Invalid {name} value {value:?}; expected {} to {}",
+                f64::from(*range.start()) / 1000.0,
+                f64::from(*range.end()) / 1000.0
+            ));
+        }
+        Ok(thousandths as i32)
+    }
+
+    /// The fields that differ from the identity, e.g. to render them into the config
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = Vec::new();
+        if self.quarter_turns != 0 {
+            fields.push(("rotate", u16::from(self.quarter_turns).saturating_mul(90).to_string()));
+        }
+        if self.mirror {
+            fields.push(("mirror", "true".to_string()));
+        }
+        if let Some(Crop { x, y, width, height }) = self.crop {
+            fields.push(("crop", format!("{x},{y},{width},{height}")));
+        }
+        let thousandths =
+            [("brightness", self.brightness, 0), ("contrast", self.contrast, 1000), ("gamma", self.gamma, 1000)];
+        for (name, value, _) in thousandths.into_iter().filter(|(_, value, neutral)| value != neutral) {
+            fields.push((name, (f64::from(value) / 1000.0).to_string()));
+        }
+        fields
+    }
+
+    /// Encodes the fields that differ from the identity as JSON object
+    pub fn to_json(self) -> String {
+        // All fields but the crop rectangle are numbers or booleans
+        let object = self.fields().into_iter().fold(json::Object::new(), |object, (name, value)| match name {
+            "crop" => object.string(name, &value),
+            name => object.raw(name, &value),
+        });
+        object.finish()
+    }
+
+    /// The EXIF orientation that displays the original frame like this transform, if it only rotates and/or mirrors
+    fn exif_orientation(&self) -> Option<u16> {
+        // Crops and tone adjustments require the decoded pixels
+        let orientation = Self { quarter_turns: self.quarter_turns, mirror: self.mirror, ..Self::IDENTITY };
+        if *self != orientation {
+            return None;
+        }
+
+        // EXIF mirrors before it rotates, so a mirrored rotation by `n` turns is a rotation by `-n` turns in EXIF terms
+        match (self.quarter_turns, self.mirror) {
+            (0, true) => Some(2),
+            (2, false) => Some(3),
+            (2, true) => Some(4),
+            (1, true) => Some(5),
+            (1, false) => Some(6),
+            (3, true) => Some(7),
+            (3, false) => Some(8),
+            _ => None,
+        }
+    }
+
+    /// Orients the given JPEG losslessly via an EXIF orientation tag, or returns `None` if this is not possible
+    ///
+    /// # Note
+    /// The image data is passed through unchanged and viewers (e.g. all current browsers) apply the orientation when
+    /// they display the JPEG. This is not possible if the transform also crops or adjusts tones, or if the JPEG already
+    /// has EXIF data, since its orientation would have to be merged.
+    pub fn orient_lossless(&self, jpeg: &[u8]) -> Option<Vec<u8>> {
+        /// The JFIF marker
+        const APP0: u8 = 0xE0;
+        /// The EXIF marker
+        const APP1: u8 = 0xE1;
+        /// The start-of-scan marker, which ends the header segments
+        const SOS: u8 = 0xDA;
+
+        // Find the first segment after the JFIF segment, and ensure that there is no EXIF segment
+        let orientation = self.exif_orientation()?;
+        if !jpeg.starts_with(&[0xFF, 0xD8]) {
+            return None;
+        }
+        let (mut offset, mut insert_at) = (2, None);
+        loop {
+            let [0xFF, marker, high, low, ..] = *jpeg.get(offset..)? else {
+                return None;
+            };
+            if marker == SOS {
+                break;
+            }
+            let end = offset.checked_add(2)?.checked_add(usize::from(u16::from_be_bytes([high, low])))?;
+            if marker == APP1 && jpeg.get(offset.checked_add(4)?..end)?.starts_with(b"Exif\0\0") {
+                return None;
+            }
+            insert_at = insert_at.or(Some(offset).filter(|_| marker != APP0));
+            offset = end;
+        }
+
+        // Insert an EXIF segment with a single orientation tag into big-endian TIFF data
+        let mut exif = vec![0xFF, APP1, 0x00, 0x22];
+        exif.extend_from_slice(b"Exif\0\0MM\x00\x2A\x00\x00\x00\x08");
+        exif.extend_from_slice(&[0x00, 0x01, 0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01]);
+        exif.extend_from_slice(&orientation.to_be_bytes());
+        exif.extend_from_slice(&[0x00; 6]);
+        let (head, tail) = jpeg.split_at_checked(insert_at.unwrap_or(offset))?;
+        Some([head, &exif, tail].concat())
+    }
+
+    /// Decodes the given JPEG and applies the transform
+    ///
+    /// # Note
+    /// The size hint refers to the transformed frame (see [`Canvas::from_jpeg`]); it is ignored if the frame is cropped,
+    /// since the crop rectangle refers to the full-size frame.
+    pub fn decode(&self, jpeg: &[u8], hint: Option<(usize, usize)>) -> Result<Canvas, Error> {
+        let hint = match (self.crop, hint) {
+            (Some(_), _) | (_, None) => None,
+            (None, Some((width, height))) if self.quarter_turns % 2 == 1 => Some((height, width)),
+            (None, hint) => hint,
+        };
+        let canvas = Canvas::from_jpeg(jpeg, hint)?;
+        Ok(self.apply(canvas))
+    }
+
+    /// Applies the transform to the given canvas
+    pub fn apply(&self, canvas: Canvas) -> Canvas {
+        // Crop and orient the canvas
+        let canvas = match self.crop {
+            Some(Crop { x, y, width, height }) => canvas.cropped(x, y, width, height),
+            None => canvas,
+        };
+        let mut canvas = match (self.quarter_turns, self.mirror) {
+            (0, false) => canvas,
+            (quarter_turns, mirror) => canvas.oriented(quarter_turns, mirror),
+        };
+
+        // Adjust the tones via a lookup table
+        if (self.brightness, self.contrast, self.gamma) != (0, 1000, 1000) {
+            let (brightness, contrast) = (f64::from(self.brightness) / 1000.0, f64::from(self.contrast) / 1000.0);
+            let exponent = 1000.0 / f64::from(self.gamma);
+            let mut table = [0; 256];
+            for (value, mapped) in (0..=u8::MAX).zip(table.iter_mut()) {
+                let value = (f64::from(value) / 255.0 - 0.5) * contrast + 0.5 + brightness;
+                *mapped = (value.clamp(0.0, 1.0).powf(exponent) * 255.0).round() as u8;
+            }
+            canvas.map_channels(&table);
+        }
+        canvas
+    }
+}
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a transform with the given fields
+    fn transform(fields: &[(&str, &str)]) -> Result<Transform, Error> {
+        let mut transform = Transform::IDENTITY;
+        for (name, value) in fields {
+            transform.set(name, value)?;
+        }
+        Ok(transform)
+    }
+
+    /// Valid values are parsed into the transform
+    #[test]
+    fn set() {
+        for (value, quarter_turns) in [("0", 0), ("90", 1), ("180", 2), ("270", 3)] {
+            assert_eq!(transform(&[("rotate", value)]).unwrap().quarter_turns, quarter_turns);
+        }
+        assert!(transform(&[("mirror", "true")]).unwrap().mirror);
+        assert!(!transform(&[("mirror", "true"), ("mirror", "false")]).unwrap().mirror);
+
+        let crop = transform(&[("crop", "0,40,1280,640")]).unwrap().crop;
+        assert_eq!(crop, Some(Crop { x: 0, y: 40, width: 1280, height: 640 }));
+        assert_eq!(transform(&[("crop", "0,40,1280,640"), ("crop", "none")]).unwrap().crop, None);
+
+        let tones = transform(&[("brightness", "-0.25"), ("contrast", "1.2"), ("gamma", "0.1")]).unwrap();
+        assert_eq!((tones.brightness, tones.contrast, tones.gamma), (-250, 1200, 100));
+        assert!(transform(&[("brightness", "0"), ("contrast", "1"), ("gamma", "1")]).unwrap().is_identity());
+    }
+
+    /// Invalid values and unknown fields are rejected
+    #[test]
+    fn set_invalid() {
+        let invalid = [
+            ("rotate", "45"),
+            ("rotate", "-90"),
+            ("rotate", "360"),
+            ("mirror", "yes"),
+            ("crop", "1,2,3"),
+            ("crop", "1,2,3,4,5"),
+            ("crop", "0,0,0,5"),
+            ("crop", "0,0,5,0"),
+            ("crop", "a,b,c,d"),
+            ("crop", "-1,0,5,5"),
+            ("brightness", "1.5"),
+            ("brightness", "NaN"),
+            ("contrast", "-0.1"),
+            ("contrast", "inf"),
+            ("gamma", "0.05"),
+            ("gamma", "10.1"),
+            ("scale", "2"),
+        ];
+        for (name, value) in invalid {
+            assert!(transform(&[(name, value)]).is_err(), "{name}={value}");
+        }
+    }
+
+    /// The rendered fields parse back into the same transform
+    #[test]
+    fn fields_round_trip() {
+        let fields =
+            [("rotate", "270"), ("mirror", "true"), ("crop", "1,2,3,4"), ("brightness", "0.1"), ("gamma", "1.4")];
+        let transform_ = transform(&fields).unwrap();
+        let rendered = transform_.fields();
+        assert_eq!(rendered.iter().map(|(name, value)| (*name, value.as_str())).collect::<Vec<_>>(), fields);
+        assert!(Transform::IDENTITY.fields().is_empty());
+        assert_eq!(Transform::IDENTITY.to_json(), "{}");
+    }
+
+    /// The transform crops in the coordinates of the original frame before it rotates and mirrors
+    #[test]
+    fn apply() {
+        let canvas = Canvas::new(4, 2, [0; 3]);
+        let applied = transform(&[("crop", "1,0,3,2"), ("rotate", "90")]).unwrap().apply(canvas.clone());
+        assert_eq!((applied.width(), applied.height()), (2, 3));
+        assert_eq!(Transform::IDENTITY.apply(canvas).width(), 4);
+
+        let (dark, bright) = (Canvas::new(1, 1, [0; 3]), transform(&[("brightness", "1")]).unwrap());
+        assert_eq!(bright.apply(dark).pixels, [255; 3]);
+    }
+
+    /// Pure rotations and mirroring only add an EXIF orientation tag and keep the image data
+    #[test]
+    fn orient_lossless() {
+        let jpeg = Canvas::new(32, 16, [200, 100, 50]).to_jpeg(80).unwrap();
+        let mut orientations = Vec::new();
+        for rotate in ["0", "90", "180", "270"] {
+            for mirror in ["false", "true"] {
+                let Some(oriented) =
+                    transform(&[("rotate", rotate), ("mirror", mirror)]).unwrap().orient_lossless(&jpeg)
+                else {
+                    continue;
+                };
+
+                // Removing the EXIF segment restores the original JPEG
+                let exif = oriented.windows(6).position(|window| window == b"Exif\0\0").unwrap();
+                orientations.push(u16::from_be_bytes([oriented[exif + 24], oriented[exif + 25]]));
+                assert_eq!([&oriented[..exif - 4], &oriented[exif + 32..]].concat(), jpeg);
+                let canvas = Canvas::from_jpeg(&oriented, None).unwrap();
+                assert_eq!((canvas.width(), canvas.height()), (32, 16));
+
+                // The orientation is not merged with existing EXIF data
+                assert!(transform(&[("rotate", "90")]).unwrap().orient_lossless(&oriented).is_none());
+            }
+        }
+        orientations.sort_unstable();
+        assert_eq!(orientations, [2, 3, 4, 5, 6, 7, 8]);
+
+        // Other transforms require the decoded pixels
+        for fields in [&[("rotate", "90"), ("crop", "0,0,8,8")][..], &[("mirror", "true"), ("gamma", "2")]] {
+            assert!(transform(fields).unwrap().orient_lossless(&jpeg).is_none(), "{fields:?}");
+        }
+        assert!(Transform::IDENTITY.orient_lossless(&jpeg).is_none());
+        assert!(transform(&[("rotate", "90")]).unwrap().orient_lossless(b"GIF89a").is_none());
+    }
+}
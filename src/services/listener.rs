@@ -0,0 +1,585 @@
+//! The plain HTTP, HTTPS and Unix domain socket listeners
+
+use crate::{
+    error,
+    error::Error,
+    log::{self, Level},
+    services::{
+        shutdown,
+        tls::{ReloadingAcceptor, SharedStream},
+    },
+};
+use bamborvideostream_p1::{tls, ConnectOptions};
+use ehttpd::{
+    bytes::{Sink, Source},
+    Server,
+};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::{
+    cell::RefCell,
+    io::{self, BufReader, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// The timeout for the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+thread_local! {
+    /// The peer of the connection that has been read last on this thread
+    static PEER: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+}
+
+/// The peer address of the connection whose request is currently handled on this thread
+///
+/// # Note
+/// `ehttpd` does not pass the peer address to the handler, so the connection reader records it whenever the handler
+/// reads from it (see [`PeerReader`]); this is `-` if the current thread has not read from a connection yet.
+pub fn current_peer() -> Arc<str> {
+    PEER.with_borrow(|peer| peer.clone()).unwrap_or_else(|| Arc::from("-"))
+}
+
+/// A connection reader that records its peer address as current peer of the reading thread
+#[derive(Debug)]
+struct PeerReader<T> {
+    /// The underlying reader
+    inner: T,
+    /// The peer address
+    peer: Arc<str>,
+}
+impl<T> PeerReader<T> {
+    /// Wraps the given reader
+    fn new(inner: T, peer: &str) -> Self {
+        Self { inner, peer: Arc::from(peer) }
+    }
+}
+impl<T> Read for PeerReader<T>
+where
+    T: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        PEER.with_borrow_mut(|peer| {
+            // Only replace the peer if it has changed, since requests are read byte by byte
+            if !peer.as_ref().is_some_and(|peer| Arc::ptr_eq(peer, &self.peer)) {
+                *peer = Some(self.peer.clone());
+            }
+        });
+        self.inner.read(buf)
+    }
+}
+
+/// The authentication policy of a listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthPolicy {
+    /// Authed endpoints require the API key
+    ApiKey,
+    /// Authed endpoints are accessible without API key (e.g. for loopback or trusted LAN interfaces)
+    Trusted,
+    /// Authed and admin endpoints are accessible without API key (only for loopback or Unix domain socket listeners)
+    Admin,
+}
+
+/// The address of a listener
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenerAddress {
+    /// A plain HTTP listener on the given socket address
+    Http(String),
+    /// An HTTPS listener on the given socket address
+    Https(String),
+    /// A plain HTTP listener on the given Unix domain socket path
+    Unix(PathBuf),
+}
+impl ListenerAddress {
+    /// Whether the listener is only reachable from the local host, i.e. a loopback address or a Unix domain socket
+    pub fn is_local(&self) -> bool {
+        match self {
+            Self::Http(address) | Self::Https(address) => {
+                let is_localhost = address.rsplit_once(':').is_some_and(|(host, _)| host == "localhost");
+                is_localhost || address.parse::<SocketAddr>().is_ok_and(|address| address.ip().is_loopback())
+            }
+            Self::Unix(_) => true,
+        }
+    }
+}
+
+/// A listener configuration
+#[derive(Debug, Clone)]
+pub struct ListenerSpec {
+    /// The listener address
+    pub address: ListenerAddress,
+    /// The authentication policy
+    pub auth: AuthPolicy,
+    /// Whether to redirect all requests to the HTTPS listener
+    pub redirect: bool,
+}
+impl ListenerSpec {
+    /// Parses a comma-separated list of listener specs
+    ///
+    /// # Example
+    /// `http://127.0.0.1:8080?auth=none,https://[::]:443,unix:/run/bamborvideostream.sock?auth=none`
+    pub fn parse_list(specs: &str) -> Result<Vec<Self>, Error> {
+        let specs = specs.split(',').map(str::trim).filter(|spec| !spec.is_empty());
+        specs.map(Self::parse).collect()
+    }
+
+    /// Parses a listener spec like `http://[::]:80?redirect=https`, `https://[::]:443` or `unix:/path?auth=none`
+    ///
+    /// # Note
+    /// `auth=admin` also opens the admin API without API key, so it is only accepted for loopback addresses and Unix
+    /// domain sockets.
+    pub fn parse(spec: &str) -> Result<Self, Error> {
+        // Split the address and the options
+        let (address, options) = spec.split_once('?').unwrap_or((spec, ""));
+        let address = if let Some(address) = address.strip_prefix("http://") {
+            ListenerAddress::Http(address.to_string())
+        } else if let Some(address) = address.strip_prefix("https://") {
+            ListenerAddress::Https(address.to_string())
+        } else if let Some(path) = address.strip_prefix("unix://").or_else(|| address.strip_prefix("unix:")) {
+            ListenerAddress::Unix(PathBuf::from(path))
+        } else {
+            return Err(error!("Invalid listener address: {address}"));
+        };
+
+        // Parse the options
+        let mut this = Self { address, auth: AuthPolicy::ApiKey, redirect: false };
+        for option in options.split('&').filter(|option| !option.is_empty()) {
+            match option {
+                "auth=apikey" => this.auth = AuthPolicy::ApiKey,
+                "auth=none" => this.auth = AuthPolicy::Trusted,
+                "auth=admin" if this.address.is_local() => this.auth = AuthPolicy::Admin,
+                "auth=admin" => return Err(error!("Invalid listener option: auth=admin requires a loopback address")),
+                "redirect=https" if matches!(this.address, ListenerAddress::Http(_)) => this.redirect = true,
+                option => return Err(error!("Invalid listener option: {option}")),
+            }
+        }
+        Ok(this)
+    }
+}
+
+/// A bound listening socket
+#[derive(Debug)]
+pub enum Socket {
+    /// A TCP socket
+    Tcp(TcpListener),
+    /// A Unix domain socket
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+impl Socket {
+    /// Takes the matching socket from the inherited sockets or binds a new socket for the given address
+    pub fn take_or_bind(address: &ListenerAddress, inherited: &mut Vec<Self>) -> Result<Self, Error> {
+        // Reuse an inherited socket if possible
+        if let Some(index) = inherited.iter().position(|socket| socket.matches(address)) {
+            return Ok(inherited.swap_remove(index));
+        }
+
+        // Bind a new socket
+        match address {
+            ListenerAddress::Http(address) | ListenerAddress::Https(address) => {
+                Ok(Self::Tcp(TcpListener::bind(address)?))
+            }
+            #[cfg(unix)]
+            ListenerAddress::Unix(path) => {
+                use std::{fs, os::unix::fs::FileTypeExt};
+
+                // Remove a stale socket from a previous run
+                if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                    fs::remove_file(path)?;
+                }
+                Ok(Self::Unix(UnixListener::bind(path)?))
+            }
+            #[cfg(not(unix))]
+            ListenerAddress::Unix(path) => Err(error!("Unix domain sockets are not supported: {}", path.display())),
+        }
+    }
+
+    /// Whether the socket is bound to the given address
+    pub fn matches(&self, address: &ListenerAddress) -> bool {
+        match (self, address) {
+            (Self::Tcp(socket), ListenerAddress::Http(address) | ListenerAddress::Https(address)) => {
+                let Ok(local) = socket.local_addr() else {
+                    return false;
+                };
+                let mut addresses = address.to_socket_addrs().into_iter().flatten();
+                addresses.any(|address| address == local)
+            }
+            #[cfg(unix)]
+            (Self::Unix(socket), ListenerAddress::Unix(path)) => {
+                let local = socket.local_addr().ok();
+                local.as_ref().and_then(|local| local.as_pathname()) == Some(path.as_path())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A local connection probe for a listener to check its health or to wake up its accept loop
+#[derive(Debug, Clone)]
+pub enum Probe {
+    /// A plain HTTP listener that must answer a request
+    Http(SocketAddr),
+    /// An HTTPS listener that must complete a TLS handshake and answer a request
+    Https(SocketAddr),
+    /// A Unix domain socket listener that must answer a request
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+impl Probe {
+    /// The request to send to the listeners
+    const REQUEST: &[u8] = b"HEAD /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+    /// Creates a probe for the given listener
+    pub fn new(address: &ListenerAddress, socket: &Socket) -> Result<Self, Error> {
+        match socket {
+            Socket::Tcp(socket) => {
+                let local = Self::loopback(socket.local_addr()?);
+                match address {
+                    ListenerAddress::Https(_) => Ok(Self::Https(local)),
+                    _ => Ok(Self::Http(local)),
+                }
+            }
+            #[cfg(unix)]
+            Socket::Unix(socket) => {
+                let local = socket.local_addr()?;
+                let path = local.as_pathname().ok_or_else(|| error!("Cannot probe unnamed Unix domain socket"))?;
+                Ok(Self::Unix(path.to_path_buf()))
+            }
+        }
+    }
+
+    /// Creates a probe for the given listener address without a bound socket (e.g. from a separate process)
+    pub fn resolve(address: &ListenerAddress) -> Result<Self, Error> {
+        match address {
+            ListenerAddress::Http(sockaddr) | ListenerAddress::Https(sockaddr) => {
+                let mut resolved = sockaddr.to_socket_addrs()?;
+                let local = resolved.next().ok_or_else(|| error!("Cannot resolve listener address {sockaddr}"))?;
+                match address {
+                    ListenerAddress::Https(_) => Ok(Self::Https(Self::loopback(local))),
+                    _ => Ok(Self::Http(Self::loopback(local))),
+                }
+            }
+            #[cfg(unix)]
+            ListenerAddress::Unix(path) => Ok(Self::Unix(path.clone())),
+            #[cfg(not(unix))]
+            ListenerAddress::Unix(path) => Err(error!("Cannot probe Unix domain socket {}", path.display())),
+        }
+    }
+
+    /// Maps an unspecified address to loopback, so that listeners on all interfaces are probed via loopback
+    fn loopback(mut address: SocketAddr) -> SocketAddr {
+        match address.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => address.set_ip(Ipv4Addr::LOCALHOST.into()),
+            IpAddr::V6(ip) if ip.is_unspecified() => address.set_ip(Ipv6Addr::LOCALHOST.into()),
+            _ => (),
+        }
+        address
+    }
+
+    /// Checks whether the listener is healthy
+    pub fn check(&self, timeout: Duration) -> Result<(), Error> {
+        match self {
+            Self::Http(address) => {
+                let stream = TcpStream::connect_timeout(address, timeout)?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                Self::request(stream)
+            }
+            Self::Https(address) => {
+                // Perform a complete handshake, since the kernel accepts connections even if the listener hangs; any
+                // certificate is accepted since only the listener itself is checked
+                let stream = TcpStream::connect_timeout(address, timeout)?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                let stream = tls::connect(stream, "localhost", &ConnectOptions::default())?;
+                Self::request(stream)
+            }
+            #[cfg(unix)]
+            Self::Unix(path) => {
+                let stream = UnixStream::connect(path)?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                Self::request(stream)
+            }
+        }
+    }
+
+    /// Connects to the listener without sending a request to wake up its blocking accept loop
+    pub fn wake(&self, timeout: Duration) {
+        let _ = match self {
+            Self::Http(address) | Self::Https(address) => TcpStream::connect_timeout(address, timeout).map(drop),
+            #[cfg(unix)]
+            Self::Unix(path) => UnixStream::connect(path).map(drop),
+        };
+    }
+
+    /// Sends a request and checks whether the server responds with an HTTP response
+    fn request<T>(mut stream: T) -> Result<(), Error>
+    where
+        T: Read + Write,
+    {
+        stream.write_all(Self::REQUEST)?;
+        let mut status = [0; 7];
+        stream.read_exact(&mut status)?;
+        match &status {
+            b"HTTP/1." => Ok(()),
+            _ => Err(error!("Invalid health check response")),
+        }
+    }
+}
+
+/// Listens on the given socket and dispatches the connections to the server
+///
+/// # Note
+/// `connmax` limits the pending TLS handshakes of HTTPS listeners. This function returns `Ok` once a shutdown has been
+/// requested and the accept loop has been woken up (see [`Probe::wake`]); the socket is closed on return so that no new
+/// connections are accepted.
+pub fn serve<T>(
+    server: Arc<Server<T>>,
+    address: &ListenerAddress,
+    socket: Socket,
+    acceptor: Option<Arc<ReloadingAcceptor>>,
+    connmax: usize,
+) -> Result<(), Error>
+where
+    T: Fn(&mut Source, &mut Sink) -> bool + Clone + Send + Sync + 'static,
+{
+    match (address, socket) {
+        (ListenerAddress::Https(_), Socket::Tcp(socket)) => {
+            let acceptor = acceptor.ok_or_else(|| error!("HTTPS listener without TLS certificate"))?;
+            serve_tls(server, socket, acceptor, connmax)
+        }
+        (_, Socket::Tcp(socket)) => serve_plain(server, socket),
+        #[cfg(unix)]
+        (_, Socket::Unix(socket)) => serve_unix(server, socket),
+    }
+}
+
+/// Listens for plain HTTP connections on the given socket and dispatches them to the server
+fn serve_plain<T>(server: Arc<Server<T>>, socket: TcpListener) -> Result<(), Error>
+where
+    T: Fn(&mut Source, &mut Sink) -> bool + Clone + Send + Sync + 'static,
+{
+    loop {
+        // Accept and prepare connection
+        let (stream, peer) = socket.accept()?;
+        if shutdown::is_requested() {
+            return Ok(());
+        }
+        let tx = stream.try_clone()?;
+        let rx = PeerReader::new(BufReader::new(stream), &peer.to_string());
+
+        // Dispatch connection
+        let rx = Source::from_other(rx);
+        server.dispatch(rx, tx.into())?;
+    }
+}
+
+/// Listens for plain HTTP connections on the given Unix domain socket and dispatches them to the server
+#[cfg(unix)]
+fn serve_unix<T>(server: Arc<Server<T>>, socket: UnixListener) -> Result<(), Error>
+where
+    T: Fn(&mut Source, &mut Sink) -> bool + Clone + Send + Sync + 'static,
+{
+    loop {
+        // Accept and prepare connection
+        let (stream, _) = socket.accept()?;
+        if shutdown::is_requested() {
+            return Ok(());
+        }
+        let tx = stream.try_clone()?;
+        let rx = PeerReader::new(BufReader::new(stream), "unix");
+
+        // Dispatch connection
+        server.dispatch(Source::from_other(rx), Sink::from_other(tx))?;
+    }
+}
+
+/// Listens for HTTPS connections on the given socket and dispatches them to the server
+///
+/// # Note
+/// The handshakes are performed in separate threads to not block the listener; like the connections themselves, the
+/// amount of pending handshakes is limited to `connmax`, and excess connections are dropped.
+fn serve_tls<T>(
+    server: Arc<Server<T>>,
+    socket: TcpListener,
+    acceptor: Arc<ReloadingAcceptor>,
+    connmax: usize,
+) -> Result<(), Error>
+where
+    T: Fn(&mut Source, &mut Sink) -> bool + Clone + Send + Sync + 'static,
+{
+    let pending = Arc::new(AtomicUsize::new(0));
+    loop {
+        // Accept the connection
+        let (stream, peer) = socket.accept()?;
+        if shutdown::is_requested() {
+            return Ok(());
+        }
+
+        // Reserve a handshake slot or drop the connection
+        let Some(slot) = HandshakeSlot::acquire(&pending, connmax) else {
+            log::warn("Too many pending TLS handshakes", &[("peer", peer.to_string().into())]);
+            continue;
+        };
+
+        // Perform the handshake in a separate thread
+        let (server, acceptor) = (server.clone(), acceptor.clone());
+        thread::spawn(move || {
+            let result = handshake(&server, stream, &acceptor);
+            drop(slot);
+            if let Err(e) = result {
+                // Handshake errors are usually client errors, so we only log them
+                e.log_as(Level::Warn);
+            }
+        });
+    }
+}
+
+/// A reserved slot for a pending TLS handshake that is released on drop
+#[derive(Debug)]
+struct HandshakeSlot {
+    /// The amount of pending handshakes
+    pending: Arc<AtomicUsize>,
+}
+impl HandshakeSlot {
+    /// Reserves a slot if less than `max` handshakes are pending
+    fn acquire(pending: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        let increment = |count: usize| count.checked_add(1).filter(|count| *count <= max);
+        pending.fetch_update(Ordering::SeqCst, Ordering::SeqCst, increment).ok()?;
+        Some(Self { pending: pending.clone() })
+    }
+}
+impl Drop for HandshakeSlot {
+    fn drop(&mut self) {
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Performs the TLS handshake and dispatches the connection to the server
+fn handshake<T>(server: &Server<T>, stream: TcpStream, acceptor: &ReloadingAcceptor) -> Result<(), Error>
+where
+    T: Fn(&mut Source, &mut Sink) -> bool + Clone + Send + Sync + 'static,
+{
+    // Perform the handshake with a timeout
+    let peer = stream.peer_addr()?.to_string();
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let stream = acceptor.acceptor().accept(stream)?;
+    stream.get_ref().set_read_timeout(None)?;
+    stream.get_ref().set_write_timeout(None)?;
+
+    // Dispatch connection
+    let (rx, tx) = SharedStream::split(stream);
+    let rx = PeerReader::new(BufReader::new(rx), &peer);
+    server.dispatch(Source::from_other(rx), Sink::from_other(tx))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ehttpd::http::{Request, Response, ResponseExt};
+    use std::{net::Shutdown, path::Path, time::Instant};
+
+    /// The request to send
+    const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+    /// Serves a test server with the given connection and handshake limit via HTTPS on an ephemeral port
+    fn serve(connmax: usize) -> SocketAddr {
+        // Create a server that answers each request with an empty response
+        let server = Arc::new(Server::new(connmax, |source: &mut Source, sink: &mut Sink| {
+            let Ok(Some(_)) = Request::<4096>::from_stream(source) else {
+                return false;
+            };
+            Response::<4096>::new_200_ok().to_stream(sink).is_ok()
+        }));
+
+        // Serve with the built-in identity of the mock printer
+        let identity = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/mock");
+        let acceptor = ReloadingAcceptor::new(identity.join("identity.crt"), identity.join("identity.key")).unwrap();
+        let socket = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        thread::spawn(move || serve_tls(server, socket, Arc::new(acceptor), connmax));
+        address
+    }
+
+    /// Connects via TLS and sends the given amount of requests on the same connection
+    fn requests(address: SocketAddr, count: usize) -> io::Result<()> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut stream = tls::connect(stream, "localhost", &ConnectOptions::default()).map_err(io::Error::other)?;
+        for _ in 0..count {
+            stream.write_all(REQUEST)?;
+            let mut status = [0; 15];
+            stream.read_exact(&mut status)?;
+            assert_eq!(&status, b"HTTP/1.1 200 OK");
+            let mut rest = [0; 4];
+            while rest != *b"\r\n\r\n" {
+                rest.rotate_left(1);
+                stream.read_exact(&mut rest[3..])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Connections are dispatched to the server after the handshake and kept alive for subsequent requests
+    #[test]
+    fn tls_keep_alive() {
+        let address = serve(4);
+        requests(address, 3).unwrap();
+        requests(address, 1).unwrap();
+    }
+
+    /// Pending handshakes are limited to the connection limit, and the listener recovers once they are gone
+    #[test]
+    fn tls_connection_limit() {
+        // Occupy the single handshake slot
+        let address = serve(1);
+        let pending = TcpStream::connect(address).unwrap();
+        thread::sleep(Duration::from_millis(200));
+
+        // Further connections are dropped without a handshake
+        let mut dropped = TcpStream::connect(address).unwrap();
+        dropped.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let start = Instant::now();
+        assert!(matches!(dropped.read(&mut [0; 1]), Ok(0) | Err(_)));
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // Closing the pending connection frees the slot
+        pending.shutdown(Shutdown::Both).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        requests(address, 1).unwrap();
+    }
+
+    /// The HTTPS probe requires a handshake and a response, so a listener that only accepts connections is unhealthy
+    #[test]
+    fn probe_https() {
+        assert!(Probe::Https(serve(4)).check(Duration::from_secs(5)).is_ok());
+
+        // The kernel completes connections via the backlog even if the accept loop hangs
+        let wedged = TcpListener::bind("127.0.0.1:0").unwrap();
+        let probe = Probe::Https(wedged.local_addr().unwrap());
+        assert!(probe.check(Duration::from_millis(500)).is_err());
+    }
+
+    /// `auth=admin` is only accepted for listeners that are not reachable from other hosts
+    #[test]
+    fn admin_policy() {
+        for spec in ["http://127.0.0.1:8080?auth=admin", "https://[::1]:443?auth=admin", "unix:/run/x.sock?auth=admin"]
+        {
+            assert_eq!(ListenerSpec::parse(spec).unwrap().auth, AuthPolicy::Admin, "{spec}");
+        }
+        for spec in
+            ["http://[::]:80?auth=admin", "http://192.168.1.2:80?auth=admin", "https://example.com:443?auth=admin"]
+        {
+            assert!(ListenerSpec::parse(spec).is_err(), "{spec}");
+        }
+    }
+}
@@ -0,0 +1,360 @@
+//! Prometheus metrics for the HTTP server and the device services
+//!
+//! All metrics are kept in process-wide registries and rendered in the Prometheus text format on scrape. Devices are
+//! labelled with the key of their service (e.g. the device address); credentials within URLs are stripped.
+
+use crate::secret;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+/// The HTTP requests by route and status
+static REQUESTS: Mutex<BTreeMap<(&'static str, String), u64>> = Mutex::new(BTreeMap::new());
+/// The amount of rejected API keys
+static AUTH_FAILURES: AtomicU64 = AtomicU64::new(0);
+/// The device metrics by device label
+static DEVICES: Mutex<BTreeMap<String, DeviceMetrics>> = Mutex::new(BTreeMap::new());
+
+/// The upper bounds of the frame interval histogram buckets in milliseconds
+const INTERVAL_BUCKETS: [u64; 8] = [100, 250, 500, 1000, 2000, 5000, 10000, 30000];
+
+/// The state of the upstream device connection
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum UpstreamState {
+    /// The service is connecting and logging in
+    #[default]
+    Connecting,
+    /// The session is established
+    Connected,
+    /// The service has been stopped because it was idle, its config changed or the server is shutting down
+    Stopped,
+    /// The session has failed
+    Failed,
+}
+impl UpstreamState {
+    /// All states
+    const ALL: [Self; 4] = [Self::Connecting, Self::Connected, Self::Stopped, Self::Failed];
+
+    /// The label value
+    const fn label(self) -> &'static str {
+        match self {
+            Self::Connecting => "connecting",
+            Self::Connected => "connected",
+            Self::Stopped => "stopped",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// The metrics of a single device
+#[derive(Debug, Default)]
+struct DeviceMetrics {
+    /// The generation of the most recently started service, to ignore updates from replaced services
+    generation: u64,
+    /// The upstream state of the most recently started service
+    state: UpstreamState,
+    /// The amount of started services
+    starts: u64,
+    /// The amount of failed connection attempts
+    connect_failures: u64,
+    /// The amount of sessions that failed before the first frame (e.g. due to a wrong PIN)
+    login_failures: u64,
+    /// The amount of received frames
+    frames: u64,
+    /// The amount of received frame bytes
+    bytes: u64,
+    /// When the first service has been started
+    first_start: Option<Instant>,
+    /// When the last frame has been received
+    last_frame: Option<Instant>,
+    /// The cumulative frame interval histogram buckets
+    interval_buckets: [u64; INTERVAL_BUCKETS.len()],
+    /// The amount of observed frame intervals
+    interval_count: u64,
+    /// The sum of all observed frame intervals
+    interval_sum: Duration,
+    /// The amount of active streaming responses
+    viewers: u64,
+}
+
+/// Gets a counter value from the device metrics
+type DeviceCounter = fn(&DeviceMetrics) -> u64;
+
+/// Locks the given registry
+///
+/// # Note
+/// Since viewers are also updated during unwinding, a poisoned mutex is recovered instead of panicking again.
+fn lock<T>(registry: &'static Mutex<T>) -> MutexGuard<'static, T> {
+    registry.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Derives the device label from a service key by stripping credentials from URLs
+pub fn device_label(key: &str) -> String {
+    secret::redact_url(key)
+}
+
+/// Records an HTTP request
+pub fn record_request(route: &'static str, status: &[u8]) {
+    let status = String::from_utf8_lossy(status).into_owned();
+    let mut requests = lock(&REQUESTS);
+    let count = requests.entry((route, status)).or_default();
+    *count = count.saturating_add(1);
+}
+
+/// Records a rejected API key
+pub fn record_auth_failure() {
+    AUTH_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The upstream status of a device
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceStatus {
+    /// The upstream state label (`connecting`, `connected`, `stopped` or `failed`)
+    pub state: &'static str,
+    /// Whether a service is running or has failed, i.e. whether the device is expected to deliver frames
+    pub is_active: bool,
+    /// The time since the last frame, or since the first start if no frame has been received yet
+    pub staleness: Duration,
+    /// The time since the last frame, if any
+    pub frame_age: Option<Duration>,
+    /// The amount of active streaming responses
+    pub viewers: u64,
+}
+
+/// Gets the upstream status of the device with the given service key, if it has been started before
+pub fn device_status(key: &str) -> Option<DeviceStatus> {
+    let devices = lock(&DEVICES);
+    let device = devices.get(&device_label(key))?;
+    let now = Instant::now();
+    let frame_age = device.last_frame.map(|last_frame| now.saturating_duration_since(last_frame));
+    let first_age = device.first_start.map(|first_start| now.saturating_duration_since(first_start));
+    Some(DeviceStatus {
+        state: device.state.label(),
+        is_active: device.state != UpstreamState::Stopped,
+        staleness: frame_age.or(first_age).unwrap_or_default(),
+        frame_age,
+        viewers: device.viewers,
+    })
+}
+
+/// The metrics handle of a running device service
+#[derive(Debug)]
+pub struct DeviceHandle {
+    /// The device label
+    label: String,
+    /// The generation of the service
+    generation: u64,
+    /// Whether the current session has received a frame
+    received: bool,
+}
+impl DeviceHandle {
+    /// Registers a newly started service for the given service key
+    pub fn start(key: &str) -> Self {
+        let label = device_label(key);
+        let mut devices = lock(&DEVICES);
+        let device = devices.entry(label.clone()).or_default();
+        device.generation = device.generation.saturating_add(1);
+        device.starts = device.starts.saturating_add(1);
+        device.state = UpstreamState::Connecting;
+        device.first_start.get_or_insert_with(Instant::now);
+        Self { label, generation: device.generation, received: false }
+    }
+
+    /// Records an established session
+    pub fn connected(&mut self) {
+        self.update(|device, is_current| {
+            if is_current {
+                device.state = UpstreamState::Connected;
+            }
+        });
+    }
+
+    /// Records a received frame
+    pub fn frame(&mut self, bytes: usize) {
+        self.received = true;
+        self.update(|device, _| {
+            // Record the interval to the previous frame
+            let now = Instant::now();
+            if let Some(interval) = device.last_frame.map(|last_frame| now.saturating_duration_since(last_frame)) {
+                let millis = u64::try_from(interval.as_millis()).unwrap_or(u64::MAX);
+                for (bucket, upper) in device.interval_buckets.iter_mut().zip(INTERVAL_BUCKETS) {
+                    if millis <= upper {
+                        *bucket = bucket.saturating_add(1);
+                    }
+                }
+                device.interval_count = device.interval_count.saturating_add(1);
+                device.interval_sum = device.interval_sum.saturating_add(interval);
+            }
+
+            // Record the frame
+            device.last_frame = Some(now);
+            device.frames = device.frames.saturating_add(1);
+            device.bytes = device.bytes.saturating_add(u64::try_from(bytes).unwrap_or(u64::MAX));
+        });
+    }
+
+    /// Records the end of the service
+    ///
+    /// # Note
+    /// `connected` tells whether the session has been established; `failed` tells whether it ended with an error.
+    pub fn ended(&mut self, connected: bool, failed: bool) {
+        let received = self.received;
+        self.update(|device, is_current| {
+            // Count the failure
+            match (connected, received) {
+                (false, _) if failed => device.connect_failures = device.connect_failures.saturating_add(1),
+                (true, false) if failed => device.login_failures = device.login_failures.saturating_add(1),
+                _ => (),
+            }
+
+            // Update the state if the service has not been replaced
+            if is_current {
+                device.state = match failed {
+                    true => UpstreamState::Failed,
+                    false => UpstreamState::Stopped,
+                };
+            }
+        });
+    }
+
+    /// Updates the device metrics; the callback also receives whether the service is the most recent one
+    fn update<F>(&self, update: F)
+    where
+        F: FnOnce(&mut DeviceMetrics, bool),
+    {
+        let mut devices = lock(&DEVICES);
+        let device = devices.entry(self.label.clone()).or_default();
+        let is_current = device.generation == self.generation;
+        update(device, is_current);
+    }
+}
+
+/// An active streaming response that is counted as viewer until it is dropped
+#[derive(Debug)]
+pub struct Viewer {
+    /// The device label
+    label: String,
+}
+impl Viewer {
+    /// Registers a new viewer for the given service key
+    pub fn start(key: &str) -> Self {
+        let label = device_label(key);
+        let mut devices = lock(&DEVICES);
+        let device = devices.entry(label.clone()).or_default();
+        device.viewers = device.viewers.saturating_add(1);
+        Self { label }
+    }
+}
+impl Drop for Viewer {
+    fn drop(&mut self) {
+        let mut devices = lock(&DEVICES);
+        if let Some(device) = devices.get_mut(&self.label) {
+            device.viewers = device.viewers.saturating_sub(1);
+        }
+    }
+}
+
+/// Escapes a label value
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Renders all metrics in the Prometheus text format
+pub fn render() -> String {
+    let mut text = String::new();
+    // Writing to a string cannot fail
+    let _ = render_into(&mut text);
+    text
+}
+
+/// Renders all metrics into the given string
+fn render_into(text: &mut String) -> std::fmt::Result {
+    /// Writes the `HELP` and `TYPE` lines of a metric
+    fn header(text: &mut String, name: &str, kind: &str, help: &str) -> std::fmt::Result {
+        writeln!(text, "# HELP bamborvideostream_{name} {help}")?;
+        writeln!(text, "# TYPE bamborvideostream_{name} {kind}")
+    }
+
+    // Render the HTTP metrics
+    header(text, "http_requests_total", "counter", "HTTP requests by route and status.")?;
+    for ((route, status), count) in lock(&REQUESTS).iter() {
+        let status = escape(status);
+        writeln!(text, "bamborvideostream_http_requests_total{{route=\"{route}\",status=\"{status}\"}} {count}")?;
+    }
+    header(text, "auth_failures_total", "counter", "Requests rejected because of a missing or invalid API key.")?;
+    writeln!(text, "bamborvideostream_auth_failures_total {}", AUTH_FAILURES.load(Ordering::Relaxed))?;
+
+    // Render the device metrics
+    let devices = lock(&DEVICES);
+    let now = Instant::now();
+    header(text, "device_up", "gauge", "Whether the upstream session of the device is established.")?;
+    for (label, device) in devices.iter() {
+        let up = u8::from(device.state == UpstreamState::Connected);
+        writeln!(text, "bamborvideostream_device_up{{device=\"{}\"}} {up}", escape(label))?;
+    }
+    header(text, "device_state", "gauge", "The upstream state of the device.")?;
+    for (label, device) in devices.iter() {
+        for state in UpstreamState::ALL {
+            let value = u8::from(device.state == state);
+            let (label, state) = (escape(label), state.label());
+            writeln!(text, "bamborvideostream_device_state{{device=\"{label}\",state=\"{state}\"}} {value}")?;
+        }
+    }
+    let counters: [(&str, &str, DeviceCounter); 5] = [
+        ("device_frames_total", "Frames received from the device.", |device| device.frames),
+        ("device_bytes_total", "Frame bytes received from the device.", |device| device.bytes),
+        ("device_reconnects_total", "Service restarts after the first one.", |device| device.starts.saturating_sub(1)),
+        ("device_connect_failures_total", "Failed connection attempts.", |device| device.connect_failures),
+        ("device_login_failures_total", "Sessions that failed before the first frame (e.g. a wrong PIN).", |device| {
+            device.login_failures
+        }),
+    ];
+    for (name, help, value) in counters {
+        header(text, name, "counter", help)?;
+        for (label, device) in devices.iter() {
+            writeln!(text, "bamborvideostream_{name}{{device=\"{}\"}} {}", escape(label), value(device))?;
+        }
+    }
+    header(text, "device_frame_age_seconds", "gauge", "The time since the last frame has been received.")?;
+    for (label, device) in devices.iter().filter(|(_, device)| device.last_frame.is_some()) {
+        let age = device.last_frame.map(|last_frame| now.saturating_duration_since(last_frame)).unwrap_or_default();
+        writeln!(
+            text,
+            "bamborvideostream_device_frame_age_seconds{{device=\"{}\"}} {:.3}",
+            escape(label),
+            age.as_secs_f64()
+        )?;
+    }
+    header(text, "device_frame_interval_seconds", "histogram", "The intervals between two received frames.")?;
+    for (label, device) in devices.iter() {
+        let label = escape(label);
+        for (bucket, upper) in device.interval_buckets.iter().zip(INTERVAL_BUCKETS) {
+            let upper = Duration::from_millis(upper).as_secs_f64();
+            writeln!(
+                text,
+                "bamborvideostream_device_frame_interval_seconds_bucket{{device=\"{label}\",le=\"{upper}\"}} {bucket}"
+            )?;
+        }
+        let count = device.interval_count;
+        writeln!(
+            text,
+            "bamborvideostream_device_frame_interval_seconds_bucket{{device=\"{label}\",le=\"+Inf\"}} {count}"
+        )?;
+        let sum = device.interval_sum.as_secs_f64();
+        writeln!(text, "bamborvideostream_device_frame_interval_seconds_sum{{device=\"{label}\"}} {sum:.3}")?;
+        writeln!(text, "bamborvideostream_device_frame_interval_seconds_count{{device=\"{label}\"}} {count}")?;
+    }
+    header(text, "device_viewers", "gauge", "Active streaming responses of the device.")?;
+    for (label, device) in devices.iter() {
+        writeln!(text, "bamborvideostream_device_viewers{{device=\"{}\"}} {}", escape(label), device.viewers)?;
+    }
+    header(text, "active_streams", "gauge", "Active streaming responses of all devices.")?;
+    let streams = devices.values().fold(0u64, |streams, device| streams.saturating_add(device.viewers));
+    writeln!(text, "bamborvideostream_active_streams {streams}")
+}
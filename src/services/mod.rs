@@ -1,4 +1,16 @@
 //! Some service classes
 
+pub mod accesslog;
+pub mod camera;
 pub mod config;
+pub mod devicestore;
+pub mod health;
+pub mod http;
+pub mod image;
+pub mod listener;
+pub mod metrics;
 pub mod p1;
+pub mod shutdown;
+pub mod systemd;
+pub mod tls;
+pub mod x1;
@@ -0,0 +1,241 @@
+//! A file format to record raw P1 sessions
+//!
+//! A capture file starts with the magic bytes `BVSCAP01` followed by the UNIX timestamp of the capture start in
+//! microseconds (`u64`, little endian). Each received frame is stored as a record consisting of the receive time in
+//! microseconds since the capture start (`u64`, little endian), the raw 16-byte frame header and the frame payload as
+//! announced by the header.
+//!
+//! Each capture file is limited to the remaining capture budget of its directory (see [`directory_size`]), so that a
+//! forgotten capture cannot fill the disk.
+
+use crate::{error, error::Error};
+use bamborvideostream_p1::{protocol::FRAME_HEADER_SIZE, ConnectOptions, FrameHeader};
+use std::{
+    fs,
+    io::{ErrorKind, Read, Write},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The magic bytes of a capture file
+const MAGIC: [u8; 8] = *b"BVSCAP01";
+/// The size of the file header
+const FILE_HEADER_SIZE: u64 = 16;
+/// The size of the record header, i.e. the timestamp and the frame header
+const RECORD_HEADER_SIZE: u64 = 24;
+/// The file extension of capture files
+pub const EXTENSION: &str = "bvscap";
+
+/// The total size of all capture files within the given directory
+pub fn directory_size(dir: &Path) -> Result<u64, Error> {
+    let mut size = 0u64;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == EXTENSION) {
+            size = size.saturating_add(fs::metadata(path)?.len());
+        }
+    }
+    Ok(size)
+}
+
+/// A single captured frame
+#[derive(Debug, Clone)]
+pub struct CaptureRecord {
+    /// The receive time relative to the capture start
+    pub timestamp: Duration,
+    /// The frame payload
+    pub payload: Vec<u8>,
+}
+
+/// Writes a capture file
+pub struct CaptureWriter {
+    /// The underlying sink
+    sink: Box<dyn Write + Send>,
+    /// The capture start
+    start: Instant,
+    /// The amount of bytes written so far
+    written: u64,
+    /// The maximum size of the capture file
+    limit: u64,
+}
+impl CaptureWriter {
+    /// Creates a new capture writer with the given maximum file size and writes the file header
+    pub fn new<T>(sink: T, limit: u64) -> Result<Self, Error>
+    where
+        T: Write + Send + 'static,
+    {
+        // Write the file header
+        let mut sink: Box<dyn Write + Send> = Box::new(sink);
+        let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let unix_time = u64::try_from(unix_time.as_micros())?;
+        sink.write_all(&MAGIC)?;
+        sink.write_all(&unix_time.to_le_bytes())?;
+        sink.flush()?;
+
+        // Init self
+        Ok(Self { sink, start: Instant::now(), written: FILE_HEADER_SIZE, limit })
+    }
+
+    /// Records a frame that has been received at the given instant
+    ///
+    /// # Note
+    /// If the record would exceed the maximum file size, nothing is written and `false` is returned.
+    pub fn record(
+        &mut self,
+        received: Instant,
+        header: &[u8; FRAME_HEADER_SIZE],
+        payload: &[u8],
+    ) -> Result<bool, Error> {
+        // Check the size limit
+        let written = self.written.saturating_add(RECORD_HEADER_SIZE).saturating_add(payload.len() as u64);
+        if written > self.limit {
+            return Ok(false);
+        }
+
+        // Write the record and flush it so that the capture survives a crash
+        let timestamp = u64::try_from(received.saturating_duration_since(self.start).as_micros())?;
+        self.sink.write_all(&timestamp.to_le_bytes())?;
+        self.sink.write_all(header)?;
+        self.sink.write_all(payload)?;
+        self.sink.flush()?;
+        self.written = written;
+        Ok(true)
+    }
+}
+
+/// Reads a capture file
+#[derive(Debug)]
+pub struct CaptureReader<T> {
+    /// The underlying source
+    source: T,
+    /// The maximum payload size of a record
+    max_frame_size: u32,
+}
+impl<T> CaptureReader<T>
+where
+    T: Read,
+{
+    /// Creates a new capture reader and validates the file header
+    pub fn new(mut source: T) -> Result<Self, Error> {
+        // Read and validate the file header
+        let mut magic = [0; MAGIC.len()];
+        source.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(error!("Invalid capture file"));
+        }
+
+        // Skip the capture start since replays are relative
+        let mut _unix_time = [0; 8];
+        source.read_exact(&mut _unix_time)?;
+        Ok(Self { source, max_frame_size: ConnectOptions::default().max_frame_size })
+    }
+
+    /// Reads the next record or returns `None` at the end of the capture
+    pub fn record(&mut self) -> Result<Option<CaptureRecord>, Error> {
+        // Read the timestamp or detect the end of the capture
+        let mut timestamp = [0; 8];
+        match self.source.read_exact(&mut timestamp) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let timestamp = Duration::from_micros(u64::from_le_bytes(timestamp));
+
+        // Read the header and the payload; the size is checked like for live sessions before allocating the payload
+        let mut header = [0; FRAME_HEADER_SIZE];
+        self.source.read_exact(&mut header)?;
+        let header = FrameHeader::parse(header);
+        let size = header.check_size(self.max_frame_size).map_err(|e| error!(with: e, "Invalid capture record"))?;
+        let mut payload = vec![0; size];
+        self.source.read_exact(&mut payload)?;
+        Ok(Some(CaptureRecord { timestamp, payload }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{self, Cursor},
+        sync::{Arc, Mutex},
+    };
+
+    /// A sink that can be inspected after the writer has been moved
+    #[derive(Debug, Clone, Default)]
+    struct SharedSink(Arc<Mutex<Vec<u8>>>);
+    impl Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Creates a capture with the given raw records
+    fn capture(records: &[(u64, FrameHeader, &[u8])]) -> Vec<u8> {
+        let mut capture = [MAGIC.as_slice(), &0u64.to_le_bytes()].concat();
+        for (timestamp, header, payload) in records {
+            capture.extend_from_slice(&timestamp.to_le_bytes());
+            capture.extend_from_slice(&header.raw);
+            capture.extend_from_slice(payload);
+        }
+        capture
+    }
+
+    /// Recorded frames are read back in order
+    #[test]
+    fn round_trip() {
+        let sink = SharedSink::default();
+        let mut writer = CaptureWriter::new(sink.clone(), u64::MAX).unwrap();
+        assert!(writer.record(Instant::now(), &FrameHeader::new(3).raw, b"abc").unwrap());
+        assert!(writer.record(Instant::now(), &FrameHeader::new(0).raw, b"").unwrap());
+        drop(writer);
+
+        let capture = sink.0.lock().unwrap().clone();
+        let mut reader = CaptureReader::new(Cursor::new(capture)).unwrap();
+        let (first, second) = (reader.record().unwrap().unwrap(), reader.record().unwrap().unwrap());
+        assert_eq!((first.payload.as_slice(), second.payload.as_slice()), (b"abc".as_slice(), b"".as_slice()));
+        assert!(first.timestamp <= second.timestamp);
+        assert!(reader.record().unwrap().is_none());
+    }
+
+    /// Records that would exceed the maximum file size are not written
+    #[test]
+    fn limit() {
+        let sink = SharedSink::default();
+        let mut writer = CaptureWriter::new(sink.clone(), FILE_HEADER_SIZE + RECORD_HEADER_SIZE + 3).unwrap();
+        assert!(!writer.record(Instant::now(), &FrameHeader::new(4).raw, b"abcd").unwrap());
+        assert!(writer.record(Instant::now(), &FrameHeader::new(3).raw, b"abc").unwrap());
+        assert!(!writer.record(Instant::now(), &FrameHeader::new(0).raw, b"").unwrap());
+        drop(writer);
+
+        let capture = sink.0.lock().unwrap().clone();
+        assert_eq!(capture.len() as u64, FILE_HEADER_SIZE + RECORD_HEADER_SIZE + 3);
+        let mut reader = CaptureReader::new(Cursor::new(capture)).unwrap();
+        assert_eq!(reader.record().unwrap().unwrap().payload, b"abc");
+        assert!(reader.record().unwrap().is_none());
+    }
+
+    /// Oversized records are rejected before their payload is allocated
+    #[test]
+    fn oversized_record() {
+        let limit = ConnectOptions::default().max_frame_size;
+        for size in [limit.saturating_add(1), u32::MAX] {
+            let mut reader = CaptureReader::new(Cursor::new(capture(&[(0, FrameHeader::new(size), b"")]))).unwrap();
+            assert!(reader.record().is_err(), "size {size}");
+        }
+    }
+
+    /// Invalid headers and truncated records are rejected
+    #[test]
+    fn malformed() {
+        assert!(CaptureReader::new(Cursor::new(b"BVSCAP00\0\0\0\0\0\0\0\0".to_vec())).is_err());
+        assert!(CaptureReader::new(Cursor::new(MAGIC.to_vec())).is_err());
+
+        let capture = capture(&[(0, FrameHeader::new(4), b"abc")]);
+        let mut reader = CaptureReader::new(Cursor::new(capture)).unwrap();
+        assert!(reader.record().is_err());
+    }
+}
@@ -1,48 +1,442 @@
 //! An image service for a P1S/P1P client
 
-mod connection;
+pub mod capture;
+pub mod connection;
+pub mod mosaic;
+pub mod replay;
 
-use crate::{error::Error, services::p1::connection::P1Connection};
+use crate::{
+    error,
+    error::Error,
+    log::{self, Level},
+    secret::Secret,
+    services::{
+        camera::{Frame, Source, Stream},
+        image::rendition::{Rendition, RenditionCache},
+        metrics::{self, DeviceHandle},
+        p1::{
+            capture::CaptureWriter,
+            connection::{P1Connection, P1Session},
+        },
+        shutdown::{self, Activity},
+    },
+};
+use bamborvideostream_p1::ConnectOptions;
 use std::{
-    collections::BTreeMap,
-    sync::{Arc, LazyLock, Mutex, Weak},
+    collections::{BTreeMap, VecDeque},
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    str,
+    sync::{Arc, Condvar, LazyLock, Mutex, MutexGuard, PoisonError, RwLock, Weak},
     thread,
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-/// A service for a P1S/P1P client
+/// Parses a hex-encoded SHA-256 certificate fingerprint; colon separators like `AB:CD:...` are accepted
+pub fn parse_fingerprint(fingerprint: &str) -> Option<[u8; 32]> {
+    // Strip separators and validate the length
+    let hex: Vec<u8> = fingerprint.bytes().filter(|byte| *byte != b':').collect();
+    let mut bytes = [0; 32];
+    if hex.len() != bytes.len().saturating_mul(2) || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+
+    // Decode the hex pairs
+    for (byte, pair) in bytes.iter_mut().zip(hex.chunks_exact(2)) {
+        let pair = str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Formats a SHA-256 certificate fingerprint as colon-separated uppercase hex pairs like `AB:CD:...`
+pub fn format_fingerprint(fingerprint: &[u8; 32]) -> String {
+    let pairs: Vec<String> = fingerprint.iter().map(|byte| format!("{byte:02X}")).collect();
+    pairs.join(":")
+}
+
+/// A camera source for a P1S/P1P device
+#[derive(Debug, Clone)]
+pub struct P1Source {
+    /// The device address
+    address: String,
+    /// The device PIN
+    pin: Secret,
+    /// The directory to record the raw sessions into, if any
+    capture_dir: Option<PathBuf>,
+    /// The maximum total size of all capture files within the capture directory
+    capture_max: u64,
+    /// The connection options
+    options: ConnectOptions,
+}
+impl P1Source {
+    /// Creates a new P1 camera source
+    pub fn new(address: &str, pin: &Secret) -> Self {
+        let (address, pin) = (address.to_string(), pin.clone());
+        Self { address, pin, capture_dir: None, capture_max: 0, options: ConnectOptions::default() }
+    }
+
+    /// Pins the device certificate to the given SHA-256 fingerprint of its DER encoding
+    pub fn with_pinned_sha256(mut self, fingerprint: Option<[u8; 32]>) -> Self {
+        self.options.pinned_sha256 = fingerprint;
+        self
+    }
+
+    /// Records each session into a new capture file within the given directory, until all capture files within the
+    /// directory reach the given total size in bytes
+    pub fn with_capture<T>(mut self, capture_dir: Option<T>, capture_max: u64) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        self.capture_dir = capture_dir.map(|capture_dir| capture_dir.as_ref().to_path_buf());
+        self.capture_max = capture_max;
+        self
+    }
+
+    /// Creates a new capture file for a session, or returns `None` if the capture directory is full
+    fn capture(&self, capture_dir: &Path) -> Result<Option<CaptureWriter>, Error> {
+        // Get the remaining capture budget
+        let used = capture::directory_size(capture_dir)?;
+        let Some(limit) = self.capture_max.checked_sub(used).filter(|limit| *limit > 0) else {
+            let fields = [("address", self.address.as_str().into()), ("used_bytes", used.into())];
+            log::warn("Skipped capture since the capture directory is full", &fields);
+            return Ok(None);
+        };
+
+        // Derive a unique file name from the address and the current time
+        let address: String = self.address.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+        let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let path = capture_dir.join(format!("{address}-{}.{}", unix_time.as_millis(), capture::EXTENSION));
+
+        // Create the capture file
+        let file = File::create_new(path)?;
+        Ok(Some(CaptureWriter::new(BufWriter::new(file), limit)?))
+    }
+}
+impl Source for P1Source {
+    fn content_type(&self) -> &'static str {
+        "image/jpeg"
+    }
+
+    fn connect(&self) -> Result<Box<dyn Stream>, Error> {
+        // Setup connection
+        let connection = P1Connection::new(&self.address, &self.options)?;
+        let mut session = connection.login(&self.pin)?;
+
+        // Start recording if the device is captured
+        if let Some(capture_dir) = &self.capture_dir {
+            if let Some(capture) = self.capture(capture_dir)? {
+                session.capture(capture);
+            }
+        }
+        Ok(Box::new(P1Stream { session, last_frame: None }))
+    }
+
+    fn with_pin(&self, pin: &Secret) -> Option<Arc<dyn Source>> {
+        Some(Arc::new(Self { pin: pin.clone(), ..self.clone() }))
+    }
+}
+
+/// A frame stream from a P1S/P1P device
+///
+/// # Note
+/// The stream is throttled to one frame per second, except while the session is captured, so that the recorded
+/// timestamps reflect the timing of the device.
+struct P1Stream {
+    /// The device session
+    session: P1Session,
+    /// When the last frame has been received
+    last_frame: Option<Instant>,
+}
+impl P1Stream {
+    /// The duration of a single frame
+    const FRAME_DURATION: Duration = Duration::from_secs(1);
+}
+impl Stream for P1Stream {
+    fn frame(&mut self) -> Result<Frame, Error> {
+        // Pause for the remaining frame duration
+        if let Some(last_frame) = self.last_frame.filter(|_| !self.session.is_capturing()) {
+            let remaining = Self::FRAME_DURATION.saturating_sub(last_frame.elapsed());
+            thread::sleep(remaining);
+        }
+
+        // Receive the next JPEG
+        let jpeg = self.session.jpeg()?;
+        self.last_frame = Some(Instant::now());
+        Ok(Frame::jpeg(jpeg))
+    }
+
+    fn close(self: Box<Self>) -> Result<(), Error> {
+        self.session.close()
+    }
+}
+
+/// The shared state of a service
+#[derive(Debug)]
+struct ServiceState {
+    /// The most recent frames together with their sequence numbers
+    frames: VecDeque<(u64, Frame)>,
+    /// The sequence number of the next frame
+    sequence: u64,
+    /// The last time a consumer has accessed the service
+    last_access: Instant,
+    /// Whether the runloop has been asked to stop (e.g. because the device config has changed)
+    stopped: bool,
+    /// Whether the runloop has been asked to close the current session and connect again
+    reconnect: bool,
+    /// Whether the runloop has terminated
+    terminated: bool,
+}
+
+/// A service for a camera device
+///
+/// # Note
+/// Despite its name, the service is not limited to P1S/P1P devices and can be started for any camera [`Source`].
 #[derive(Debug)]
 pub struct P1Service {
-    /// The last image
-    last_image: Mutex<Option<Vec<u8>>>,
+    /// The key of the service in the service registry
+    key: String,
+    /// The camera source; it is replaced if the device PIN changes
+    source: RwLock<Arc<dyn Source>>,
+    /// The shared service state
+    state: Mutex<ServiceState>,
+    /// Signals new frames or the termination of the runloop
+    signal: Condvar,
+    /// The scaled and re-encoded renditions of the most recent frame
+    renditions: RenditionCache,
 }
 impl P1Service {
-    /// The duration of a single frame
-    const FRAME_DURATION: Duration = Duration::from_secs(1);
-    /// Keep alive for 600 frames (aka 10 minutes)
-    const KEEP_ALIVE: usize = 600;
+    /// Keep the runloop alive for 10 minutes after the last access
+    const KEEP_ALIVE: Duration = Duration::from_secs(600);
+    /// The amount of frames to buffer for streaming consumers
+    const FRAME_BUFFER: usize = 64;
+
+    /// Starts a new service with the given registry key for the given camera source
+    pub fn with_source<T>(key: &str, source: T) -> Arc<Self>
+    where
+        T: Source + 'static,
+    {
+        Self::start(key, Arc::new(source))
+    }
 
-    /// Starts a new P1 service
-    pub fn new(address: &str, pin: &str) -> Arc<Self> {
+    /// Starts a new service with the given registry key for the given shared camera source
+    pub fn start(key: &str, source: Arc<dyn Source>) -> Arc<Self> {
         // Setup service state
-        let service = Arc::new(Self { last_image: Mutex::new(None) });
+        let state = ServiceState {
+            frames: VecDeque::with_capacity(Self::FRAME_BUFFER),
+            sequence: 0,
+            last_access: Instant::now(),
+            stopped: false,
+            reconnect: false,
+            terminated: false,
+        };
+        let service = Arc::new(Self {
+            key: key.to_string(),
+            source: RwLock::new(source),
+            state: Mutex::new(state),
+            signal: Condvar::new(),
+            renditions: RenditionCache::default(),
+        });
 
         // Start runloop thread
-        let address_ = address.to_string();
-        let pin_ = pin.to_string();
-        let service_ = service.clone();
-        thread::spawn(|| Self::runloop(address_, pin_, service_));
+        let (service_, activity, metrics) = (service.clone(), Activity::start(), DeviceHandle::start(key));
+        thread::spawn(|| Self::runloop(service_, activity, metrics));
 
         // Return the service
         service
     }
 
-    /// Gets the last JPEG of the connected device
-    pub fn jpeg(&self) -> Option<Vec<u8>> {
-        // Get last image
+    /// Gets the living service for the given key or starts a new one
+    pub fn get_or_start<F>(key: &str, start: F) -> Arc<Self>
+    where
+        F: FnOnce() -> Arc<Self>,
+    {
+        // Get the associated device service
         #[allow(clippy::expect_used, reason = "Locking a mutex should never fail under normal conditions")]
-        let last_image_lock = self.last_image.lock().expect("Failed to lock mutex");
-        last_image_lock.clone()
+        let mut services = Self::services().lock().expect("Failed to lock services registry");
+
+        // Try to get a living service for the given device
+        let maybe_service = services.get(key).and_then(Weak::upgrade);
+        if let Some(service) = maybe_service.filter(|service| !service.is_stopped()) {
+            // The service is still alive, use it
+            service
+        } else {
+            // Create new service and get a weak reference for the registry
+            let service = start();
+            let service_weak = Arc::downgrade(&service);
+
+            // Register the weak reference and return the service
+            services.insert(key.to_string(), service_weak);
+            service
+        }
+    }
+
+    /// Gets the registered service for the given key, if it is still alive
+    ///
+    /// # Note
+    /// Unlike [`Self::get_or_start`], this also returns stopped services as long as they have consumers.
+    pub fn get(key: &str) -> Option<Arc<Self>> {
+        #[allow(clippy::expect_used, reason = "Locking a mutex should never fail under normal conditions")]
+        let services = Self::services().lock().expect("Failed to lock services registry");
+        services.get(key).and_then(Weak::upgrade)
+    }
+
+    /// Gets all registered services that are still alive
+    pub fn list() -> Vec<Arc<Self>> {
+        #[allow(clippy::expect_used, reason = "Locking a mutex should never fail under normal conditions")]
+        let services = Self::services().lock().expect("Failed to lock services registry");
+        services.values().filter_map(Weak::upgrade).collect()
+    }
+
+    /// The key of the service in the service registry
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The MIME type of the frames yielded by this service
+    pub fn content_type(&self) -> &'static str {
+        self.source().content_type()
+    }
+
+    /// The time since the last consumer has accessed the service
+    pub fn idle_time(&self) -> Duration {
+        self.state().last_access.elapsed()
+    }
+
+    /// Whether the service runloop has terminated
+    pub fn is_terminated(&self) -> bool {
+        self.state().terminated
+    }
+
+    /// Whether the service runloop has been asked to stop or has terminated
+    pub fn is_stopped(&self) -> bool {
+        let state = self.state();
+        state.stopped || state.terminated
+    }
+
+    /// Asks the runloop of the service with the given key to stop after the current frame
+    ///
+    /// # Note
+    /// Consumers of the stopped service receive the remaining frames; subsequent calls to [`Self::get_or_start`] start a
+    /// new service.
+    pub fn stop(key: &str) {
+        #[allow(clippy::expect_used, reason = "Locking a mutex should never fail under normal conditions")]
+        let services = Self::services().lock().expect("Failed to lock services registry");
+        if let Some(service) = services.get(key).and_then(Weak::upgrade) {
+            service.state().stopped = true;
+        }
+    }
+
+    /// Stops the service with the given key and removes it from the registry; returns whether the service existed
+    ///
+    /// # Note
+    /// Unlike [`Self::stop`], the service is dropped from the registry immediately, so it is not listed anymore even if
+    /// consumers are still receiving the remaining frames.
+    pub fn remove(key: &str) -> bool {
+        #[allow(clippy::expect_used, reason = "Locking a mutex should never fail under normal conditions")]
+        let mut services = Self::services().lock().expect("Failed to lock services registry");
+        let Some(service) = services.remove(key).and_then(|service| service.upgrade()) else {
+            return false;
+        };
+        service.state().stopped = true;
+        true
+    }
+
+    /// Forces the service with the given key to reconnect to its device and returns the service
+    ///
+    /// # Note
+    /// A running service closes its current session and connects again while keeping its consumers; a stopped or
+    /// terminated service (e.g. after a failed login) is replaced by a new service with the same source.
+    pub fn reconnect(key: &str) -> Option<Arc<Self>> {
+        #[allow(clippy::expect_used, reason = "Locking a mutex should never fail under normal conditions")]
+        let mut services = Self::services().lock().expect("Failed to lock services registry");
+        let service = services.get(key).and_then(Weak::upgrade)?;
+        if !service.is_stopped() {
+            // Ask the runloop to start a new session
+            service.state().reconnect = true;
+            return Some(service);
+        }
+
+        // Start a replacement service
+        let replacement = Self::start(key, service.source());
+        services.insert(key.to_string(), Arc::downgrade(&replacement));
+        Some(replacement)
+    }
+
+    /// Replaces the PIN for all subsequent sessions; returns `false` if the source does not use a PIN
+    ///
+    /// # Note
+    /// The current session is kept; use [`Self::reconnect`] to log in with the new PIN immediately.
+    pub fn set_pin(&self, pin: &Secret) -> bool {
+        let Some(source) = self.source().with_pin(pin) else {
+            return false;
+        };
+        *self.source.write().unwrap_or_else(PoisonError::into_inner) = source;
+        true
+    }
+
+    /// Gets the last JPEG of the connected device, optionally as the given rendition
+    pub fn jpeg(&self, rendition: Option<&Rendition>) -> Result<Option<Vec<u8>>, Error> {
+        // Get last image
+        let frame = {
+            let mut state = self.state();
+            state.last_access = Instant::now();
+            match state.frames.back() {
+                Some((_, frame)) => frame.clone(),
+                None => return Ok(None),
+            }
+        };
+
+        // Ensure that the last frame is a JPEG
+        if frame.content_type != "image/jpeg" {
+            return Ok(None);
+        }
+        match rendition {
+            Some(rendition) => Ok(Some(self.rendition(&frame, rendition)?.data.to_vec())),
+            None => Ok(Some(frame.data.to_vec())),
+        }
+    }
+
+    /// Gets the given rendition of the given JPEG frame; renditions of the most recent frame are cached
+    pub fn rendition(&self, frame: &Frame, rendition: &Rendition) -> Result<Frame, Error> {
+        self.renditions.get(frame, rendition)
+    }
+
+    /// Waits for the most recent frame that is newer than `after`
+    ///
+    /// # Note
+    /// This function returns `None` if the timeout has been reached or the runloop has terminated.
+    pub fn latest_frame(&self, after: Option<u64>, timeout: Duration) -> Option<(u64, Frame)> {
+        self.wait(timeout, |frames| {
+            // Get the most recent frame if it is newer than `after`
+            let (sequence, frame) = frames.back()?;
+            match after {
+                Some(after) if *sequence <= after => None,
+                _ => Some((*sequence, frame.clone())),
+            }
+        })
+    }
+
+    /// Waits for the frame directly following `after`
+    ///
+    /// # Note
+    /// If there is no previous frame or it has already been evicted from the buffer, the stream is resumed at the most
+    /// recent keyframe. This function returns `None` if the timeout has been reached or the runloop has terminated.
+    pub fn next_frame(&self, after: Option<u64>, timeout: Duration) -> Option<(u64, Frame)> {
+        self.wait(timeout, |frames| {
+            // Find the successor if it is still buffered
+            let successor = after.and_then(|after| after.checked_add(1));
+            let (oldest, _) = frames.front()?;
+            if let Some(successor) = successor.filter(|successor| successor >= oldest) {
+                let (sequence, frame) = frames.iter().find(|(sequence, _)| *sequence == successor)?;
+                return Some((*sequence, frame.clone()));
+            }
+
+            // Resume at the most recent keyframe
+            let (sequence, frame) = frames.iter().rev().find(|(_, frame)| frame.keyframe)?;
+            Some((*sequence, frame.clone()))
+        })
     }
 
     /// The globally registered P1 services
@@ -55,32 +449,129 @@ impl P1Service {
         &IMAGE_SERVICES
     }
 
+    /// The current camera source
+    fn source(&self) -> Arc<dyn Source> {
+        self.source.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Takes a pending reconnect request
+    fn take_reconnect(&self) -> bool {
+        std::mem::take(&mut self.state().reconnect)
+    }
+
+    /// Locks the shared service state
+    fn state(&self) -> MutexGuard<'_, ServiceState> {
+        #[allow(clippy::expect_used, reason = "Locking a mutex should never fail under normal conditions")]
+        self.state.lock().expect("Failed to lock mutex")
+    }
+
+    /// Waits until `select` yields a frame, the timeout is reached or the runloop terminates
+    fn wait<F>(&self, timeout: Duration, mut select: F) -> Option<(u64, Frame)>
+    where
+        F: FnMut(&VecDeque<(u64, Frame)>) -> Option<(u64, Frame)>,
+    {
+        let deadline = Instant::now().checked_add(timeout)?;
+        let mut state = self.state();
+        loop {
+            // Mark the service as accessed and try to select a frame
+            state.last_access = Instant::now();
+            if let Some(frame) = select(&state.frames) {
+                return Some(frame);
+            }
+
+            // Wait for the next frame
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if state.terminated || remaining.is_zero() {
+                return None;
+            }
+
+            #[allow(clippy::expect_used, reason = "Locking a mutex should never fail under normal conditions")]
+            let (state_, _) = self.signal.wait_timeout(state, remaining).expect("Failed to lock mutex");
+            state = state_;
+        }
+    }
+
+    /// Publishes a new frame
+    fn publish(&self, frame: Frame) {
+        // Append the frame and evict old frames
+        let mut state = self.state();
+        let sequence = state.sequence;
+        state.sequence = sequence.saturating_add(1);
+        state.frames.push_back((sequence, frame));
+        while state.frames.len() > Self::FRAME_BUFFER {
+            state.frames.pop_front();
+        }
+
+        // Notify waiting consumers
+        drop(state);
+        self.signal.notify_all();
+    }
+
+    /// Whether the service has not been accessed for the keep-alive duration
+    fn is_idle(&self) -> bool {
+        self.state().last_access.elapsed() > Self::KEEP_ALIVE
+    }
+
+    /// Marks the runloop as terminated
+    fn terminate(&self) {
+        self.state().terminated = true;
+        self.signal.notify_all();
+    }
+
     /// The service runloop
-    #[allow(clippy::expect_used, reason = "We run in a separate thread and may panick")]
-    fn runloop(address: String, pin: String, service: Arc<Self>) {
+    fn runloop(service: Arc<Self>, _activity: Activity, mut metrics: DeviceHandle) {
         // Fallible runloop scope
-        let try_catch = || -> Result<(), Error> {
-            // Setup connection
-            let connection = P1Connection::new(&address)?;
-            let mut session = connection.login(&pin)?;
-
-            // Fetch some images for some time
-            for _ in 0..Self::KEEP_ALIVE {
-                // Set JPEG
-                let jpeg = session.jpeg()?;
-                let mut last_image = service.last_image.lock().expect("Failed to lock mutex");
-                *last_image = Some(jpeg);
-
-                // Unlock shared state and pause for the frame duration
-                drop(last_image);
-                thread::sleep(Self::FRAME_DURATION);
+        let device = metrics::device_label(&service.key);
+        log::info("Device service started", &[("device", device.as_str().into())]);
+        let mut connected = false;
+        let mut try_catch = || -> Result<(), Error> {
+            loop {
+                // Setup connection; reconnect requests during the login are obsolete
+                service.take_reconnect();
+                let mut stream = service.source().connect()?;
+                connected = true;
+                metrics.connected();
+                log::info("Device connected", &[("device", device.as_str().into())]);
+
+                // Fetch frames as long as the service is in use or until a reconnect is requested
+                let mut reconnect = false;
+                while !service.is_idle() && !service.is_stopped() && !shutdown::is_requested() && !reconnect {
+                    let frame = stream.frame()?;
+                    metrics.frame(frame.data.len());
+                    service.publish(frame);
+                    reconnect = service.take_reconnect();
+                }
+
+                // Keep-alive expired, service stopped or shutdown requested
+                stream.close()?;
+                if !reconnect {
+                    return Ok(());
+                }
+
+                // Record the session end and start a new session
+                log::info("Device reconnecting", &[("device", device.as_str().into())]);
+                metrics.ended(connected, false);
+                metrics = DeviceHandle::start(&service.key);
+                connected = false;
             }
+        };
 
-            // Keep-alive expired
-            Ok(())
+        // Run fallible code and determine why the service has ended before marking it as terminated
+        let result = try_catch();
+        metrics.ended(connected, result.is_err());
+        let reason = match (&result, shutdown::is_requested(), service.state().stopped) {
+            (Err(_), ..) => "failed",
+            (Ok(()), true, _) => "shutdown",
+            (Ok(()), false, true) => "stopped",
+            (Ok(()), false, false) => "idle",
         };
+        service.terminate();
 
-        // Run fallible code
-        try_catch().expect("Image service terminated");
+        // Log why the service has ended
+        let fields = [("device", device.as_str().into()), ("reason", reason.into())];
+        match result {
+            Err(e) => error!(with: e, "Device service failed").log_with(Level::Warn, &fields),
+            Ok(()) => log::info("Device service ended", &fields),
+        }
     }
 }
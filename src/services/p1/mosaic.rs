@@ -0,0 +1,250 @@
+//! A virtual camera source that composes the latest frames of several device services into one grid
+
+use crate::{
+    error,
+    error::Error,
+    log::Level,
+    services::{
+        camera::{Frame, Source, Stream},
+        image::{font, transform::Transform, Canvas},
+        metrics,
+        p1::P1Service,
+    },
+};
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The prefix of the registry keys of mosaic services
+pub const KEY_PREFIX: &str = "mosaic+";
+
+/// Stops all mosaic services
+///
+/// # Note
+/// Mosaics start the services of their devices with the device config from when the mosaic was created, so they must
+/// be stopped if a device changes; clients then have to request the mosaic again.
+pub fn stop_all() {
+    let services = P1Service::list();
+    for service in services.iter().filter(|service| service.key().starts_with(KEY_PREFIX)) {
+        P1Service::stop(service.key());
+    }
+}
+
+/// A device within the mosaic
+#[derive(Debug, Clone)]
+pub struct MosaicTile {
+    /// The device name for the label
+    pub name: String,
+    /// The key of the device service in the service registry
+    pub key: String,
+    /// The camera source to start the device service
+    pub source: Arc<dyn Source>,
+    /// The configured transform of the device
+    pub transform: Transform,
+}
+
+/// A camera source that renders a labelled grid of device frames
+#[derive(Debug, Clone)]
+pub struct MosaicSource {
+    /// The devices in row-major order
+    tiles: Vec<MosaicTile>,
+    /// The amount of columns and rows
+    layout: (usize, usize),
+    /// The width and height of a tile including its label
+    tile_size: (usize, usize),
+}
+impl MosaicSource {
+    /// The default tile size
+    pub const DEFAULT_TILE_SIZE: (usize, usize) = (640, 360);
+    /// The maximum amount of tiles
+    const MAX_TILES: usize = 64;
+    /// The minimum tile width and height
+    const MIN_TILE_SIZE: (usize, usize) = (160, 90);
+    /// The maximum tile width and height
+    const MAX_TILE_SIZE: (usize, usize) = (1920, 1080);
+    /// The maximum mosaic width and height
+    const MAX_SIZE: (usize, usize) = (7680, 4320);
+    /// The gap between and around the tiles
+    const GAP: usize = 4;
+
+    /// Creates a new mosaic for the given devices
+    ///
+    /// # Note
+    /// If no layout is given, the tiles are arranged in a grid that is about as wide as it is high.
+    pub fn new(
+        tiles: Vec<MosaicTile>,
+        layout: Option<(usize, usize)>,
+        tile_size: (usize, usize),
+    ) -> Result<Self, Error> {
+        // Validate the amount of tiles and the layout
+        if tiles.is_empty() || tiles.len() > Self::MAX_TILES {
+            return Err(error!("Invalid amount of mosaic tiles: {}", tiles.len()));
+        }
+        let layout = layout.unwrap_or_else(|| {
+            let columns = (1..).find(|columns: &usize| columns.saturating_mul(*columns) >= tiles.len()).unwrap_or(1);
+            (columns, tiles.len().div_ceil(columns))
+        });
+        if layout.0.saturating_mul(layout.1) < tiles.len() {
+            return Err(error!("Mosaic layout {}x{} is too small for {} tiles", layout.0, layout.1, tiles.len()));
+        }
+
+        // Validate the tile and mosaic size
+        let (min, max) = (Self::MIN_TILE_SIZE, Self::MAX_TILE_SIZE);
+        if !(min.0..=max.0).contains(&tile_size.0) || !(min.1..=max.1).contains(&tile_size.1) {
+            return Err(error!("Invalid mosaic tile size: {}x{}", tile_size.0, tile_size.1));
+        }
+        let this = Self { tiles, layout, tile_size };
+        let (width, height) = this.size();
+        if width > Self::MAX_SIZE.0 || height > Self::MAX_SIZE.1 {
+            return Err(error!("Mosaic size {width}x{height} is too large"));
+        }
+        Ok(this)
+    }
+
+    /// The width and height of the mosaic
+    fn size(&self) -> (usize, usize) {
+        let extent =
+            |count: usize, size: usize| count.saturating_mul(size.saturating_add(Self::GAP)).saturating_add(Self::GAP);
+        (extent(self.layout.0, self.tile_size.0), extent(self.layout.1, self.tile_size.1))
+    }
+}
+impl Source for MosaicSource {
+    fn content_type(&self) -> &'static str {
+        "image/jpeg"
+    }
+
+    fn connect(&self) -> Result<Box<dyn Stream>, Error> {
+        let images = vec![None; self.tiles.len()];
+        Ok(Box::new(MosaicStream { source: self.clone(), images, last_frame: None }))
+    }
+}
+
+/// A frame stream that renders the mosaic once per frame duration
+#[derive(Debug)]
+struct MosaicStream {
+    /// The mosaic to render
+    source: MosaicSource,
+    /// The last device frame and its scaled image for each tile, so that unchanged frames are not decoded again
+    images: Vec<Option<(Arc<Vec<u8>>, Canvas)>>,
+    /// When the last frame has been rendered
+    last_frame: Option<Instant>,
+}
+impl MosaicStream {
+    /// The duration of a single frame
+    const FRAME_DURATION: Duration = Duration::from_secs(1);
+    /// The JPEG quality of the mosaic
+    const QUALITY: u8 = 80;
+    /// The frame age from which the age is shown in the label
+    const STALE_AGE: Duration = Duration::from_secs(5);
+    /// The height of the label bar
+    const LABEL_HEIGHT: usize = 20;
+    /// The background color
+    const BACKGROUND: [u8; 3] = [30, 30, 30];
+    /// The label bar color
+    const LABEL_BACKGROUND: [u8; 3] = [15, 15, 15];
+    /// The label text color
+    const LABEL_COLOR: [u8; 3] = [255, 255, 255];
+
+    /// The status color for the given upstream state
+    fn status_color(state: &str) -> [u8; 3] {
+        match state {
+            "connected" => [60, 200, 90],
+            "connecting" => [230, 180, 40],
+            "failed" => [220, 70, 60],
+            _ => [140, 140, 140],
+        }
+    }
+
+    /// Gets the latest frame of the given tile, transformed and scaled to fit into the given size
+    fn image(&mut self, index: usize, frame: Frame, transform: &Transform, size: (usize, usize)) -> Option<&Canvas> {
+        // Decode and scale the frame only if it has changed
+        let image = self.images.get_mut(index)?;
+        if !image.as_ref().is_some_and(|(data, _)| Arc::ptr_eq(data, &frame.data)) {
+            let canvas = match transform.decode(&frame.data, Some(size)) {
+                Ok(canvas) => canvas,
+                Err(e) => {
+                    // Keep the tile empty if the frame cannot be decoded
+                    e.log_as(Level::Debug);
+                    return None;
+                }
+            };
+
+            // Fit the image into the tile while keeping its aspect ratio
+            let (width, height) = (canvas.width(), canvas.height());
+            let (fit_width, fit_height) = match width.saturating_mul(size.1) <= size.0.saturating_mul(height) {
+                true => (width.saturating_mul(size.1).checked_div(height).unwrap_or(size.0), size.1),
+                false => (size.0, height.saturating_mul(size.0).checked_div(width).unwrap_or(size.1)),
+            };
+            *image = Some((frame.data, canvas.resized(fit_width, fit_height)));
+        }
+        image.as_ref().map(|(_, canvas)| canvas)
+    }
+
+    /// Renders the tile with the given index with its top-left corner at `x`/`y`
+    fn render_tile(&mut self, canvas: &mut Canvas, index: usize, x: usize, y: usize) {
+        /// The label text scale
+        const SCALE: usize = 2;
+
+        // Get the device service and its latest frame; this also keeps the service alive
+        let Some(tile) = self.source.tiles.get(index).cloned() else {
+            return;
+        };
+        let service = P1Service::get_or_start(&tile.key, || P1Service::start(&tile.key, tile.source.clone()));
+        let frame = service.latest_frame(None, Duration::ZERO).map(|(_, frame)| frame);
+
+        // Draw the centered image
+        let (width, height) = self.source.tile_size;
+        let image_height = height.saturating_sub(Self::LABEL_HEIGHT);
+        let image = frame.filter(|frame| frame.content_type == "image/jpeg");
+        if let Some(image) = image.and_then(|frame| self.image(index, frame, &tile.transform, (width, image_height))) {
+            let image_x = x.saturating_add(width.saturating_sub(image.width()) / 2);
+            let image_y = y.saturating_add(image_height.saturating_sub(image.height()) / 2);
+            canvas.draw(image_x, image_y, image);
+        }
+
+        // Describe the device state
+        let status = metrics::device_status(&tile.key);
+        let state = status.map(|status| status.state).unwrap_or("idle");
+        let mut label = format!("{} {state}", tile.name);
+        if let Some(frame_age) = status.and_then(|status| status.frame_age).filter(|age| *age >= Self::STALE_AGE) {
+            label.push_str(&format!(" {}s ago", frame_age.as_secs()));
+        }
+
+        // Draw the label bar with a status indicator
+        let label_y = y.saturating_add(image_height);
+        let text_y =
+            label_y.saturating_add(Self::LABEL_HEIGHT.saturating_sub(font::GLYPH_HEIGHT.saturating_mul(SCALE)) / 2);
+        canvas.fill_rect(x, label_y, width, Self::LABEL_HEIGHT, Self::LABEL_BACKGROUND);
+        canvas.fill_rect(x.saturating_add(6), label_y.saturating_add(6), 8, 8, Self::status_color(state));
+        let max_chars =
+            width.saturating_sub(26).checked_div(font::GLYPH_ADVANCE.saturating_mul(SCALE)).unwrap_or_default();
+        let label: String = label.chars().take(max_chars).collect();
+        canvas.text(x.saturating_add(20), text_y, SCALE, Self::LABEL_COLOR, &label);
+    }
+}
+impl Stream for MosaicStream {
+    fn frame(&mut self) -> Result<Frame, Error> {
+        // Pause for the remaining frame duration
+        if let Some(last_frame) = self.last_frame {
+            let remaining = Self::FRAME_DURATION.saturating_sub(last_frame.elapsed());
+            thread::sleep(remaining);
+        }
+        self.last_frame = Some(Instant::now());
+
+        // Render the tiles in row-major order
+        let (width, height) = self.source.size();
+        let mut canvas = Canvas::new(width, height, Self::BACKGROUND);
+        let (tile_width, tile_height) = self.source.tile_size;
+        for index in 0..self.source.tiles.len() {
+            let columns = self.source.layout.0;
+            let (column, row) = (index.checked_rem(columns).unwrap_or(0), index.checked_div(columns).unwrap_or(0));
+            let x =
+                MosaicSource::GAP.saturating_add(column.saturating_mul(tile_width.saturating_add(MosaicSource::GAP)));
+            let y = MosaicSource::GAP.saturating_add(row.saturating_mul(tile_height.saturating_add(MosaicSource::GAP)));
+            self.render_tile(&mut canvas, index, x, y);
+        }
+        Ok(Frame::jpeg(canvas.to_jpeg(Self::QUALITY)?))
+    }
+}
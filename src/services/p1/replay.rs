@@ -0,0 +1,103 @@
+//! A virtual camera source that replays a recorded P1 session
+
+use crate::{
+    error,
+    error::Error,
+    services::{
+        camera::{Frame, Source, Stream},
+        p1::capture::CaptureReader,
+    },
+};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A camera source that replays a capture file in a loop
+#[derive(Debug, Clone)]
+pub struct ReplaySource {
+    /// The path to the capture file
+    path: PathBuf,
+    /// The playback speed factor
+    speed: f64,
+}
+impl ReplaySource {
+    /// The default playback speed (real time)
+    pub const DEFAULT_SPEED: f64 = 1.0;
+    /// The maximum playback speed
+    const MAX_SPEED: f64 = 100.0;
+
+    /// Creates a new replay source for the given capture file
+    ///
+    /// # Note
+    /// A speed of `1.0` plays the capture in real time; higher values play it back faster.
+    pub fn new<T>(path: T, speed: f64) -> Result<Self, Error>
+    where
+        T: AsRef<Path>,
+    {
+        // Validate the playback speed
+        if !(Self::DEFAULT_SPEED..=Self::MAX_SPEED).contains(&speed) {
+            return Err(error!("Invalid playback speed: {speed}"));
+        }
+        Ok(Self { path: path.as_ref().to_path_buf(), speed })
+    }
+
+    /// Opens the capture file
+    fn open(&self) -> Result<CaptureReader<BufReader<File>>, Error> {
+        let file = File::open(&self.path)?;
+        CaptureReader::new(BufReader::new(file))
+    }
+}
+impl Source for ReplaySource {
+    fn content_type(&self) -> &'static str {
+        "image/jpeg"
+    }
+
+    fn connect(&self) -> Result<Box<dyn Stream>, Error> {
+        let reader = self.open()?;
+        Ok(Box::new(ReplayStream { source: self.clone(), reader, start: Instant::now(), offset: None, frames: 0 }))
+    }
+}
+
+/// A frame stream that replays a capture file with its original timing
+#[derive(Debug)]
+struct ReplayStream {
+    /// The source to reopen the capture file
+    source: ReplaySource,
+    /// The capture reader
+    reader: CaptureReader<BufReader<File>>,
+    /// The start of the current playback loop
+    start: Instant,
+    /// The timestamp of the first record of the current playback loop
+    offset: Option<Duration>,
+    /// The amount of frames in the current playback loop
+    frames: u64,
+}
+impl Stream for ReplayStream {
+    fn frame(&mut self) -> Result<Frame, Error> {
+        // Get the next record and restart the playback at the end of the capture
+        let record = match self.reader.record()? {
+            Some(record) => record,
+            None if self.frames == 0 => return Err(error!("Capture file is empty")),
+            None => {
+                // Rewind the capture
+                self.reader = self.source.open()?;
+                self.start = Instant::now();
+                (self.offset, self.frames) = (None, 0);
+                self.reader.record()?.ok_or_else(|| error!("Capture file is empty"))?
+            }
+        };
+
+        // Wait until the scaled receive time of the record
+        let offset = *self.offset.get_or_insert(record.timestamp);
+        let elapsed = record.timestamp.saturating_sub(offset).div_f64(self.source.speed);
+        thread::sleep(elapsed.saturating_sub(self.start.elapsed()));
+
+        // Yield the frame
+        self.frames = self.frames.saturating_add(1);
+        Ok(Frame::jpeg(record.payload))
+    }
+}
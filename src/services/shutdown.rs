@@ -0,0 +1,88 @@
+//! Graceful shutdown on `SIGTERM` and `SIGINT`
+//!
+//! Once a shutdown is requested, listeners stop accepting connections, device runloops close their sessions and
+//! streams end with a final boundary. Long-running work holds an [`Activity`], so the shutdown can wait until all
+//! activities have finished or the grace period has expired.
+
+use crate::error::Error;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, LazyLock, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+/// Whether a shutdown has been requested
+static REQUESTED: LazyLock<Arc<AtomicBool>> = LazyLock::new(|| Arc::new(AtomicBool::new(false)));
+/// The amount of running activities
+static ACTIVE: Mutex<usize> = Mutex::new(0);
+/// Signals that an activity has finished
+static FINISHED: Condvar = Condvar::new();
+
+/// Requests a shutdown on `SIGTERM` and `SIGINT`
+///
+/// # Note
+/// A second signal terminates the process immediately with exit status `1`.
+pub fn register_signals() -> Result<(), Error> {
+    for signal in [SIGTERM, SIGINT] {
+        // Register the immediate termination first, so that it only triggers if the flag has already been set
+        signal_hook::flag::register_conditional_shutdown(signal, 1, REQUESTED.clone())?;
+        signal_hook::flag::register(signal, REQUESTED.clone())?;
+    }
+    Ok(())
+}
+
+/// Whether a shutdown has been requested
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Waits until all activities have finished or the timeout has been reached and returns the amount of activities that
+/// are still running
+pub fn wait_idle(timeout: Duration) -> usize {
+    let deadline = Instant::now().checked_add(timeout);
+    let mut active = active();
+    loop {
+        // Check whether we are done
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if *active == 0 || remaining.is_some_and(|remaining| remaining.is_zero()) {
+            return *active;
+        }
+
+        // Wait for the next activity to finish
+        let remaining = remaining.unwrap_or(timeout);
+        let (active_, _) = FINISHED.wait_timeout(active, remaining).unwrap_or_else(PoisonError::into_inner);
+        active = active_;
+    }
+}
+
+/// Locks the amount of running activities
+///
+/// # Note
+/// Since the counter is also updated during unwinding, a poisoned mutex is recovered instead of panicking again.
+fn active() -> MutexGuard<'static, usize> {
+    ACTIVE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A running activity that delays the shutdown until it is dropped
+#[derive(Debug)]
+pub struct Activity {
+    _private: (),
+}
+impl Activity {
+    /// Registers a new running activity
+    pub fn start() -> Self {
+        let mut active = active();
+        *active = active.saturating_add(1);
+        Self { _private: () }
+    }
+}
+impl Drop for Activity {
+    fn drop(&mut self) {
+        let mut active = active();
+        *active = active.saturating_sub(1);
+        FINISHED.notify_all();
+    }
+}
@@ -0,0 +1,150 @@
+//! systemd socket activation, readiness notifications and the service watchdog
+//!
+//! # Note
+//! All functions are no-ops if the process is not started by systemd or the platform is not a Unix platform.
+
+use crate::{
+    error,
+    error::Error,
+    services::listener::{Probe, Socket},
+};
+use std::{env, process, thread, time::Duration};
+
+/// A readiness notification: the server is accepting connections
+pub const READY: &str = "READY=1";
+/// A watchdog keepalive notification
+pub const WATCHDOG: &str = "WATCHDOG=1";
+/// A shutdown notification
+pub const STOPPING: &str = "STOPPING=1";
+
+/// Gets the sockets passed via socket activation (i.e. `LISTEN_FDS`)
+pub fn listen_fds() -> Result<Vec<Socket>, Error> {
+    // Check whether the sockets are meant for us
+    let Some(count) = env_for_us("LISTEN_FDS", "LISTEN_PID")? else {
+        return Ok(Vec::new());
+    };
+    let count: i32 = count.parse()?;
+
+    // Take ownership of the passed file descriptors
+    #[cfg(unix)]
+    {
+        /// The first file descriptor passed via socket activation
+        const LISTEN_FDS_START: i32 = 3;
+
+        let end = LISTEN_FDS_START.checked_add(count).ok_or_else(|| error!("Invalid LISTEN_FDS: {count}"))?;
+        (LISTEN_FDS_START..end).map(socket_from_fd).collect()
+    }
+    #[cfg(not(unix))]
+    match count {
+        0 => Ok(Vec::new()),
+        _ => Err(error!("Socket activation is not supported on this platform")),
+    }
+}
+
+/// Takes ownership of the given socket-activated file descriptor
+#[cfg(unix)]
+fn socket_from_fd(raw_fd: i32) -> Result<Socket, Error> {
+    use std::{
+        net::TcpListener,
+        os::{
+            fd::{FromRawFd, OwnedFd},
+            unix::net::UnixListener,
+        },
+    };
+
+    // SAFETY: systemd passes the file descriptors `3..3 + LISTEN_FDS` to us, and they are taken exactly once
+    let fd = unsafe { OwnedFd::from_raw_fd(raw_fd) };
+
+    // Check the socket family via the local address
+    let socket = UnixListener::from(fd);
+    if socket.local_addr().is_ok() {
+        socket.set_nonblocking(false)?;
+        return Ok(Socket::Unix(socket));
+    }
+    let socket = TcpListener::from(OwnedFd::from(socket));
+    match socket.local_addr() {
+        Ok(_) => {
+            socket.set_nonblocking(false)?;
+            Ok(Socket::Tcp(socket))
+        }
+        Err(e) => Err(error!(with: e, "Socket-activated file descriptor {raw_fd} is neither a TCP nor a Unix socket")),
+    }
+}
+
+/// Sends a state notification to the service manager (i.e. `NOTIFY_SOCKET`)
+pub fn notify(state: &str) -> Result<(), Error> {
+    // Check whether we are supervised
+    let Some(path) = env_opt("NOTIFY_SOCKET")? else {
+        return Ok(());
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::net::UnixDatagram;
+
+        // Send the notification to a named or an abstract socket
+        let socket = UnixDatagram::unbound()?;
+        #[cfg(target_os = "linux")]
+        if let Some(name) = path.strip_prefix('@') {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            let address = SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &address)?;
+            return Ok(());
+        }
+        socket.send_to(state.as_bytes(), path)?;
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    Err(error!("Cannot notify {path} about {state}: not supported on this platform"))
+}
+
+/// Sends a state notification to the service manager and logs the error if any
+pub fn notify_or_log(state: &str) {
+    if let Err(e) = notify(state) {
+        error!(with: e, "Failed to notify the service manager about {state}").log();
+    }
+}
+
+/// Starts the watchdog keepalive thread if the service manager expects keepalives (i.e. `WATCHDOG_USEC`)
+///
+/// # Note
+/// The keepalives are only sent if all listeners pass their health checks, so a stuck server gets restarted.
+pub fn spawn_watchdog(probes: Vec<Probe>) -> Result<(), Error> {
+    // Check whether the watchdog is enabled
+    let Some(usec) = env_for_us("WATCHDOG_USEC", "WATCHDOG_PID")? else {
+        return Ok(());
+    };
+
+    // Send keepalives at half the watchdog interval and give each check a quarter of the interval
+    let watchdog = Duration::from_micros(usec.parse()?);
+    let interval = watchdog.checked_div(2).unwrap_or_default();
+    let timeout = watchdog.checked_div(4).unwrap_or_default();
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        match probes.iter().try_for_each(|probe| probe.check(timeout)) {
+            Ok(_) => notify_or_log(WATCHDOG),
+            Err(e) => error!(with: e, "Health check failed; skipping watchdog keepalive").log(),
+        }
+    });
+    Ok(())
+}
+
+/// Gets an optional environment variable that is only valid if the associated PID variable matches our PID
+fn env_for_us(name: &str, pid_name: &str) -> Result<Option<String>, Error> {
+    let Some(value) = env_opt(name)? else {
+        return Ok(None);
+    };
+    match env_opt(pid_name)? {
+        Some(pid) if pid.parse::<u32>()? != process::id() => Ok(None),
+        _ => Ok(Some(value)),
+    }
+}
+
+/// Gets an optional environment variable
+fn env_opt(name: &str) -> Result<Option<String>, Error> {
+    match env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(error!(with: e, "Invalid environment variable {name}")),
+    }
+}
@@ -0,0 +1,195 @@
+//! Server-side TLS for the selected TLS backend
+
+use crate::{error, error::Error};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+/// A server-side TLS stream
+#[cfg(not(feature = "rustls"))]
+pub type TlsStream = native_tls::TlsStream<TcpStream>;
+/// A server-side TLS stream
+#[cfg(feature = "rustls")]
+pub type TlsStream = rustls::StreamOwned<rustls::ServerConnection, TcpStream>;
+
+/// A TLS acceptor
+#[cfg(not(feature = "rustls"))]
+pub struct Acceptor {
+    /// The native TLS acceptor
+    acceptor: native_tls::TlsAcceptor,
+}
+#[cfg(not(feature = "rustls"))]
+impl Acceptor {
+    /// Creates a new acceptor from a PEM certificate (chain) and a PEM PKCS#8 private key
+    pub fn new(cert: &[u8], key: &[u8]) -> Result<Self, Error> {
+        let identity = native_tls::Identity::from_pkcs8(cert, key)?;
+        let acceptor = native_tls::TlsAcceptor::new(identity)?;
+        Ok(Self { acceptor })
+    }
+
+    /// Performs the server-side TLS handshake
+    pub fn accept(&self, stream: TcpStream) -> Result<TlsStream, Error> {
+        Ok(self.acceptor.accept(stream)?)
+    }
+}
+
+/// A TLS acceptor
+#[cfg(feature = "rustls")]
+pub struct Acceptor {
+    /// The server config
+    config: Arc<rustls::ServerConfig>,
+}
+#[cfg(feature = "rustls")]
+impl Acceptor {
+    /// Creates a new acceptor from a PEM certificate (chain) and a PEM PKCS#8 private key
+    pub fn new(cert: &[u8], key: &[u8]) -> Result<Self, Error> {
+        use rustls::{
+            crypto::ring,
+            pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+            version::{TLS12, TLS13},
+            ServerConfig,
+        };
+
+        // Parse the identity
+        let certs = CertificateDer::pem_slice_iter(cert).collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_slice(key)?;
+
+        // Create the server config with TLS 1.2 as minimum version
+        let provider = Arc::new(ring::default_provider());
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&TLS13, &TLS12])?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        Ok(Self { config: Arc::new(config) })
+    }
+
+    /// Performs the server-side TLS handshake
+    pub fn accept(&self, mut stream: TcpStream) -> Result<TlsStream, Error> {
+        let mut connection = rustls::ServerConnection::new(self.config.clone())?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut stream)?;
+        }
+        Ok(rustls::StreamOwned::new(connection, stream))
+    }
+}
+
+/// The currently loaded identity of a reloading acceptor
+struct LoadedIdentity {
+    /// The modification times of the certificate and the key file
+    modified: (Option<SystemTime>, Option<SystemTime>),
+    /// The acceptor for the loaded identity
+    acceptor: Arc<Acceptor>,
+}
+
+/// A TLS acceptor that reloads the certificate and key files if they change
+pub struct ReloadingAcceptor {
+    /// The path to the PEM certificate (chain)
+    cert: PathBuf,
+    /// The path to the PEM PKCS#8 private key
+    key: PathBuf,
+    /// The currently loaded identity
+    loaded: Mutex<LoadedIdentity>,
+}
+impl ReloadingAcceptor {
+    /// Creates a new acceptor and loads the initial identity
+    pub fn new<T, U>(cert: T, key: U) -> Result<Self, Error>
+    where
+        T: AsRef<Path>,
+        U: AsRef<Path>,
+    {
+        // Load the initial identity
+        let (cert, key) = (cert.as_ref().to_path_buf(), key.as_ref().to_path_buf());
+        let modified = Self::modified(&cert, &key);
+        let acceptor = Arc::new(Self::load(&cert, &key)?);
+        Ok(Self { cert, key, loaded: Mutex::new(LoadedIdentity { modified, acceptor }) })
+    }
+
+    /// Gets the acceptor for the current identity and reloads the identity if the files have changed
+    ///
+    /// # Note
+    /// If the new identity cannot be loaded, the error is logged and the previous identity is kept until the files
+    /// change again.
+    pub fn acceptor(&self) -> Arc<Acceptor> {
+        // Check whether the files have changed
+        let mut loaded = self.loaded();
+        let modified = Self::modified(&self.cert, &self.key);
+        if modified == loaded.modified {
+            return loaded.acceptor.clone();
+        }
+
+        // Reload the identity
+        loaded.modified = modified;
+        match Self::load(&self.cert, &self.key) {
+            Ok(acceptor) => loaded.acceptor = Arc::new(acceptor),
+            Err(e) => error!(with: e, "Failed to reload TLS certificate; keeping the previous one").log(),
+        }
+        loaded.acceptor.clone()
+    }
+
+    /// Loads the identity from the given files
+    fn load(cert: &Path, key: &Path) -> Result<Acceptor, Error> {
+        let cert = fs::read(cert)?;
+        let key = fs::read(key)?;
+        Acceptor::new(&cert, &key)
+    }
+
+    /// Gets the modification times of the given files
+    fn modified(cert: &Path, key: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+        let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+        (modified(cert), modified(key))
+    }
+
+    /// Locks the loaded identity
+    fn loaded(&self) -> MutexGuard<'_, LoadedIdentity> {
+        #[allow(clippy::expect_used, reason = "Locking a mutex should never fail under normal conditions")]
+        self.loaded.lock().expect("Failed to lock mutex")
+    }
+}
+
+/// A stream that can be shared between a reading and a writing half
+///
+/// # Note
+/// TLS streams cannot be split, so both halves lock the stream. This is fine for HTTP/1.1 since requests and responses
+/// are never read and written at the same time.
+#[derive(Debug)]
+pub struct SharedStream<T> {
+    /// The underlying stream
+    stream: Arc<Mutex<T>>,
+}
+impl<T> SharedStream<T> {
+    /// Creates a reading and a writing half for the given stream
+    pub fn split(stream: T) -> (Self, Self) {
+        let stream = Arc::new(Mutex::new(stream));
+        (Self { stream: stream.clone() }, Self { stream })
+    }
+
+    /// Locks the underlying stream
+    fn lock(&self) -> io::Result<MutexGuard<'_, T>> {
+        self.stream.lock().map_err(|_| io::Error::other("Poisoned stream mutex"))
+    }
+}
+impl<T> Read for SharedStream<T>
+where
+    T: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock()?.read(buf)
+    }
+}
+impl<T> Write for SharedStream<T>
+where
+    T: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock()?.flush()
+    }
+}
@@ -0,0 +1,54 @@
+//! A camera source for X1/X1C devices
+
+mod rtsp;
+
+use crate::{
+    error::Error,
+    secret::Secret,
+    services::{
+        camera::{Frame, Source, Stream},
+        x1::rtsp::{RtspConnection, RtspSession},
+    },
+};
+use std::sync::Arc;
+
+/// A camera source for an X1/X1C device
+///
+/// # Note
+/// X1 devices stream their camera as H.264 via RTSP over TLS (usually on port `322`); the access units are relayed as-is
+/// without decoding.
+#[derive(Debug, Clone)]
+pub struct X1Source {
+    /// The device address
+    address: String,
+    /// The device PIN
+    pin: Secret,
+}
+impl X1Source {
+    /// Creates a new X1 camera source
+    pub fn new(address: &str, pin: &Secret) -> Self {
+        Self { address: address.to_string(), pin: pin.clone() }
+    }
+}
+impl Source for X1Source {
+    fn content_type(&self) -> &'static str {
+        "video/h264"
+    }
+
+    fn connect(&self) -> Result<Box<dyn Stream>, Error> {
+        // Setup connection
+        let connection = RtspConnection::new(&self.address)?;
+        let session = connection.login(&self.pin)?;
+        Ok(Box::new(session))
+    }
+
+    fn with_pin(&self, pin: &Secret) -> Option<Arc<dyn Source>> {
+        Some(Arc::new(Self { pin: pin.clone(), ..self.clone() }))
+    }
+}
+impl Stream for RtspSession {
+    fn frame(&mut self) -> Result<Frame, Error> {
+        let (access_unit, keyframe) = self.access_unit()?;
+        Ok(Frame::h264(access_unit, keyframe))
+    }
+}
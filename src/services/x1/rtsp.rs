@@ -0,0 +1,886 @@
+//! An RTSP-over-TLS connection to an X1 device
+
+use crate::{error, error::Error, secret::Secret};
+use bamborvideostream_p1::{
+    tls::{self, TlsStream},
+    ConnectOptions,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use md5::{Digest, Md5};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    str,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The Annex B start code which prefixes each NAL unit
+const START_CODE: &[u8] = &[0x00, 0x00, 0x00, 0x01];
+
+/// An RTSP response
+#[derive(Debug)]
+struct RtspResponse {
+    /// The status code
+    status: u16,
+    /// The header fields
+    fields: Vec<(String, String)>,
+    /// The body
+    body: Vec<u8>,
+}
+impl RtspResponse {
+    /// Gets the first header field with the given name
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+/// An authentication challenge sent by the device
+#[derive(Debug, Clone)]
+enum Challenge {
+    /// HTTP basic authentication
+    Basic,
+    /// HTTP digest authentication
+    Digest {
+        /// The realm
+        realm: String,
+        /// The server nonce
+        nonce: String,
+        /// Whether the server requested `qop=auth`
+        qop: bool,
+    },
+}
+impl Challenge {
+    /// Parses an authentication challenge from the `WWW-Authenticate` header fields
+    fn parse<'a, I>(fields: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut basic = None;
+        for field in fields {
+            // Prefer digest over basic authentication
+            let (scheme, params) = field.split_once(' ').unwrap_or((field, ""));
+            if scheme.eq_ignore_ascii_case("Digest") {
+                let realm = Self::param(params, "realm")?;
+                let nonce = Self::param(params, "nonce")?;
+                let qop = Self::param(params, "qop").is_some_and(|qop| qop.split(',').any(|qop| qop.trim() == "auth"));
+                return Some(Self::Digest { realm, nonce, qop });
+            }
+            if scheme.eq_ignore_ascii_case("Basic") {
+                basic = Some(Self::Basic);
+            }
+        }
+        basic
+    }
+
+    /// Gets a parameter from a comma separated `key=value` or `key="value"` list
+    fn param(params: &str, name: &str) -> Option<String> {
+        for param in params.split(',') {
+            let Some((key, value)) = param.split_once('=') else {
+                continue;
+            };
+            if key.trim().eq_ignore_ascii_case(name) {
+                return Some(value.trim().trim_matches('"').to_string());
+            }
+        }
+        None
+    }
+
+    /// Creates the `Authorization` header value for the given request
+    fn authorization(&self, username: &str, password: &str, method: &str, url: &str, nc: u32) -> String {
+        match self {
+            Self::Basic => {
+                let credentials = BASE64.encode(format!("{username}:{password}"));
+                format!("Basic {credentials}")
+            }
+            Self::Digest { realm, nonce, qop: false } => {
+                let ha1 = Self::md5(&format!("{username}:{realm}:{password}"));
+                let ha2 = Self::md5(&format!("{method}:{url}"));
+                let response = Self::md5(&format!("{ha1}:{nonce}:{ha2}"));
+                format!(
+                    r#"Digest username="{username}", realm="{realm}", nonce="{nonce}", uri="{url}", response="{response}""#
+                )
+            }
+            Self::Digest { realm, nonce, qop: true } => {
+                // Derive a client nonce from the current time
+                let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                let cnonce = Self::md5(&format!("{}:{nonce}", time.as_nanos()));
+
+                // Compute the response
+                let ha1 = Self::md5(&format!("{username}:{realm}:{password}"));
+                let ha2 = Self::md5(&format!("{method}:{url}"));
+                let response = Self::md5(&format!("{ha1}:{nonce}:{nc:08x}:{cnonce}:auth:{ha2}"));
+                format!(
+                    r#"Digest username="{username}", realm="{realm}", nonce="{nonce}", uri="{url}", qop=auth, nc={nc:08x}, cnonce="{cnonce}", response="{response}""#
+                )
+            }
+        }
+    }
+
+    /// Computes the lowercase hex MD5 digest of the given string
+    fn md5(input: &str) -> String {
+        format!("{:x}", Md5::digest(input.as_bytes()))
+    }
+}
+
+/// The negotiated H.264 video track
+#[derive(Debug, Clone)]
+struct VideoTrack {
+    /// The control URL of the track
+    control: String,
+    /// The out-of-band parameter sets (SPS/PPS) from the session description in Annex B format
+    parameter_sets: Vec<u8>,
+}
+impl VideoTrack {
+    /// Parses the H.264 video track from a session description
+    fn from_sdp(sdp: &str, base: &str) -> Result<Self, Error> {
+        let (mut in_video, mut is_h264) = (false, false);
+        let (mut control, mut parameter_sets) = (None, Vec::new());
+        for line in sdp.lines().map(str::trim) {
+            // Track the current media section
+            if let Some(media) = line.strip_prefix("m=") {
+                in_video = media.starts_with("video ") && control.is_none();
+                continue;
+            }
+            if !in_video {
+                continue;
+            }
+
+            // Parse the video attributes
+            if let Some(rtpmap) = line.strip_prefix("a=rtpmap:") {
+                is_h264 |= rtpmap.to_ascii_uppercase().contains(" H264/");
+            } else if let Some(control_) = line.strip_prefix("a=control:") {
+                control = Some(Self::resolve(base, control_));
+            } else if let Some((_, sprop)) =
+                line.strip_prefix("a=fmtp:").and_then(|f| f.split_once("sprop-parameter-sets="))
+            {
+                // Decode the parameter sets
+                let sprop = sprop.split(';').next().unwrap_or_default();
+                for parameter_set in sprop.split(',').filter(|p| !p.is_empty()) {
+                    let parameter_set =
+                        BASE64.decode(parameter_set.trim()).map_err(|e| error!(with: e, "Invalid SDP"))?;
+                    parameter_sets.extend_from_slice(START_CODE);
+                    parameter_sets.extend_from_slice(&parameter_set);
+                }
+            }
+        }
+
+        // Validate the video track
+        let (true, Some(control)) = (is_h264, control) else {
+            return Err(error!("Device does not offer an H.264 video track"));
+        };
+        Ok(Self { control, parameter_sets })
+    }
+
+    /// Resolves a control URL against the base URL
+    fn resolve(base: &str, control: &str) -> String {
+        match control {
+            "*" => base.to_string(),
+            control if control.starts_with("rtsp://") || control.starts_with("rtsps://") => control.to_string(),
+            control => format!("{}/{}", base.trim_end_matches('/'), control.trim_start_matches('/')),
+        }
+    }
+}
+
+/// An RTSP-over-TLS connection to an X1 device
+#[derive(Debug)]
+pub struct RtspConnection<S = TlsStream> {
+    /// The TLS connection
+    connection: BufReader<S>,
+    /// The stream URL
+    url: String,
+    /// The sequence number of the next request
+    cseq: u32,
+}
+impl RtspConnection {
+    /// The default timeout
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Creates a new connection to an X1 device
+    pub fn new(address: &str) -> Result<Self, Error> {
+        // Connect to the device
+        let connection = TcpStream::connect(address)?;
+        connection.set_read_timeout(Some(Self::DEFAULT_TIMEOUT))?;
+        connection.set_write_timeout(Some(Self::DEFAULT_TIMEOUT))?;
+
+        // Create a TLS stream from the TCP connection; devices use self-signed certificates
+        let (host, _) = address.rsplit_once(':').unwrap_or((address, ""));
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let connection = tls::connect(connection, host, &ConnectOptions::default())?;
+
+        Ok(Self::with_stream(connection, address))
+    }
+}
+impl<S> RtspConnection<S>
+where
+    S: Read + Write,
+{
+    /// The default stream path
+    const STREAM_PATH: &'static str = "/streaming/live/1";
+    /// The username for the LAN mode access
+    const USERNAME: &'static str = "bblp";
+    /// The maximum length of a single response line or body
+    const LIMIT: u64 = 64 * 1024;
+    /// The session timeout if the device does not specify one
+    const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+    /// The accepted range of session timeouts to derive the keep-alive interval from
+    const SESSION_TIMEOUTS: (Duration, Duration) = (Duration::from_secs(2), Duration::from_secs(3600));
+
+    /// Creates a new connection over an already established stream to the device with the given address
+    fn with_stream(stream: S, address: &str) -> Self {
+        let url = format!("rtsps://{address}{}", Self::STREAM_PATH);
+        Self { connection: BufReader::new(stream), url, cseq: 1 }
+    }
+
+    /// Performs a login to the device and negotiates the H.264 stream
+    pub fn login(mut self, pin: &Secret) -> Result<RtspSession<S>, Error> {
+        // Describe the stream and authenticate if necessary
+        let url = self.url.clone();
+        let mut challenge = None;
+        let mut describe = self.request("DESCRIBE", &url, &[("Accept", "application/sdp")], None)?;
+        if describe.status == 401 {
+            // Parse the challenge and retry
+            let fields = describe.fields.iter().filter(|(key, _)| key.eq_ignore_ascii_case("WWW-Authenticate"));
+            challenge = Challenge::parse(fields.map(|(_, value)| value.as_str()));
+            let Some(challenge) = &challenge else {
+                return Err(error!("Device requested an unsupported authentication scheme"));
+            };
+            let authorization = challenge.authorization(Self::USERNAME, pin.expose(), "DESCRIBE", &url, 1);
+            describe = self.request("DESCRIBE", &url, &[("Accept", "application/sdp")], Some(&authorization))?;
+        }
+        match describe.status {
+            200 => (),
+            401 => return Err(error!("Invalid PIN")),
+            status => return Err(error!("Unexpected RTSP response to DESCRIBE: {status}")),
+        }
+
+        // Parse the session description
+        let base = describe.field("Content-Base").unwrap_or(&url).to_string();
+        let sdp = str::from_utf8(&describe.body)?;
+        let track = VideoTrack::from_sdp(sdp, &base)?;
+
+        // Setup the video track for interleaved transfer
+        let mut auth = Auth { challenge, username: Self::USERNAME, password: pin.clone(), nc: 1 };
+        let authorization = auth.next("SETUP", &track.control);
+        let transport = ("Transport", "RTP/AVP/TCP;unicast;interleaved=0-1");
+        let setup = self.request("SETUP", &track.control, &[transport], authorization.as_deref())?;
+        let (200, Some(session)) = (setup.status, setup.field("Session")) else {
+            return Err(error!("Unexpected RTSP response to SETUP: {}", setup.status));
+        };
+
+        // Parse the session ID and timeout; the timeout is clamped since it is controlled by the device
+        let mut session = session.split(';').map(str::trim);
+        let session_id = session.next().unwrap_or_default().to_string();
+        let timeout = session.find_map(|param| param.strip_prefix("timeout=")).and_then(|t| t.parse().ok());
+        let timeout = timeout.map(Duration::from_secs).unwrap_or(Self::DEFAULT_SESSION_TIMEOUT);
+        let timeout = timeout.clamp(Self::SESSION_TIMEOUTS.0, Self::SESSION_TIMEOUTS.1);
+
+        // Start the playback
+        let authorization = auth.next("PLAY", &base);
+        let fields = [("Session", session_id.as_str()), ("Range", "npt=0.000-")];
+        let play = self.request("PLAY", &base, &fields, authorization.as_deref())?;
+        if play.status != 200 {
+            return Err(error!("Unexpected RTSP response to PLAY: {}", play.status));
+        }
+
+        // Init session
+        Ok(RtspSession {
+            connection: self,
+            auth,
+            base,
+            session_id,
+            keep_alive: timeout.checked_div(2).unwrap_or(timeout),
+            last_keep_alive: Instant::now(),
+            parameter_sets: track.parameter_sets,
+            timestamp: None,
+            access_unit: Vec::new(),
+            keyframe: false,
+            fragment: false,
+            completed: None,
+        })
+    }
+
+    /// Sends a request and receives the associated response
+    fn request(
+        &mut self,
+        method: &str,
+        url: &str,
+        fields: &[(&str, &str)],
+        authorization: Option<&str>,
+    ) -> Result<RtspResponse, Error> {
+        self.send(method, url, fields, authorization)?;
+        self.response()
+    }
+
+    /// Sends a request
+    fn send(
+        &mut self,
+        method: &str,
+        url: &str,
+        fields: &[(&str, &str)],
+        authorization: Option<&str>,
+    ) -> Result<(), Error> {
+        // Assemble the request
+        let mut request =
+            format!("{method} {url} RTSP/1.0\r\nCSeq: {}\r\nUser-Agent: bamborvideostream\r\n", self.cseq);
+        if let Some(authorization) = authorization {
+            request.push_str(&format!("Authorization: {authorization}\r\n"));
+        }
+        for (key, value) in fields {
+            request.push_str(&format!("{key}: {value}\r\n"));
+        }
+        request.push_str("\r\n");
+
+        // Send the request
+        self.cseq = self.cseq.wrapping_add(1);
+        self.connection.get_mut().write_all(request.as_bytes())?;
+        Ok(())
+    }
+
+    /// Receives the next response and skips any interleaved data packets
+    fn response(&mut self) -> Result<RtspResponse, Error> {
+        while self.peek()? == b'$' {
+            // Skip interleaved packet
+            let _ = self.interleaved()?;
+        }
+
+        // Parse the status line
+        let status_line = self.line()?;
+        let mut status_line = status_line.split_whitespace();
+        let (Some("RTSP/1.0"), Some(status)) = (status_line.next(), status_line.next()) else {
+            return Err(error!("Invalid RTSP status line"));
+        };
+        let status: u16 = status.parse()?;
+
+        // Parse the header fields
+        let mut fields = Vec::new();
+        loop {
+            let line = self.line()?;
+            if line.is_empty() {
+                break;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                return Err(error!("Invalid RTSP header field"));
+            };
+            fields.push((key.trim().to_string(), value.trim().to_string()));
+        }
+
+        // Read the body
+        let mut response = RtspResponse { status, fields, body: Vec::new() };
+        let content_length: u64 = response.field("Content-Length").unwrap_or("0").parse()?;
+        if content_length > Self::LIMIT {
+            return Err(error!("RTSP response body is too large"));
+        }
+        (&mut self.connection).take(content_length).read_to_end(&mut response.body)?;
+        Ok(response)
+    }
+
+    /// Reads an interleaved data packet and returns the channel and the payload
+    fn interleaved(&mut self) -> Result<(u8, Vec<u8>), Error> {
+        // Read the header
+        let mut header = [0; 4];
+        self.connection.read_exact(&mut header)?;
+        let [b'$', channel, len_hi, len_lo] = header else {
+            return Err(error!("Invalid interleaved RTSP packet"));
+        };
+
+        // Read the payload
+        let mut payload = vec![0; u16::from_be_bytes([len_hi, len_lo]) as usize];
+        self.connection.read_exact(&mut payload)?;
+        Ok((channel, payload))
+    }
+
+    /// Peeks the next byte from the connection
+    fn peek(&mut self) -> Result<u8, Error> {
+        let buf = self.connection.fill_buf()?;
+        let Some(byte) = buf.first() else {
+            return Err(error!("Connection has been closed by the device"));
+        };
+        Ok(*byte)
+    }
+
+    /// Reads a single CRLF-terminated line
+    fn line(&mut self) -> Result<String, Error> {
+        let mut line = Vec::new();
+        (&mut self.connection).take(Self::LIMIT).read_until(b'\n', &mut line)?;
+        if !line.ends_with(b"\n") {
+            return Err(error!("Truncated RTSP response"));
+        }
+        Ok(str::from_utf8(&line)?.trim_end().to_string())
+    }
+}
+
+/// The credentials to authenticate subsequent requests
+#[derive(Debug)]
+struct Auth {
+    /// The challenge sent by the device, if any
+    challenge: Option<Challenge>,
+    /// The username
+    username: &'static str,
+    /// The password
+    password: Secret,
+    /// The digest nonce count
+    nc: u32,
+}
+impl Auth {
+    /// Creates the next `Authorization` header value if the device requested authentication
+    fn next(&mut self, method: &str, url: &str) -> Option<String> {
+        let challenge = self.challenge.as_ref()?;
+        self.nc = self.nc.wrapping_add(1);
+        Some(challenge.authorization(self.username, self.password.expose(), method, url, self.nc))
+    }
+}
+
+/// An authenticated RTSP session to an X1 device
+#[derive(Debug)]
+pub struct RtspSession<S = TlsStream> {
+    /// The underlying connection
+    connection: RtspConnection<S>,
+    /// The credentials
+    auth: Auth,
+    /// The base URL of the stream
+    base: String,
+    /// The session ID
+    session_id: String,
+    /// The interval to send keep-alive requests
+    keep_alive: Duration,
+    /// When the last keep-alive request has been sent
+    last_keep_alive: Instant,
+    /// The out-of-band parameter sets in Annex B format
+    parameter_sets: Vec<u8>,
+    /// The RTP timestamp of the current access unit
+    timestamp: Option<u32>,
+    /// The current access unit in Annex B format
+    access_unit: Vec<u8>,
+    /// Whether the current access unit contains an IDR slice
+    keyframe: bool,
+    /// Whether a fragmented NAL unit is in progress
+    fragment: bool,
+    /// An access unit that has been completed together with its predecessor and is returned next
+    completed: Option<(Vec<u8>, bool)>,
+}
+impl<S> RtspSession<S>
+where
+    S: Read + Write,
+{
+    /// Receives the next H.264 access unit in Annex B format and whether it is a keyframe
+    pub fn access_unit(&mut self) -> Result<(Vec<u8>, bool), Error> {
+        loop {
+            // Return an access unit that has already been completed
+            if let Some(completed) = self.completed.take() {
+                return Ok(completed);
+            }
+
+            // Send a keep-alive request if necessary
+            if self.last_keep_alive.elapsed() >= self.keep_alive {
+                let authorization = self.auth.next("GET_PARAMETER", &self.base);
+                let session = [("Session", self.session_id.as_str())];
+                self.connection.send("GET_PARAMETER", &self.base, &session, authorization.as_deref())?;
+                self.last_keep_alive = Instant::now();
+            }
+
+            // Skip responses to keep-alive requests
+            if self.connection.peek()? != b'$' {
+                let _ = self.connection.response()?;
+                continue;
+            }
+
+            // Receive the next RTP packet and skip RTCP packets
+            let (0, packet) = self.connection.interleaved()? else {
+                continue;
+            };
+            let Some((marker, timestamp, payload)) = Self::rtp(&packet) else {
+                return Err(error!("Invalid RTP packet"));
+            };
+
+            // Complete the current access unit if the timestamp changes without a marker
+            let mut completed = None;
+            if self.timestamp.is_some_and(|current| current != timestamp) && !self.access_unit.is_empty() {
+                completed = Some(self.take());
+            }
+            self.timestamp = Some(timestamp);
+
+            // Depacketize the payload; if the packet also completes the new access unit, return it next
+            self.depacketize(payload);
+            if marker && !self.access_unit.is_empty() {
+                let access_unit = self.take();
+                match completed {
+                    Some(completed) => {
+                        self.completed = Some(access_unit);
+                        return Ok(completed);
+                    }
+                    None => return Ok(access_unit),
+                }
+            }
+            if let Some(completed) = completed {
+                return Ok(completed);
+            }
+        }
+    }
+
+    /// Parses an RTP packet and returns the marker bit, the timestamp and the payload
+    fn rtp(packet: &[u8]) -> Option<(bool, u32, &[u8])> {
+        // Parse the fixed header
+        let [flags, marker_pt, _, _, ts0, ts1, ts2, ts3, _, _, _, _, rest @ ..] = packet else {
+            return None;
+        };
+        let (2, padding, extension, csrc_count) = (flags >> 6, flags & 0x20 != 0, flags & 0x10 != 0, flags & 0x0f)
+        else {
+            return None;
+        };
+        let marker = marker_pt & 0x80 != 0;
+        let timestamp = u32::from_be_bytes([*ts0, *ts1, *ts2, *ts3]);
+
+        // Skip the CSRC identifiers and the header extension
+        let mut payload = rest.get(usize::from(csrc_count).checked_mul(4)?..)?;
+        if extension {
+            let [_, _, len_hi, len_lo, rest @ ..] = payload else {
+                return None;
+            };
+            let len = usize::from(u16::from_be_bytes([*len_hi, *len_lo])).checked_mul(4)?;
+            payload = rest.get(len..)?;
+        }
+
+        // Strip the padding
+        if padding {
+            let padding = usize::from(*payload.last()?);
+            payload = payload.get(..payload.len().checked_sub(padding)?)?;
+        }
+        Some((marker, timestamp, payload))
+    }
+
+    /// Depacketizes an H.264 RTP payload into the current access unit
+    fn depacketize(&mut self, payload: &[u8]) {
+        let Some(header) = payload.first() else {
+            return;
+        };
+        match header & 0x1f {
+            // Single NAL unit packet
+            1..=23 => self.push_nal(payload),
+            // STAP-A aggregation packet
+            24 => {
+                let mut rest = payload.get(1..).unwrap_or_default();
+                while let [len_hi, len_lo, tail @ ..] = rest {
+                    let len = usize::from(u16::from_be_bytes([*len_hi, *len_lo]));
+                    let (Some(nal), Some(tail)) = (tail.get(..len), tail.get(len..)) else {
+                        break;
+                    };
+                    self.push_nal(nal);
+                    rest = tail;
+                }
+            }
+            // FU-A fragmentation unit
+            28 => {
+                let [indicator, fu_header, fragment @ ..] = payload else {
+                    return;
+                };
+                if fu_header & 0x80 != 0 {
+                    // Start a new fragmented NAL unit
+                    let nal_header = (indicator & 0xe0) | (fu_header & 0x1f);
+                    self.push_nal(&[nal_header]);
+                    self.fragment = true;
+                }
+                if self.fragment {
+                    self.access_unit.extend_from_slice(fragment);
+                }
+                if fu_header & 0x40 != 0 {
+                    self.fragment = false;
+                }
+            }
+            // Unsupported packetization
+            _ => (),
+        }
+    }
+
+    /// Appends a NAL unit to the current access unit
+    fn push_nal(&mut self, nal: &[u8]) {
+        let nal_type = nal.first().map(|header| header & 0x1f);
+        self.keyframe |= nal_type == Some(5);
+        self.access_unit.extend_from_slice(START_CODE);
+        self.access_unit.extend_from_slice(nal);
+    }
+
+    /// Takes the current access unit and prepends the parameter sets to keyframes if necessary
+    fn take(&mut self) -> (Vec<u8>, bool) {
+        let access_unit = std::mem::take(&mut self.access_unit);
+        let keyframe = std::mem::replace(&mut self.keyframe, false);
+        self.fragment = false;
+
+        // Prepend the parameter sets so that each keyframe can be decoded on its own
+        let is_sps = |window: &[u8]| window.starts_with(START_CODE) && window.last().is_some_and(|nal| nal & 0x1f == 7);
+        let has_sps = access_unit.windows(5).any(is_sps);
+        match keyframe && !has_sps && !self.parameter_sets.is_empty() {
+            true => ([self.parameter_sets.as_slice(), &access_unit].concat(), keyframe),
+            false => (access_unit, keyframe),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::TcpListener,
+        thread::{self, JoinHandle},
+    };
+
+    /// The PIN of the stand-in device
+    const PIN: &str = "12345678";
+    /// The base URL of the stand-in device
+    const BASE: &str = "rtsps://printer:322/streaming/live/1/";
+    /// The session description with an audio track first and the out-of-band parameter sets `67 42` and `68 ce`
+    const SDP: &str = "v=0\r\nm=audio 0 RTP/AVP 97\r\na=control:trackID=1\r\nm=video 0 RTP/AVP 96\r\n\
+        a=rtpmap:96 H264/90000\r\na=fmtp:96 packetization-mode=1;sprop-parameter-sets=Z0I=,aM4=\r\n\
+        a=control:trackID=0\r\n";
+    /// The out-of-band parameter sets in Annex B format
+    const PARAMETER_SETS: &[u8] = &[0, 0, 0, 1, 0x67, 0x42, 0, 0, 0, 1, 0x68, 0xce];
+
+    /// The connection of the stand-in device to the client
+    struct Peer {
+        /// The connection
+        connection: BufReader<TcpStream>,
+        /// The received requests
+        requests: Vec<String>,
+    }
+    impl Peer {
+        /// Receives the next request and returns its request line
+        fn request(&mut self) -> String {
+            let mut request = String::new();
+            loop {
+                let mut line = String::new();
+                self.connection.read_line(&mut line).unwrap();
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            self.requests.push(request.clone());
+            request.lines().next().unwrap().to_string()
+        }
+
+        /// Sends a response to the last request
+        fn respond(&mut self, status: &str, fields: &[&str], body: &str) {
+            let request = self.requests.last().unwrap();
+            let cseq = request.lines().find_map(|line| line.strip_prefix("CSeq: ")).unwrap();
+            let mut response = format!("RTSP/1.0 {status}\r\nCSeq: {cseq}\r\n");
+            for field in fields {
+                response.push_str(&format!("{field}\r\n"));
+            }
+            response.push_str(&format!("Content-Length: {}\r\n\r\n{body}", body.len()));
+            self.connection.get_mut().write_all(response.as_bytes()).unwrap();
+        }
+
+        /// Sends an interleaved packet on the given channel
+        fn interleaved(&mut self, channel: u8, packet: &[u8]) {
+            let len = u16::try_from(packet.len()).unwrap().to_be_bytes();
+            let data = [&[b'$', channel, len[0], len[1]], packet].concat();
+            self.connection.get_mut().write_all(&data).unwrap();
+        }
+
+        /// Sends an RTP packet with the given marker bit, timestamp and payload on channel `0`
+        fn rtp(&mut self, marker: bool, timestamp: u32, payload: &[u8]) {
+            let ts = timestamp.to_be_bytes();
+            let header = [0x80, u8::from(marker) << 7 | 96, 0, 1, ts[0], ts[1], ts[2], ts[3], 0, 0, 0, 42];
+            self.interleaved(0, &[&header, payload].concat());
+        }
+
+        /// Answers DESCRIBE, SETUP and PLAY without authentication
+        fn handshake(&mut self, session: &str) {
+            assert!(self.request().starts_with("DESCRIBE "));
+            self.respond("200 OK", &[&format!("Content-Base: {BASE}"), "Content-Type: application/sdp"], SDP);
+            assert!(self.request().starts_with("SETUP "));
+            self.respond("200 OK", &[&format!("Session: {session}")], "");
+            assert!(self.request().starts_with("PLAY "));
+            self.respond("200 OK", &[], "");
+        }
+    }
+
+    /// Starts a stand-in device that runs the given script and returns the received requests
+    fn serve<F>(script: F) -> (RtspConnection<TcpStream>, JoinHandle<Vec<String>>)
+    where
+        F: FnOnce(&mut Peer) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (connection, _) = listener.accept().unwrap();
+            let mut peer = Peer { connection: BufReader::new(connection), requests: Vec::new() };
+            script(&mut peer);
+            peer.requests
+        });
+
+        let connection = TcpStream::connect(address).unwrap();
+        connection.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        (RtspConnection::with_stream(connection, "printer:322"), server)
+    }
+
+    /// Gets the `Authorization` header field of the given request
+    fn authorization(request: &str) -> Option<&str> {
+        request.lines().find_map(|line| line.strip_prefix("Authorization: "))
+    }
+
+    /// Asserts that the request carries a valid digest response for the given method, URL and nonce count
+    fn assert_digest(request: &str, method: &str, url: &str, nc: &str) {
+        let params = authorization(request).unwrap().strip_prefix("Digest ").unwrap();
+        let param = |name| Challenge::param(params, name).unwrap();
+        assert_eq!(param("username"), "bblp");
+        assert_eq!(param("uri"), url);
+        assert_eq!(param("nc"), nc);
+
+        let md5 = |input: String| format!("{:x}", Md5::digest(input.as_bytes()));
+        let ha1 = md5(format!("bblp:Streaming Server:{PIN}"));
+        let ha2 = md5(format!("{method}:{url}"));
+        let expected = md5(format!("{ha1}:f00d:{nc}:{}:auth:{ha2}", param("cnonce")));
+        assert_eq!(param("response"), expected);
+    }
+
+    /// A `401` with a digest challenge is retried with a digest response that is reused for SETUP and PLAY
+    #[test]
+    fn digest_retry() {
+        let (connection, server) = serve(|peer| {
+            assert!(peer.request().starts_with("DESCRIBE "));
+            let challenges = [
+                r#"WWW-Authenticate: Basic realm="Streaming Server""#,
+                concat!(
+                    r#"WWW-Authenticate: Digest realm="Streaming Server", nonce="f00d", "#,
+                    r#"qop="auth,auth-int""#
+                ),
+            ];
+            peer.respond("401 Unauthorized", &challenges, "");
+            peer.handshake("1234");
+        });
+        connection.login(&Secret::new(PIN)).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(authorization(&requests[0]), None);
+        let url = "rtsps://printer:322/streaming/live/1";
+        assert_digest(&requests[1], "DESCRIBE", url, "00000001");
+        assert_digest(&requests[2], "SETUP", &format!("{BASE}trackID=0"), "00000002");
+        assert_digest(&requests[3], "PLAY", BASE, "00000003");
+    }
+
+    /// A `401` with a basic challenge is retried with basic credentials
+    #[test]
+    fn basic_auth() {
+        let (connection, server) = serve(|peer| {
+            assert!(peer.request().starts_with("DESCRIBE "));
+            peer.respond("401 Unauthorized", &[r#"WWW-Authenticate: Basic realm="Streaming Server""#], "");
+            peer.handshake("1234");
+        });
+        connection.login(&Secret::new(PIN)).unwrap();
+
+        let requests = server.join().unwrap();
+        for request in &requests[1..] {
+            assert_eq!(authorization(request), Some("Basic YmJscDoxMjM0NTY3OA=="));
+        }
+    }
+
+    /// A second `401` is reported as invalid PIN
+    #[test]
+    fn invalid_pin() {
+        let (connection, server) = serve(|peer| {
+            for _ in 0..2 {
+                assert!(peer.request().starts_with("DESCRIBE "));
+                peer.respond("401 Unauthorized", &[r#"WWW-Authenticate: Basic realm="Streaming Server""#], "");
+            }
+        });
+        let error = connection.login(&Secret::new("87654321")).unwrap_err();
+        assert!(error.to_string().contains("Invalid PIN"), "{error}");
+        server.join().unwrap();
+    }
+
+    /// SETUP uses the video control URL and interleaved transport, and PLAY the session ID without parameters
+    #[test]
+    fn setup_and_play() {
+        let (connection, server) = serve(|peer| peer.handshake("C0FFEE;timeout=30"));
+        let session = connection.login(&Secret::new(PIN)).unwrap();
+        assert_eq!(session.session_id, "C0FFEE");
+        assert_eq!(session.keep_alive, Duration::from_secs(15));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("DESCRIBE rtsps://printer:322/streaming/live/1 RTSP/1.0\r\n"));
+        assert!(requests[1].starts_with(&format!("SETUP {BASE}trackID=0 RTSP/1.0\r\n")));
+        assert!(requests[1].contains("\r\nTransport: RTP/AVP/TCP;unicast;interleaved=0-1\r\n"));
+        assert!(requests[2].starts_with(&format!("PLAY {BASE} RTSP/1.0\r\n")));
+        assert!(requests[2].contains("\r\nSession: C0FFEE\r\n"));
+    }
+
+    /// Huge session timeouts from the device are clamped
+    #[test]
+    fn huge_session_timeout() {
+        let (connection, server) = serve(|peer| peer.handshake("1234;timeout=18446744073709551615"));
+        let session = connection.login(&Secret::new(PIN)).unwrap();
+        assert_eq!(session.keep_alive, Duration::from_secs(1800));
+        server.join().unwrap();
+    }
+
+    /// RTCP packets and interleaved RTSP responses are skipped, and non-IDR slices get no parameter sets
+    #[test]
+    fn interleaved_channels() {
+        let (connection, server) = serve(|peer| {
+            peer.handshake("1234");
+            peer.interleaved(1, &[0x80, 200, 0, 6]);
+            peer.respond("200 OK", &[], "");
+            peer.rtp(true, 3000, &[0x41, 0x9a, 0x02]);
+        });
+        let mut session = connection.login(&Secret::new(PIN)).unwrap();
+        assert_eq!(session.access_unit().unwrap(), (vec![0, 0, 0, 1, 0x41, 0x9a, 0x02], false));
+        server.join().unwrap();
+    }
+
+    /// Fragmented IDR slices are reassembled and prefixed with the out-of-band parameter sets
+    #[test]
+    fn fu_a_keyframe() {
+        let (connection, server) = serve(|peer| {
+            peer.handshake("1234");
+            peer.rtp(false, 3000, &[0x7c, 0x85, 0x88, 0x84]);
+            peer.rtp(false, 3000, &[0x7c, 0x05, 0x21]);
+            peer.rtp(true, 3000, &[0x7c, 0x45, 0x7f]);
+        });
+        let mut session = connection.login(&Secret::new(PIN)).unwrap();
+        let expected = [PARAMETER_SETS, &[0, 0, 0, 1, 0x65, 0x88, 0x84, 0x21, 0x7f]].concat();
+        assert_eq!(session.access_unit().unwrap(), (expected, true));
+        server.join().unwrap();
+    }
+
+    /// Aggregated NAL units are split, and in-band parameter sets are not duplicated
+    #[test]
+    fn stap_a_keyframe() {
+        let (connection, server) = serve(|peer| {
+            peer.handshake("1234");
+            let nals = [0x78, 0, 2, 0x67, 0x4d, 0, 2, 0x68, 0xee, 0, 3, 0x65, 0x88, 0x80];
+            peer.rtp(true, 3000, &nals);
+        });
+        let mut session = connection.login(&Secret::new(PIN)).unwrap();
+        let expected = vec![0, 0, 0, 1, 0x67, 0x4d, 0, 0, 0, 1, 0x68, 0xee, 0, 0, 0, 1, 0x65, 0x88, 0x80];
+        assert_eq!(session.access_unit().unwrap(), (expected, true));
+        server.join().unwrap();
+    }
+
+    /// A new timestamp completes the current access unit even without a marker bit
+    #[test]
+    fn timestamp_change() {
+        let (connection, server) = serve(|peer| {
+            peer.handshake("1234");
+            peer.rtp(false, 3000, &[0x41, 0x01]);
+            peer.rtp(true, 6000, &[0x41, 0x02]);
+        });
+        let mut session = connection.login(&Secret::new(PIN)).unwrap();
+        assert_eq!(session.access_unit().unwrap(), (vec![0, 0, 0, 1, 0x41, 0x01], false));
+        assert_eq!(session.access_unit().unwrap(), (vec![0, 0, 0, 1, 0x41, 0x02], false));
+        server.join().unwrap();
+    }
+
+    /// CSRC identifiers, header extensions and padding are stripped from RTP packets
+    #[test]
+    fn rtp_header() {
+        let packet = [
+            0xb1, 0xe0, 0, 1, 0, 0, 0x0b, 0xb8, 0, 0, 0, 42, // header with padding, extension, 1 CSRC and marker
+            0, 0, 0, 7, // CSRC
+            0xbe, 0xde, 0, 1, 1, 2, 3, 4, // extension with one word
+            0x41, 0x9a, // payload
+            0, 2, // padding
+        ];
+        assert_eq!(RtspSession::<TcpStream>::rtp(&packet), Some((true, 3000, &[0x41, 0x9a][..])));
+        assert_eq!(RtspSession::<TcpStream>::rtp(&[0x40, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+    }
+}
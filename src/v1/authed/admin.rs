@@ -0,0 +1,208 @@
+//! Manages the running device services
+//!
+//! Services are addressed either via `device=<name>` for configured devices or via `key=<key>` with the service key as
+//! listed by [`services`] (e.g. the device address; credentials within URLs are stripped).
+
+use crate::{
+    error::Error,
+    json,
+    secret::Secret,
+    services::{
+        config::{self, Config, DeviceKind},
+        devicestore, metrics,
+        p1::P1Service,
+    },
+    v1::authed::{http, json_response, p1, x1, AdminTicket},
+};
+use ehttpd::http::{Request, Response, ResponseExt};
+use ehttpd_querystring::{querystringext::QueryStringExt, RequestQuerystringExt};
+use std::sync::Arc;
+
+/// The name of the configured device field
+const DEVICE_FIELD: &[u8] = b"device";
+/// The name of the service key field
+const KEY_FIELD: &[u8] = b"key";
+
+/// Resolves the service key of the addressed service
+fn service_key(request: &Request, config: &Config) -> Option<String> {
+    // Use a configured device if given
+    let querystring = request.querystring().ok()?;
+    if let Ok(Some(name)) = querystring.get_str(DEVICE_FIELD) {
+        return config.device(name).map(|device| device.service_key());
+    }
+
+    // Find the live service with the given (redacted) key
+    let Ok(Some(key)) = querystring.get_str(KEY_FIELD) else {
+        // The service is not addressed
+        return None;
+    };
+    let service = P1Service::list().into_iter().find(|service| metrics::device_label(service.key()) == key)?;
+    Some(service.key().to_string())
+}
+
+/// The name of the configured or stored device with the given service key, if any
+fn device_name(key: &str, config: &Config) -> Option<String> {
+    let mut devices = config.all_devices().into_iter();
+    devices.find(|(_, device)| device.service_key() == key).map(|(name, _)| name)
+}
+
+/// Encodes the service with the given key as JSON object
+fn to_json(key: &str, service: Option<&P1Service>, config: &Config) -> String {
+    // Describe the service
+    let status = metrics::device_status(key);
+    let object = json::Object::new().string("key", &metrics::device_label(key));
+    let object = match device_name(key, config) {
+        Some(name) => object.string("device", &name),
+        None => object.null("device"),
+    };
+    let object = match service {
+        Some(service) => object
+            .string("content_type", service.content_type())
+            .bool("running", !service.is_stopped())
+            .float("idle_s", service.idle_time().as_secs_f64()),
+        None => object.null("content_type").bool("running", false).null("idle_s"),
+    };
+
+    // Append the upstream status
+    let object = match status {
+        Some(status) => object.string("state", status.state),
+        None => object.string("state", "idle"),
+    };
+    let object = match status.and_then(|status| status.frame_age) {
+        Some(frame_age) => object.float("frame_age_s", frame_age.as_secs_f64()),
+        None => object.null("frame_age_s"),
+    };
+    object.number("viewers", status.map(|status| status.viewers).unwrap_or_default()).finish()
+}
+
+/// Lists all live services
+pub fn services(_: Request, config: &Arc<Config>, _: AdminTicket) -> Result<Response, Error> {
+    let services = P1Service::list();
+    let services = json::array(services.iter().map(|service| to_json(service.key(), Some(service), config)));
+    Ok(json_response(json::Object::new().raw("services", &services).finish()))
+}
+
+/// Starts the service for a device ahead of time, so that the first viewer does not have to wait for the login
+///
+/// # Note
+/// The device is given like for the streaming endpoints, i.e. via `device=<name>`, via `kind=p1|x1`, `address` and
+/// `pin`, or via `kind=mjpeg|snapshot` and an allowed `url`. Like any other service, it is stopped if it is not accessed within its keep-alive period.
+pub fn start(request: Request, config: &Arc<Config>, _: AdminTicket) -> Result<Response, Error> {
+    /// The name of the device kind field
+    const KIND_FIELD: &[u8] = b"kind";
+
+    // Get the device kind
+    let Ok(querystring) = request.querystring() else {
+        // The query string was invalid
+        return Ok(Response::new_400_badrequest());
+    };
+    let kind = match (querystring.get_str(DEVICE_FIELD), querystring.get_str(KIND_FIELD)) {
+        (Ok(Some(name)), _) => config.device(name).map(|device| device.kind),
+        (_, Ok(None | Some("p1"))) => Some(DeviceKind::P1),
+        (_, Ok(Some("x1"))) => Some(DeviceKind::X1),
+        (_, Ok(Some("mjpeg"))) => Some(DeviceKind::Mjpeg),
+        (_, Ok(Some("snapshot"))) => Some(DeviceKind::Snapshot),
+        _ => None,
+    };
+
+    // Start the service
+    let service = match kind {
+        Some(DeviceKind::P1) => p1::image_service(&request, config),
+        Some(DeviceKind::X1) => x1::image_service(&request, config),
+        Some(DeviceKind::Mjpeg | DeviceKind::Snapshot) => http::image_service(&request, config),
+        None => None,
+    };
+    let Some(service) = service else {
+        // The query string was invalid or incomplete
+        return Ok(Response::new_400_badrequest());
+    };
+    Ok(json_response(to_json(service.key(), Some(&service), config)))
+}
+
+/// Forces a service to reconnect to its device
+pub fn reconnect(request: Request, config: &Arc<Config>, _: AdminTicket) -> Result<Response, Error> {
+    // Get the service
+    let Some(key) = service_key(&request, config) else {
+        // The service is not addressed or does not exist
+        return Ok(Response::new_404_notfound());
+    };
+    let Some(service) = P1Service::reconnect(&key) else {
+        // The service is not running
+        return Ok(Response::new_404_notfound());
+    };
+    Ok(json_response(to_json(&key, Some(&service), config)))
+}
+
+/// Stops a service and removes it from the registry
+///
+/// # Note
+/// Active streams receive their remaining frames and end; the next request for the device starts a new service.
+pub fn stop(request: Request, config: &Arc<Config>, _: AdminTicket) -> Result<Response, Error> {
+    // Get the service
+    let Some(key) = service_key(&request, config) else {
+        // The service is not addressed or does not exist
+        return Ok(Response::new_404_notfound());
+    };
+    if !P1Service::remove(&key) {
+        // The service is not running
+        return Ok(Response::new_404_notfound());
+    }
+    Ok(json_response(to_json(&key, None, config)))
+}
+
+/// Changes the PIN (i.e. the LAN access code) of a device and reconnects its service
+///
+/// # Note
+/// For devices from the device store, the new PIN is persisted. For devices from the config file, it is kept until the
+/// device config changes or the server restarts; update the config file to make the change permanent.
+pub fn pin(request: Request, config: &Arc<Config>, _: AdminTicket) -> Result<Response, Error> {
+    /// The name of the new device PIN field
+    const DEVICEPIN_FIELD: &[u8] = b"pin";
+
+    // Get and validate the new PIN
+    let Ok(querystring) = request.querystring() else {
+        // The query string was invalid
+        return Ok(Response::new_400_badrequest());
+    };
+    let Ok(Some(pin)) = querystring.get_str(DEVICEPIN_FIELD) else {
+        // The new PIN is missing
+        return Ok(Response::new_400_badrequest());
+    };
+    if bamborvideostream_p1::protocol::login_packet(pin).is_err() {
+        // The new PIN is invalid
+        return Ok(Response::new_400_badrequest());
+    }
+    let pin = Secret::new(pin);
+
+    // Get the service
+    let Some(key) = service_key(&request, config) else {
+        // The service is not addressed or does not exist
+        return Ok(Response::new_404_notfound());
+    };
+
+    // Persist the PIN for stored devices or remember it for devices from the config file first, so that a failed write
+    // leaves the running service unchanged
+    match device_name(&key, config) {
+        Some(name) if config.device(&name).is_some_and(|device| device.pin.is_none()) => {
+            // The device does not use a PIN (e.g. HTTP cameras)
+            return Ok(Response::new_400_badrequest());
+        }
+        Some(name) if config.devices.contains_key(&name) => config::set_pin_override(&key, &pin),
+        Some(name) => {
+            if let Some(mut device) = devicestore::get(&name) {
+                device.pin = Some(pin.clone());
+                devicestore::put(&name, device)?;
+            }
+        }
+        None => (),
+    }
+
+    // Log in with the new PIN; named devices always use a PIN, so nothing has been persisted if this fails
+    let service = P1Service::get(&key);
+    if service.as_ref().is_some_and(|service| !service.set_pin(&pin)) {
+        // The source does not use a PIN (e.g. HTTP cameras or replays)
+        return Ok(Response::new_400_badrequest());
+    }
+    let service = service.and_then(|_| P1Service::reconnect(&key));
+    Ok(json_response(to_json(&key, service.as_deref(), config)))
+}
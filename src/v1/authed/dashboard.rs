@@ -0,0 +1,62 @@
+//! Lists the configured and stored devices with their status for dashboards
+//!
+//! Unlike the admin device listing, this is available with the regular API key and does not include addresses or
+//! credentials; the URLs are relative and without `auth`, so clients append their own API key.
+
+use crate::{
+    error::Error,
+    json,
+    services::{
+        config::{Config, Device, DeviceKind},
+        metrics,
+    },
+    v1::authed::{json_response, AuthTicket},
+};
+use ehttpd::http::{Request, Response};
+use std::sync::Arc;
+
+/// Encodes the device as JSON object
+fn to_json(name: &str, device: &Device) -> String {
+    /// The maximum width of the thumbnails
+    const THUMBNAIL_WIDTH: usize = 480;
+
+    // Describe the device
+    let object = json::Object::new().string("name", name).string("kind", &device.kind.to_string());
+    let object = match &device.model {
+        Some(model) => object.string("model", model),
+        None => object.null("model"),
+    };
+
+    // Append the upstream status
+    let status = metrics::device_status(&device.service_key());
+    let object = match status {
+        Some(status) => object.string("state", status.state),
+        None => object.string("state", "idle"),
+    };
+    let object = match status.and_then(|status| status.frame_age) {
+        Some(frame_age) => object.float("frame_age_s", frame_age.as_secs_f64()),
+        None => object.null("frame_age_s"),
+    };
+    let object = object.number("viewers", status.map(|status| status.viewers).unwrap_or_default());
+
+    // Append the URLs; X1 devices deliver no single JPEGs
+    let endpoint = match device.kind {
+        DeviceKind::P1 => "/v1/p1",
+        DeviceKind::Mjpeg | DeviceKind::Snapshot => "/v1/http",
+        DeviceKind::X1 => {
+            return object.null("thumbnail_url").string("stream_url", &format!("/v1/x1/stream?device={name}")).finish()
+        }
+    };
+    object
+        .string("thumbnail_url", &format!("{endpoint}/snapshot?device={name}&width={THUMBNAIL_WIDTH}"))
+        .string("stream_url", &format!("{endpoint}/stream?device={name}"))
+        .finish()
+}
+
+/// Lists the devices with their status and URLs
+pub fn devices(_: Request, config: &Arc<Config>, _: AuthTicket) -> Result<Response, Error> {
+    let devices = config.all_devices();
+    let devices = json::array(devices.iter().map(|(name, device)| to_json(name, device)));
+
+    Ok(json_response(json::Object::new().raw("devices", &devices).finish()))
+}
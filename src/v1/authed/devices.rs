@@ -0,0 +1,145 @@
+//! Manages the persistent device store
+//!
+//! Devices are written via query fields like in the config file (i.e. `kind`, `model`, `address`, `pin`, `fingerprint`,
+//! `max_staleness`, `capture`, the transform fields, and `url` and `interval` for HTTP cameras); PINs and URL
+//! credentials are never returned.
+
+use crate::{
+    error::Error,
+    json,
+    log::Level,
+    secret,
+    services::{
+        config::{Config, Device},
+        devicestore, p1,
+        p1::{mosaic, P1Service},
+    },
+    v1::authed::{json_response, AdminTicket},
+};
+use ehttpd::http::{Request, Response, ResponseExt};
+use ehttpd_querystring::{querystringext::QueryStringExt, RequestQuerystringExt};
+use std::{str, sync::Arc};
+
+/// The name of the device name field
+const NAME_FIELD: &[u8] = b"name";
+
+/// Encodes the device as JSON object
+fn to_json(name: &str, device: &Device, config: &Config) -> String {
+    let source = match config.devices.contains_key(name) {
+        true => "config",
+        false => "store",
+    };
+    let object =
+        json::Object::new().string("name", name).string("source", source).string("kind", &device.kind.to_string());
+    let object = match &device.model {
+        Some(model) => object.string("model", model),
+        None => object.null("model"),
+    };
+    let object = match device.kind.is_http() {
+        true => object.null("address").string("url", &secret::redact_url(&device.address)),
+        false => object.string("address", &device.address).null("url"),
+    };
+    let object = match device.fingerprint {
+        Some(fingerprint) => object.string("fingerprint", &p1::format_fingerprint(&fingerprint)),
+        None => object.null("fingerprint"),
+    };
+    let object = match device.max_staleness {
+        Some(max_staleness) => object.number("max_staleness_s", max_staleness),
+        None => object.null("max_staleness_s"),
+    };
+    let object = match device.interval {
+        Some(interval) => object.number("interval_ms", interval),
+        None => object.null("interval_ms"),
+    };
+    object.raw("transform", &device.transform.to_json()).bool("capture", device.capture).finish()
+}
+
+/// Lists the devices from the config file and the device store
+pub fn list(_: Request, config: &Arc<Config>, _: AdminTicket) -> Result<Response, Error> {
+    let devices = config.all_devices();
+    let devices = json::array(devices.iter().map(|(name, device)| to_json(name, device, config)));
+    let json = json::Object::new().bool("store", devicestore::is_open()).raw("devices", &devices).finish();
+    Ok(json_response(json))
+}
+
+/// Adds or replaces a stored device
+///
+/// # Note
+/// All fields are replaced, except for the PIN, which is kept if it is omitted. If the device changes, its running
+/// service is stopped, so that the next request connects with the new settings.
+pub fn put(request: Request, config: &Arc<Config>, _: AdminTicket) -> Result<Response, Error> {
+    /// The fields which are not device fields
+    const IGNORED_FIELDS: [&[u8]; 2] = [NAME_FIELD, b"auth"];
+
+    // Get the device name
+    let Ok(querystring) = request.querystring() else {
+        // The query string was invalid
+        return Ok(Response::new_400_badrequest());
+    };
+    let Ok(Some(name)) = querystring.get_str(NAME_FIELD) else {
+        // The device name is missing
+        return Ok(Response::new_400_badrequest());
+    };
+    if !devicestore::is_open() {
+        // There is no device store
+        return Ok(Response::new_404_notfound());
+    }
+    if config.devices.contains_key(name) {
+        // Devices from the config file cannot be overridden
+        return Ok(Response::new_status_reason(409, "Conflict"));
+    }
+
+    // Collect the device fields and keep the previous PIN if none is given
+    let mut fields = Vec::new();
+    for (key, value) in querystring.iter().filter(|(key, _)| !IGNORED_FIELDS.contains(&key.as_ref())) {
+        let (Ok(key), Ok(value)) = (str::from_utf8(key), str::from_utf8(value)) else {
+            // The field is not valid UTF-8
+            return Ok(Response::new_400_badrequest());
+        };
+        fields.push((key.to_string(), value.to_string()));
+    }
+    let previous = devicestore::get(name);
+    let is_http = fields.iter().any(|(key, value)| key == "kind" && matches!(value.as_str(), "mjpeg" | "snapshot"));
+    let previous_pin = previous.as_ref().filter(|_| !is_http && !fields.iter().any(|(key, _)| key == "pin"));
+    let previous_pin = previous_pin.and_then(|previous| previous.pin.clone());
+
+    // Validate the device
+    let device = match Device::from_fields(name, fields, previous_pin, "device") {
+        Ok(device) => device,
+        Err(e) => {
+            // The device is invalid
+            e.log_as(Level::Warn);
+            return Ok(Response::new_400_badrequest());
+        }
+    };
+
+    // Store the device and stop the service of the previous device if it has changed
+    devicestore::put(name, device.clone())?;
+    if let Some(previous) = previous.filter(|previous| *previous != device) {
+        P1Service::stop(&previous.service_key());
+        mosaic::stop_all();
+    }
+    Ok(json_response(to_json(name, &device, config)))
+}
+
+/// Removes a stored device and stops its service
+pub fn delete(request: Request, config: &Arc<Config>, _: AdminTicket) -> Result<Response, Error> {
+    // Get the device name
+    let Ok(querystring) = request.querystring() else {
+        // The query string was invalid
+        return Ok(Response::new_400_badrequest());
+    };
+    let Ok(Some(name)) = querystring.get_str(NAME_FIELD) else {
+        // The device name is missing
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Remove the device
+    let Some(device) = devicestore::remove(name)? else {
+        // The device is not stored
+        return Ok(Response::new_404_notfound());
+    };
+    P1Service::stop(&device.service_key());
+    mosaic::stop_all();
+    Ok(json_response(to_json(name, &device, config)))
+}
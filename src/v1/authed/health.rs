@@ -0,0 +1,17 @@
+//! Exposes the detailed readiness
+
+use crate::{
+    error::Error,
+    services::{config::Config, health::Readiness},
+    v1::authed::{json_response, AuthTicket},
+};
+use ehttpd::http::{Request, Response};
+use std::sync::Arc;
+
+/// Reports the readiness of the server and each configured device as JSON
+///
+/// # Note
+/// This always responds with `200`, so that the details are delivered even if the server is not ready.
+pub fn readyz(_: Request, config: &Arc<Config>, _: AuthTicket) -> Result<Response, Error> {
+    Ok(json_response(Readiness::check(config).to_json()))
+}
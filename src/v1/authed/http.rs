@@ -0,0 +1,148 @@
+//! Gets the last JPEG or the live stream for the given HTTP camera
+//!
+//! Cameras are given either via `device=<name>` for configured `mjpeg` or `snapshot` devices, or via `url` (and `type`
+//! and `interval`) for URLs that are listed in `BAMBORVIDEOSTREAM_HTTPCAMERAS`.
+
+use crate::{
+    error,
+    error::Error,
+    services::{
+        camera::Source,
+        config::{Config, DeviceKind},
+        http::{self, MjpegSource, SnapshotSource},
+        p1::P1Service,
+    },
+    v1::{
+        authed::{p1, AuthTicket},
+        rendition,
+        stream::FrameStream,
+    },
+};
+use ehttpd::http::{Request, Response, ResponseExt};
+use ehttpd_querystring::{querystringext::QueryStringExt, RequestQuerystringExt};
+use std::{sync::Arc, time::Duration};
+
+/// The name of the configured device field
+const DEVICE_FIELD: &[u8] = b"device";
+/// The name of the camera URL field
+const URL_FIELD: &[u8] = b"url";
+
+/// Whether the requested camera is a configured device or an URL that is allowed by the config
+///
+/// # Note
+/// The server fetches the URL on behalf of the client, so arbitrary URLs would allow to probe the local network.
+fn is_allowed(request: &Request, config: &Config) -> bool {
+    let Ok(querystring) = request.querystring() else {
+        return false;
+    };
+    match (querystring.get_str(DEVICE_FIELD), querystring.get_str(URL_FIELD)) {
+        (Ok(Some(name)), _) => config.device(name).is_some_and(|device| device.kind.is_http()),
+        (_, Ok(Some(url))) => config.allows_http_camera(url),
+        _ => false,
+    }
+}
+
+/// Creates the camera source for the given HTTP camera together with the key of its service
+pub fn source(kind: DeviceKind, url: &str, interval: Duration) -> Result<(Arc<dyn Source>, String), Error> {
+    match kind {
+        DeviceKind::Mjpeg => Ok((Arc::new(MjpegSource::new(url)?), http::mjpeg_key(url))),
+        DeviceKind::Snapshot => Ok((Arc::new(SnapshotSource::new(url, interval)?), http::snapshot_key(url, interval))),
+        DeviceKind::P1 | DeviceKind::X1 => Err(error!("A {kind} device is no HTTP camera")),
+    }
+}
+
+/// Gets the service for the given HTTP camera, i.e. a configured device or an allowed URL
+pub fn image_service(request: &Request, config: &Config) -> Option<Arc<P1Service>> {
+    /// The name of the camera type field
+    const TYPE_FIELD: &[u8] = b"type";
+    /// The name of the snapshot polling interval field in milliseconds
+    const INTERVAL_FIELD: &[u8] = b"interval";
+
+    // Use a configured device if given
+    let querystring = request.querystring().ok()?;
+    let (kind, url, interval) = if let Ok(Some(name)) = querystring.get_str(DEVICE_FIELD) {
+        let device = config.device(name).filter(|device| device.kind.is_http())?;
+        (device.kind, device.address.clone(), device.polling_interval())
+    } else {
+        // Get the camera URL and ensure it is allowed
+        let Ok(Some(url)) = querystring.get_str(URL_FIELD) else {
+            // The camera URL is missing
+            return None;
+        };
+        if !config.allows_http_camera(url) {
+            // The camera URL is not allowed
+            return None;
+        }
+
+        // Get the camera type and interval
+        let kind = match querystring.get_str(TYPE_FIELD) {
+            Ok(None | Some("snapshot")) => DeviceKind::Snapshot,
+            Ok(Some("mjpeg")) => DeviceKind::Mjpeg,
+            _ => return None,
+        };
+        let Ok(interval) = querystring.get_as::<u64, _>(INTERVAL_FIELD) else {
+            // The interval is invalid
+            return None;
+        };
+        (kind, url.to_string(), interval.map(Duration::from_millis).unwrap_or(SnapshotSource::DEFAULT_INTERVAL))
+    };
+
+    // Get the associated camera service
+    let (source, key) = source(kind, &url, interval).ok()?;
+    Some(P1Service::get_or_start(&key, || P1Service::start(&key, source)))
+}
+
+/// Gets the last JPEG for the given HTTP camera (via `POST /v1/http` or `GET /v1/http/snapshot`)
+pub fn post(request: Request, config: &Arc<Config>, _: AuthTicket) -> Result<Response, Error> {
+    // Only allow configured cameras; others are not found, since `403` is reserved for rejected API keys
+    if !is_allowed(&request, config) {
+        return Ok(Response::new_404_notfound());
+    }
+
+    // Get the requested rendition
+    let Ok(rendition) = rendition::from_request(&request, p1::device_transform(&request, config)) else {
+        // The rendition is invalid
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Get the camera service
+    let Some(service) = image_service(&request, config) else {
+        // The query string was invalid or incomplete
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Get the image
+    let mut response = Response::new_200_ok();
+    if let Some(image) = service.jpeg(rendition.as_ref())? {
+        // Set the image as body
+        response.set_body_data(image);
+        response.set_content_type("image/jpeg");
+    } else {
+        // Set text/plain
+        response.set_content_type("text/plain");
+    }
+    Ok(response)
+}
+
+/// Streams the live MJPEG stream for the given HTTP camera
+pub fn stream(request: Request, config: &Arc<Config>, _: AuthTicket) -> Result<Response, Error> {
+    // Only allow configured cameras; others are not found, since `403` is reserved for rejected API keys
+    if !is_allowed(&request, config) {
+        return Ok(Response::new_404_notfound());
+    }
+
+    // Get the requested rendition
+    let Ok(rendition) = rendition::from_request(&request, p1::device_transform(&request, config)) else {
+        // The rendition is invalid
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Get the camera service
+    let Some(service) = image_service(&request, config) else {
+        // The query string was invalid or incomplete
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Stream the frames
+    Ok(FrameStream::response(service, rendition))
+}
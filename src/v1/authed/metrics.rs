@@ -0,0 +1,18 @@
+//! Exposes the Prometheus metrics
+
+use crate::{
+    error::Error,
+    services::{config::Config, metrics},
+    v1::authed::AuthTicket,
+};
+use ehttpd::http::{Request, Response, ResponseExt};
+use std::sync::Arc;
+
+/// Renders the metrics in the Prometheus text format
+pub fn get(_: Request, _: &Arc<Config>, _: AuthTicket) -> Result<Response, Error> {
+    let mut response = Response::new_200_ok();
+    response.set_body_data(metrics::render());
+    response.set_content_type("text/plain; version=0.0.4; charset=utf-8");
+    response.set_field("Cache-Control", "no-store");
+    Ok(response)
+}
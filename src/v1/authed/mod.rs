@@ -1,23 +1,43 @@
 //! Authed API endpoints
 
+pub mod admin;
+pub mod dashboard;
+pub mod devices;
+pub mod health;
+pub mod http;
+pub mod metrics;
+pub mod mosaic;
 pub mod p1;
+pub mod replay;
+pub mod x1;
 
-use crate::{error::Error, services::config::Config};
+use crate::{
+    error::Error,
+    services::{config::Config, listener::AuthPolicy, metrics::record_auth_failure},
+};
 use ehttpd::http::{Request, Response, ResponseExt};
 use ehttpd_querystring::RequestQuerystringExt;
 use sha2::{Digest, Sha256};
 use std::{borrow::Cow, sync::Arc};
 
+/// A ticket to assert a request is authed for a class of endpoints
+pub trait Ticket: Sized {
+    /// Creates a new ticket if the given auth token is valid
+    fn check(authtoken: &[u8], config: &Arc<Config>) -> Option<Self>;
+
+    /// Creates a new ticket without auth token if the listener's auth policy allows it
+    fn trusted(policy: AuthPolicy) -> Option<Self>;
+}
+
 /// A ticket to assert a request is authed
 pub struct AuthTicket {
     _private: (),
 }
-impl AuthTicket {
-    /// Creates a new authed token
-    pub(in crate::v1::authed) fn check(authtoken: &[u8], config: &Arc<Config>) -> Option<Self> {
+impl Ticket for AuthTicket {
+    fn check(authtoken: &[u8], config: &Arc<Config>) -> Option<Self> {
         // Hash authtoken and validate hash
         let authdigest = format!("{:x}", Sha256::digest(authtoken));
-        let true = authdigest == config.BAMBORVIDEOSTREAM_APIKEYSHA256 else {
+        let true = config.BAMBORVIDEOSTREAM_APIKEYSHA256.matches(&authdigest) else {
             // Invalid auth token
             return None;
         };
@@ -25,16 +45,51 @@ impl AuthTicket {
         // Assert correct auth
         Some(Self { _private: () })
     }
+
+    fn trusted(policy: AuthPolicy) -> Option<Self> {
+        match policy {
+            AuthPolicy::Trusted | AuthPolicy::Admin => Some(Self { _private: () }),
+            AuthPolicy::ApiKey => None,
+        }
+    }
+}
+
+/// A ticket to assert a request is authed with the admin API key
+pub struct AdminTicket {
+    _private: (),
 }
+impl Ticket for AdminTicket {
+    fn check(authtoken: &[u8], config: &Arc<Config>) -> Option<Self> {
+        // Hash authtoken and validate hash; without an admin API key, no token is valid
+        let adminapikeysha256 = config.BAMBORVIDEOSTREAM_ADMINAPIKEYSHA256.as_ref()?;
+        let authdigest = format!("{:x}", Sha256::digest(authtoken));
+        let true = adminapikeysha256.matches(&authdigest) else {
+            // Invalid auth token
+            return None;
+        };
 
-/// Validates auth and calls the endpoint directly
-pub fn call<T>(endpoint: T, request: Request, config: &Arc<Config>) -> Result<Response, Error>
+        // Assert correct auth
+        Some(Self { _private: () })
+    }
+
+    fn trusted(policy: AuthPolicy) -> Option<Self> {
+        // `auth=none` listeners may be reachable from a LAN, so only local `auth=admin` listeners skip the admin key
+        match policy {
+            AuthPolicy::Admin => Some(Self { _private: () }),
+            AuthPolicy::ApiKey | AuthPolicy::Trusted => None,
+        }
+    }
+}
+
+/// Validates auth according to the listener's auth policy and the ticket type and calls the endpoint directly
+pub fn call<K, T>(endpoint: T, request: Request, config: &Arc<Config>, policy: AuthPolicy) -> Result<Response, Error>
 where
-    T: FnOnce(Request, &Arc<Config>, AuthTicket) -> Result<Response, Error>,
+    K: Ticket,
+    T: FnOnce(Request, &Arc<Config>, K) -> Result<Response, Error>,
 {
     /// The name of the authentication field
     const AUTH_FIELD: &[u8] = b"auth";
-    /// The SHA-256 hash of an empty input
+    /// The empty auth token if the field is missing
     const EMPTY: Cow<'_, [u8]> = Cow::Borrowed(b"");
 
     // Get querystring
@@ -43,13 +98,28 @@ where
         return Ok(Response::new_400_badrequest());
     };
 
+    // Trusted listeners do not require an auth token
+    if let Some(ticket) = K::trusted(policy) {
+        return endpoint(request, config, ticket);
+    }
+
     // Validate auth token
     let authtoken = querystring.get(AUTH_FIELD).unwrap_or(&EMPTY);
-    let Some(ticket) = AuthTicket::check(authtoken, config) else {
+    let Some(ticket) = K::check(authtoken, config) else {
         // Invalid auth
+        record_auth_failure();
         return Ok(Response::new_403_forbidden());
     };
 
     // Call endpoint
     endpoint(request, config, ticket)
 }
+
+/// Creates a `200` response with the given JSON body that must not be cached
+fn json_response(json: String) -> Response {
+    let mut response = Response::new_200_ok();
+    response.set_body_data(json);
+    response.set_content_type("application/json");
+    response.set_field("Cache-Control", "no-store");
+    response
+}
@@ -0,0 +1,152 @@
+//! Gets the mosaic of several P1 devices or HTTP cameras as single JPEG or as live stream
+//!
+//! The devices are given by name via `devices=<name>,<name>,...` and default to all devices that deliver JPEGs (i.e.
+//! all but X1 devices); `layout=<columns>x<rows>` and `tile=<width>x<height>` select the grid and the size of each
+//! tile.
+
+use crate::{
+    error::Error,
+    log::Level,
+    services::{
+        camera::Source,
+        config::{Config, DeviceKind},
+        image::transform::Transform,
+        p1::{
+            mosaic::{self, MosaicSource, MosaicTile},
+            P1Service,
+        },
+    },
+    v1::{
+        authed::{http, p1, AuthTicket},
+        rendition,
+        stream::FrameStream,
+    },
+};
+use ehttpd::http::{Request, Response, ResponseExt};
+use ehttpd_querystring::{querystringext::QueryStringExt, RequestQuerystringExt};
+use std::{sync::Arc, time::Duration};
+
+/// Parses a `<width>x<height>` pair
+fn parse_size(size: &str) -> Option<(usize, usize)> {
+    let (width, height) = size.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+/// Gets the mosaic service for the requested devices
+fn image_service(request: &Request, config: &Config) -> Option<Arc<P1Service>> {
+    /// The name of the device names field
+    const DEVICES_FIELD: &[u8] = b"devices";
+    /// The name of the layout field
+    const LAYOUT_FIELD: &[u8] = b"layout";
+    /// The name of the tile size field
+    const TILE_FIELD: &[u8] = b"tile";
+
+    // Get the device names
+    let querystring = request.querystring().ok()?;
+    let all_devices = config.all_devices();
+    let names: Vec<&str> = match querystring.get_str(DEVICES_FIELD).ok()? {
+        Some(names) => names.split(',').collect(),
+        None => all_devices
+            .iter()
+            .filter(|(_, device)| device.kind != DeviceKind::X1)
+            .map(|(name, _)| name.as_str())
+            .collect(),
+    };
+
+    // Get the layout and the tile size
+    let layout = match querystring.get_str(LAYOUT_FIELD).ok()? {
+        Some(layout) => Some(parse_size(layout)?),
+        None => None,
+    };
+    let tile_size = match querystring.get_str(TILE_FIELD).ok()? {
+        Some(tile_size) => parse_size(tile_size)?,
+        None => MosaicSource::DEFAULT_TILE_SIZE,
+    };
+
+    // Resolve the devices; X1 devices deliver no JPEGs
+    let mut tiles = Vec::new();
+    for name in &names {
+        let device = all_devices.get(*name)?;
+        let source: Arc<dyn Source> = match device.kind {
+            DeviceKind::P1 => {
+                let pin = device.current_pin()?;
+                Arc::new(p1::source(&device.address, &pin, device.fingerprint, device.capture, config))
+            }
+            DeviceKind::Mjpeg | DeviceKind::Snapshot => {
+                http::source(device.kind, &device.address, device.polling_interval()).ok()?.0
+            }
+            DeviceKind::X1 => return None,
+        };
+        tiles.push(MosaicTile {
+            name: name.to_string(),
+            key: device.service_key(),
+            source,
+            transform: device.transform,
+        });
+    }
+
+    // Get the associated mosaic service
+    let source = match MosaicSource::new(tiles, layout, tile_size) {
+        Ok(source) => source,
+        Err(e) => {
+            // The mosaic is invalid
+            e.log_as(Level::Warn);
+            return None;
+        }
+    };
+    let layout = layout.map(|(columns, rows)| format!("{columns}x{rows}")).unwrap_or_else(|| "auto".to_string());
+    let key = format!("{}{}@{layout}/{}x{}", mosaic::KEY_PREFIX, names.join(","), tile_size.0, tile_size.1);
+    Some(P1Service::get_or_start(&key, || P1Service::with_source(&key, source)))
+}
+
+/// Gets the current mosaic as JPEG
+pub fn get(request: Request, config: &Arc<Config>, _: AuthTicket) -> Result<Response, Error> {
+    /// The time to wait for the first mosaic of a new service
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // Get the requested rendition
+    let Ok(rendition) = rendition::from_request(&request, Transform::IDENTITY) else {
+        // The rendition is invalid
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Get the mosaic service
+    let Some(service) = image_service(&request, config) else {
+        // The query string was invalid or incomplete
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Get the image
+    let mut response = Response::new_200_ok();
+    if let Some((_, frame)) = service.latest_frame(None, TIMEOUT) {
+        // Set the image as body
+        let frame = match rendition {
+            Some(rendition) => service.rendition(&frame, &rendition)?,
+            None => frame,
+        };
+        response.set_body_data(frame.data.to_vec());
+        response.set_content_type(frame.content_type);
+    } else {
+        // Set text/plain
+        response.set_content_type("text/plain");
+    }
+    Ok(response)
+}
+
+/// Streams the live mosaic as MJPEG stream
+pub fn stream(request: Request, config: &Arc<Config>, _: AuthTicket) -> Result<Response, Error> {
+    // Get the requested rendition
+    let Ok(rendition) = rendition::from_request(&request, Transform::IDENTITY) else {
+        // The rendition is invalid
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Get the mosaic service
+    let Some(service) = image_service(&request, config) else {
+        // The query string was invalid or incomplete
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Stream the frames
+    Ok(FrameStream::response(service, rendition))
+}
@@ -1,64 +1,107 @@
-//! Gets the last JPEG for the given P1 device
+//! Gets the last JPEG or the live stream for the given P1 device
 
 use crate::{
     error::Error,
-    services::{config::Config, p1::P1Service},
-    v1::authed::AuthTicket,
+    secret::Secret,
+    services::{
+        config::{self, Config, DeviceKind},
+        image::transform::Transform,
+        p1::{self, P1Service, P1Source},
+    },
+    v1::{authed::AuthTicket, rendition, stream::FrameStream},
 };
-use core::str;
 use ehttpd::http::{Request, Response, ResponseExt};
 use ehttpd_querystring::{querystringext::QueryStringExt, RequestQuerystringExt};
-use std::sync::{Arc, Weak};
+use std::sync::Arc;
 
-/// Gets the service for the given P1 device
-fn image_service(address: &str, pin: &str) -> Arc<P1Service> {
-    // Get the associated device service
-    #[allow(clippy::expect_used, reason = "Locking a mutex should never fail under normal conditions")]
-    let mut services = P1Service::services().lock().expect("Failed to lock services registry");
-
-    // Try to get a living service for the given device
-    let maybe_service = services.get(address).and_then(Weak::upgrade);
-    if let Some(service) = maybe_service {
-        // The service is still alive, use it
-        service
-    } else {
-        // Create new service and get a weak reference for the registry
-        let service = P1Service::new(address, pin);
-        let service_weak = Arc::downgrade(&service);
-
-        // Register the weak reference and return the service
-        services.insert(address.to_string(), service_weak);
-        service
-    }
-}
+/// The name of the configured device field
+const DEVICE_FIELD: &[u8] = b"device";
 
-/// Gets the last JPEG for the given P1 device
-pub fn post(request: Request, _: &Arc<Config>, _: AuthTicket) -> Result<Response, Error> {
+/// Gets the service for the given P1 device
+pub fn image_service(request: &Request, config: &Config) -> Option<Arc<P1Service>> {
     /// The name of the device address field
     const DEVICEADDRESS_FIELD: &[u8] = b"address";
     /// The name of the device PIN field
     const DEVICEPIN_FIELD: &[u8] = b"pin";
+    /// The name of the optional certificate fingerprint field
+    const FINGERPRINT_FIELD: &[u8] = b"fingerprint";
 
-    // Get the query string
-    let Ok(querystring) = request.querystring() else {
-        // The query string was invalid
-        return Ok(Response::new_400_badrequest());
-    };
+    // Use a configured device if given
+    let querystring = request.querystring().ok()?;
+    if let Ok(Some(name)) = querystring.get_str(DEVICE_FIELD) {
+        let device = config.device(name).filter(|device| device.kind == DeviceKind::P1)?;
+        let pin = device.current_pin()?;
+        return Some(start_service(&device.address, &pin, device.fingerprint, device.capture, config));
+    }
 
     // Get the device name and secret
     let Ok(Some(address)) = querystring.get_str(DEVICEADDRESS_FIELD) else {
         // The device address is missing
-        return Ok(Response::new_400_badrequest());
+        return None;
     };
     let Ok(Some(pin)) = querystring.get_str(DEVICEPIN_FIELD) else {
         // The device PIN is missing
+        return None;
+    };
+
+    let Ok(fingerprint) = querystring.get_str(FINGERPRINT_FIELD) else {
+        // The fingerprint is invalid
+        return None;
+    };
+    let fingerprint = match fingerprint {
+        Some(fingerprint) => Some(p1::parse_fingerprint(fingerprint)?),
+        None => None,
+    };
+    Some(start_service(address, &Secret::new(pin), fingerprint, false, config))
+}
+
+/// Gets the configured transform of the requested device, or the identity if no configured device is requested
+pub fn device_transform(request: &Request, config: &Config) -> Transform {
+    let Ok(querystring) = request.querystring() else {
+        return Transform::IDENTITY;
+    };
+    match querystring.get_str(DEVICE_FIELD) {
+        Ok(Some(name)) => config.device(name).map(|device| device.transform).unwrap_or_default(),
+        _ => Transform::IDENTITY,
+    }
+}
+
+/// Creates the camera source for the given P1 device, which records its sessions if `capture` is set
+pub fn source(address: &str, pin: &Secret, fingerprint: Option<[u8; 32]>, capture: bool, config: &Config) -> P1Source {
+    let capture_dir = config.BAMBORVIDEOSTREAM_CAPTUREDIR.as_ref().filter(|_| capture);
+    P1Source::new(address, pin).with_capture(capture_dir, config.capture_max_bytes()).with_pinned_sha256(fingerprint)
+}
+
+/// Gets the living service for the given P1 device or starts a new one
+fn start_service(
+    address: &str,
+    pin: &Secret,
+    fingerprint: Option<[u8; 32]>,
+    capture: bool,
+    config: &Config,
+) -> Arc<P1Service> {
+    let source = source(address, pin, fingerprint, capture, config);
+    let key = config::service_key(DeviceKind::P1, address);
+    P1Service::get_or_start(&key, || P1Service::with_source(&key, source))
+}
+
+/// Gets the last JPEG for the given P1 device (via `POST /v1/p1` or `GET /v1/p1/snapshot`)
+pub fn post(request: Request, config: &Arc<Config>, _: AuthTicket) -> Result<Response, Error> {
+    // Get the requested rendition
+    let Ok(rendition) = rendition::from_request(&request, device_transform(&request, config)) else {
+        // The rendition is invalid
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Get the device service
+    let Some(service) = image_service(&request, config) else {
+        // The query string was invalid or incomplete
         return Ok(Response::new_400_badrequest());
     };
 
     // Get the image
     let mut response = Response::new_200_ok();
-    let service = image_service(address, pin);
-    if let Some(image) = service.jpeg() {
+    if let Some(image) = service.jpeg(rendition.as_ref())? {
         // Set the image as body
         response.set_body_data(image);
         response.set_content_type("image/jpeg");
@@ -68,3 +111,21 @@ pub fn post(request: Request, _: &Arc<Config>, _: AuthTicket) -> Result<Response
     }
     Ok(response)
 }
+
+/// Streams the live MJPEG stream for the given P1 device
+pub fn stream(request: Request, config: &Arc<Config>, _: AuthTicket) -> Result<Response, Error> {
+    // Get the requested rendition
+    let Ok(rendition) = rendition::from_request(&request, device_transform(&request, config)) else {
+        // The rendition is invalid
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Get the device service
+    let Some(service) = image_service(&request, config) else {
+        // The query string was invalid or incomplete
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Stream the frames
+    Ok(FrameStream::response(service, rendition))
+}
@@ -0,0 +1,106 @@
+//! Gets the last JPEG or the live stream for a replayed P1 capture
+
+use crate::{
+    error::Error,
+    services::{
+        config::Config,
+        image::transform::Transform,
+        p1::{replay::ReplaySource, P1Service},
+    },
+    v1::{authed::AuthTicket, rendition, stream::FrameStream},
+};
+use ehttpd::http::{Request, Response, ResponseExt};
+use ehttpd_querystring::{querystringext::QueryStringExt, RequestQuerystringExt};
+use std::{path::Path, sync::Arc};
+
+/// Gets the service for the given capture file
+fn image_service(request: &Request, config: &Config) -> Result<Option<Arc<P1Service>>, Error> {
+    /// The name of the capture file name field
+    const FILE_FIELD: &[u8] = b"file";
+    /// The name of the playback speed field
+    const SPEED_FIELD: &[u8] = b"speed";
+
+    // Get the capture directory
+    let Some(capture_dir) = &config.BAMBORVIDEOSTREAM_CAPTUREDIR else {
+        // Replays are disabled
+        return Ok(None);
+    };
+
+    // Get the query string
+    let Ok(querystring) = request.querystring() else {
+        // The query string was invalid
+        return Ok(None);
+    };
+
+    // Get the capture file name and ensure it does not escape the capture directory
+    let Ok(Some(file)) = querystring.get_str(FILE_FIELD) else {
+        // The capture file name is missing
+        return Ok(None);
+    };
+    if file.starts_with('.') || file.contains(['/', '\\']) {
+        // The capture file name is invalid
+        return Ok(None);
+    }
+    let Ok(speed) = querystring.get_str(SPEED_FIELD) else {
+        // The playback speed is invalid
+        return Ok(None);
+    };
+    let Ok(speed) = speed.map(str::parse).unwrap_or(Ok(ReplaySource::DEFAULT_SPEED)) else {
+        // The playback speed is not a number
+        return Ok(None);
+    };
+
+    // Get the associated replay service
+    let Ok(source) = ReplaySource::new(Path::new(capture_dir).join(file), speed) else {
+        // The playback speed is out of range
+        return Ok(None);
+    };
+    let key = format!("replay+{file}@{speed}");
+    let service = P1Service::get_or_start(&key, || P1Service::with_source(&key, source));
+    Ok(Some(service))
+}
+
+/// Gets the last JPEG for the given capture file
+pub fn post(request: Request, config: &Arc<Config>, _: AuthTicket) -> Result<Response, Error> {
+    // Get the requested rendition
+    let Ok(rendition) = rendition::from_request(&request, Transform::IDENTITY) else {
+        // The rendition is invalid
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Get the replay service
+    let Ok(Some(service)) = image_service(&request, config) else {
+        // The query string was invalid or incomplete
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Get the image
+    let mut response = Response::new_200_ok();
+    if let Some(image) = service.jpeg(rendition.as_ref())? {
+        // Set the image as body
+        response.set_body_data(image);
+        response.set_content_type("image/jpeg");
+    } else {
+        // Set text/plain
+        response.set_content_type("text/plain");
+    }
+    Ok(response)
+}
+
+/// Streams the replayed MJPEG stream for the given capture file
+pub fn stream(request: Request, config: &Arc<Config>, _: AuthTicket) -> Result<Response, Error> {
+    // Get the requested rendition
+    let Ok(rendition) = rendition::from_request(&request, Transform::IDENTITY) else {
+        // The rendition is invalid
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Get the replay service
+    let Ok(Some(service)) = image_service(&request, config) else {
+        // The query string was invalid or incomplete
+        return Ok(Response::new_400_badrequest());
+    };
+
+    // Stream the frames
+    Ok(FrameStream::response(service, rendition))
+}
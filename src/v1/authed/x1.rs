@@ -0,0 +1,56 @@
+//! Streams the live H.264 stream for the given X1 device
+
+use crate::{
+    error::Error,
+    secret::Secret,
+    services::{
+        config::{self, Config, DeviceKind},
+        p1::P1Service,
+        x1::X1Source,
+    },
+    v1::{authed::AuthTicket, stream::FrameStream},
+};
+use ehttpd::http::{Request, Response, ResponseExt};
+use ehttpd_querystring::{querystringext::QueryStringExt, RequestQuerystringExt};
+use std::sync::Arc;
+
+/// Gets the service for the given X1 device
+pub fn image_service(request: &Request, config: &Config) -> Option<Arc<P1Service>> {
+    /// The name of the configured device field
+    const DEVICE_FIELD: &[u8] = b"device";
+    /// The name of the device address field
+    const DEVICEADDRESS_FIELD: &[u8] = b"address";
+    /// The name of the device PIN field
+    const DEVICEPIN_FIELD: &[u8] = b"pin";
+
+    // Get the device name and secret, either from a configured device or from the query string
+    let querystring = request.querystring().ok()?;
+    let (address, pin) = if let Ok(Some(name)) = querystring.get_str(DEVICE_FIELD) {
+        let device = config.device(name).filter(|device| device.kind == DeviceKind::X1)?;
+        (device.address.clone(), device.current_pin()?)
+    } else {
+        let Ok(Some(address)) = querystring.get_str(DEVICEADDRESS_FIELD) else {
+            // The device address is missing
+            return None;
+        };
+        let Ok(Some(pin)) = querystring.get_str(DEVICEPIN_FIELD) else {
+            // The device PIN is missing
+            return None;
+        };
+        (address.to_string(), Secret::new(pin))
+    };
+
+    // Get the associated device service
+    let key = config::service_key(DeviceKind::X1, &address);
+    Some(P1Service::get_or_start(&key, || P1Service::with_source(&key, X1Source::new(&address, &pin))))
+}
+
+/// Streams the live H.264 stream for the given X1 device
+pub fn stream(request: Request, config: &Arc<Config>, _: AuthTicket) -> Result<Response, Error> {
+    // Get the device service
+    let Some(service) = image_service(&request, config) else {
+        // The query string was invalid or incomplete
+        return Ok(Response::new_400_badrequest());
+    };
+    Ok(FrameStream::response(service, None))
+}
@@ -0,0 +1,45 @@
+//! The unauthenticated liveness and readiness probes for container orchestration
+
+use crate::{
+    error::Error,
+    services::{config::Config, health::Readiness},
+};
+use ehttpd::http::{Request, Response, ResponseExt};
+use ehttpd_querystring::RequestQuerystringExt;
+use std::sync::Arc;
+
+/// Whether the request asks for the detailed readiness via `?verbose`
+pub fn is_verbose(request: &Request) -> bool {
+    /// The name of the verbose field
+    const VERBOSE_FIELD: &[u8] = b"verbose";
+
+    let Ok(querystring) = request.querystring() else {
+        return false;
+    };
+    let verbose = querystring.get(VERBOSE_FIELD);
+    verbose.is_some_and(|verbose| !matches!(verbose.as_ref(), b"0" | b"false"))
+}
+
+/// Reports that the server is alive
+pub fn healthz(_: Request) -> Result<Response, Error> {
+    Ok(text(Response::new_200_ok(), "ok\n"))
+}
+
+/// Reports whether the server is ready, i.e. whether all configured devices are idle or fresh
+///
+/// # Note
+/// This responds with `503` if a device is stale; use `?verbose` (which requires auth) for the details.
+pub fn readyz(_: Request, config: &Arc<Config>) -> Result<Response, Error> {
+    match Readiness::check(config).is_ready() {
+        true => Ok(text(Response::new_200_ok(), "ready\n")),
+        false => Ok(Response::new_status_reason(503, "Service Unavailable")),
+    }
+}
+
+/// Sets the given plain text body
+fn text(mut response: Response, body: &'static str) -> Response {
+    response.set_body_data(body);
+    response.set_content_type("text/plain; charset=utf-8");
+    response.set_field("Cache-Control", "no-store");
+    response
+}
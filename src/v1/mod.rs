@@ -1,4 +1,7 @@
 //! The v1 API
 
 pub mod authed;
+pub mod health;
+pub mod rendition;
 pub mod site;
+pub mod stream;
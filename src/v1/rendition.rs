@@ -0,0 +1,52 @@
+//! Parses the requested rendition of JPEG frames
+
+use crate::{
+    error,
+    error::Error,
+    services::image::{rendition::Rendition, transform::Transform},
+};
+use ehttpd::http::Request;
+use ehttpd_querystring::{querystringext::QueryStringExt, RequestQuerystringExt};
+use std::str::FromStr;
+
+/// Parses the optional query field with the given name
+fn parse<T>(request: &Request, name: &[u8]) -> Result<Option<T>, Error>
+where
+    T: FromStr,
+{
+    let querystring = request.querystring()?;
+    let Some(value) = querystring.get_str(name)? else {
+        return Ok(None);
+    };
+    let value = value.parse().map_err(|_| error!("Invalid {} value: {value}", String::from_utf8_lossy(name)))?;
+    Ok(Some(value))
+}
+
+/// Gets the rendition from the `width`, `height` and `quality` query fields and the given base transform, or `None` if
+/// none of them is given
+///
+/// # Note
+/// The transform fields `rotate`, `mirror`, `crop`, `brightness`, `contrast` and `gamma` override the respective fields
+/// of the base transform (e.g. the transform of the configured device); `crop=none` removes a configured crop.
+pub fn from_request(request: &Request, base: Transform) -> Result<Option<Rendition>, Error> {
+    /// The name of the maximum width field
+    const WIDTH_FIELD: &[u8] = b"width";
+    /// The name of the maximum height field
+    const HEIGHT_FIELD: &[u8] = b"height";
+    /// The name of the JPEG quality field
+    const QUALITY_FIELD: &[u8] = b"quality";
+
+    // Parse the fields
+    let width = parse(request, WIDTH_FIELD)?;
+    let height = parse(request, HEIGHT_FIELD)?;
+    let quality = parse(request, QUALITY_FIELD)?;
+
+    // Apply the transform overrides
+    let (querystring, mut transform) = (request.querystring()?, base);
+    for field in Transform::FIELDS {
+        if let Some(value) = querystring.get_str(field.as_bytes())? {
+            transform.set(field, value)?;
+        }
+    }
+    Rendition::new(width, height, quality, transform)
+}
@@ -8,6 +8,9 @@ use ehttpd::http::{Request, Response, ResponseExt};
 const fn sitemap(url: &[u8]) -> Option<(&'static [u8], &'static [u8])> {
     match url {
         b"/site/app.html" => Some((b"text/html", include_bytes!("../../site/app.html"))),
+        b"/site/dashboard.html" => Some((b"text/html", include_bytes!("../../site/dashboard.html"))),
+        b"/site/dashboard.js" => Some((b"application/javascript", include_bytes!("../../site/dashboard.js"))),
+        b"/site/http.html" => Some((b"text/html", include_bytes!("../../site/http.html"))),
         b"/site/p1.html" => Some((b"text/html", include_bytes!("../../site/p1.html"))),
         b"/site/p1.js" => Some((b"application/javascript", include_bytes!("../../site/p1.js"))),
         b"/site/loading.js" => Some((b"application/javascript", include_bytes!("../../site/loading.js"))),
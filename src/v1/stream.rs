@@ -0,0 +1,171 @@
+//! Streaming response bodies for live camera frames
+
+use crate::{
+    log::Level,
+    services::{
+        image::rendition::Rendition,
+        metrics::Viewer,
+        p1::P1Service,
+        shutdown::{self, Activity},
+    },
+};
+use ehttpd::{
+    bytes::Source,
+    http::{Response, ResponseExt},
+};
+use std::{
+    io::{self, Cursor, Read},
+    panic::AssertUnwindSafe,
+    sync::Arc,
+    time::Duration,
+};
+
+/// A streaming body that relays the frames of a service
+///
+/// # Note
+/// JPEG frames are delivered as `multipart/x-mixed-replace` MJPEG stream, which always skips to the most recent frame;
+/// all other frames (e.g. H.264 access units) are relayed as-is and in order.
+#[derive(Debug)]
+pub struct FrameStream {
+    /// The service to relay
+    service: AssertUnwindSafe<Arc<P1Service>>,
+    /// The sequence number of the last relayed frame
+    sequence: Option<u64>,
+    /// The pending bytes of the current frame
+    buffer: Cursor<Vec<u8>>,
+    /// Whether to wrap the frames into a multipart stream
+    multipart: bool,
+    /// The rendition of the JPEG frames, if any
+    rendition: Option<Rendition>,
+    /// Whether the stream has been finished
+    finished: bool,
+    /// Delays a graceful shutdown until the stream has been finished
+    _activity: Activity,
+    /// Counts the stream as viewer of the service
+    _viewer: Viewer,
+}
+impl FrameStream {
+    /// The multipart boundary
+    pub const BOUNDARY: &'static str = "bamborvideostream-frame";
+    /// The time to wait for a new frame before checking the service state again
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Creates a streaming response for the given service; JPEG frames are delivered as the given rendition, if any
+    pub fn response(service: Arc<P1Service>, rendition: Option<Rendition>) -> Response {
+        // Select the stream type
+        let multipart = service.content_type() == "image/jpeg";
+        let content_type = match multipart {
+            true => format!("multipart/x-mixed-replace; boundary={}", Self::BOUNDARY),
+            false => service.content_type().to_string(),
+        };
+
+        // Create the response without a content length
+        let mut response = Response::new_200_ok();
+        response.fields.retain(|(key, _)| !key.eq_ignore_ascii_case(b"Content-Length"));
+        response.set_content_type(content_type);
+        response.set_field("Cache-Control", "no-store");
+        response.set_connection_close();
+
+        // Set the stream as body
+        let viewer = Viewer::start(service.key());
+        let stream = Self {
+            service: AssertUnwindSafe(service),
+            sequence: None,
+            buffer: Cursor::default(),
+            multipart,
+            rendition,
+            finished: false,
+            _activity: Activity::start(),
+            _viewer: viewer,
+        };
+        response.body = Source::from_other(stream);
+        response
+    }
+
+    /// Waits for the next frame and fills the buffer, or returns `false` if the stream has been finished
+    fn refill(&mut self) -> bool {
+        loop {
+            // Finish the stream if we are shutting down
+            if shutdown::is_requested() {
+                return self.finish();
+            }
+
+            // Wait for the next frame
+            let maybe_frame = match self.multipart {
+                true => self.service.latest_frame(self.sequence, Self::TIMEOUT),
+                false => self.service.next_frame(self.sequence, Self::TIMEOUT),
+            };
+            let Some((sequence, frame)) = maybe_frame else {
+                // Continue to wait as long as the service is alive
+                match self.service.is_terminated() {
+                    true => return self.finish(),
+                    false => continue,
+                }
+            };
+
+            // Render the requested rendition of JPEG frames; frames that cannot be rendered are skipped
+            self.sequence = Some(sequence);
+            let frame = match &self.rendition {
+                Some(rendition) if self.multipart => match self.service.rendition(&frame, rendition) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        e.log_as(Level::Debug);
+                        continue;
+                    }
+                },
+                _ => frame,
+            };
+
+            // Assemble the frame
+            let mut buffer = Vec::new();
+            if self.multipart {
+                let header = format!(
+                    "--{}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                    Self::BOUNDARY,
+                    frame.content_type,
+                    frame.data.len()
+                );
+                buffer.extend_from_slice(header.as_bytes());
+            }
+            buffer.extend_from_slice(&frame.data);
+            if self.multipart {
+                buffer.extend_from_slice(b"\r\n");
+            }
+
+            // Set the buffer
+            self.buffer = Cursor::new(buffer);
+            return true;
+        }
+    }
+
+    /// Fills the buffer with the final multipart boundary, or returns `false` if the stream has already been finished
+    fn finish(&mut self) -> bool {
+        // Only multipart streams have a final boundary
+        if self.finished || !self.multipart {
+            self.finished = true;
+            return false;
+        }
+
+        // Set the final boundary
+        let boundary = format!("--{}--\r\n", Self::BOUNDARY);
+        self.buffer = Cursor::new(boundary.into_bytes());
+        self.finished = true;
+        true
+    }
+}
+impl Read for FrameStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // Drain the buffer first
+            let read = self.buffer.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+
+            // Fetch the next frame
+            if !self.refill() {
+                return Ok(0);
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Drives the P1 client library against the `mock-p1` command on an ephemeral port
+
+use bamborvideostream_p1::{protocol::FRAME_HEADER_VERSION, ConnectOptions, Connection, Error, FrameHeader};
+use std::{
+    io::{BufRead, BufReader, ErrorKind},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+/// The PIN of the mock printer
+const PIN: &str = "12345678";
+
+/// A running mock printer that is killed on drop
+struct Mock {
+    /// The mock process
+    child: Child,
+    /// The address the mock listens on
+    address: String,
+    /// The log lines of the mock after the listening event
+    log: Receiver<String>,
+}
+impl Mock {
+    /// Starts the mock printer with the given additional arguments
+    fn start(args: &[&str]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bamborvideostream"))
+            .args(["mock-p1", "--listen", "127.0.0.1:0", "--interval-ms", "20", "--pin", PIN])
+            .args(args)
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to start the mock printer");
+
+        // Get the ephemeral address from the log and forward the remaining log lines
+        let mut lines = BufReader::new(child.stderr.take().unwrap()).lines();
+        let address = lines
+            .by_ref()
+            .map(Result::unwrap)
+            .find_map(|line| line.split_whitespace().find_map(|field| field.strip_prefix("address=")).map(String::from))
+            .expect("Mock printer did not log its address");
+        let (sender, log) = mpsc::channel();
+        thread::spawn(move || lines.map_while(Result::ok).try_for_each(|line| sender.send(line)));
+        Self { child, address, log }
+    }
+
+    /// Connects to the mock with a short timeout
+    fn connect(&self) -> Connection {
+        let options = ConnectOptions { timeout: Some(Duration::from_millis(500)), ..ConnectOptions::default() };
+        Connection::connect(&self.address, &options).unwrap()
+    }
+
+    /// Waits for a log line that contains the given text
+    fn expect_log(&self, text: &str) {
+        while let Ok(line) = self.log.recv_timeout(Duration::from_secs(5)) {
+            if line.contains(text) {
+                return;
+            }
+        }
+        panic!("Mock printer did not log {text:?}");
+    }
+}
+impl Drop for Mock {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Asserts that the error is an I/O error, e.g. because the mock closed the connection
+fn assert_io(error: &Error) {
+    assert!(matches!(error, Error::Io(_)), "{error}");
+}
+
+/// A valid login yields complete JPEGs with a consistent frame header
+#[test]
+fn login_and_frames() {
+    let mock = Mock::start(&[]);
+    let mut session = mock.connect().login(PIN).unwrap();
+    for frame in session.frames().take(3) {
+        let frame = frame.unwrap();
+        assert_eq!(frame.header.size as usize, frame.jpeg.len());
+        assert_eq!(frame.header.raw[4..], FRAME_HEADER_VERSION);
+        assert_eq!(FrameHeader::parse(frame.header.raw), frame.header);
+        assert_eq!(FrameHeader::new(frame.header.size), frame.header);
+        assert!(frame.jpeg.starts_with(&[0xff, 0xd8]) && frame.jpeg.ends_with(&[0xff, 0xd9]));
+    }
+    session.close().unwrap();
+}
+
+/// A wrong PIN makes the mock close the connection without frames
+#[test]
+fn wrong_pin() {
+    let mock = Mock::start(&[]);
+    let mut session = mock.connect().login("87654321").unwrap();
+    assert_io(&session.frame().unwrap_err());
+    mock.expect_log("Rejected login");
+}
+
+/// `--reject-login` rejects even the correct PIN
+#[test]
+fn reject_login() {
+    let mock = Mock::start(&["--reject-login"]);
+    let mut session = mock.connect().login(PIN).unwrap();
+    assert_io(&session.frame().unwrap_err());
+    mock.expect_log("Rejected login");
+}
+
+/// `--stall-after` keeps the connection open without frames, so the read times out
+#[test]
+fn stall() {
+    let mock = Mock::start(&["--stall-after", "2"]);
+    let mut session = mock.connect().login(PIN).unwrap();
+    session.frame().unwrap();
+    session.frame().unwrap();
+    match session.frame().unwrap_err() {
+        Error::Io(e) => assert!(matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut), "{e}"),
+        error => panic!("Unexpected error: {error}"),
+    }
+}
+
+/// `--truncate-after` sends half a frame and closes the connection; the frame iterator fuses afterwards
+#[test]
+fn truncate() {
+    let mock = Mock::start(&["--truncate-after", "1"]);
+    let mut session = mock.connect().login(PIN).unwrap();
+    let mut frames = session.frames();
+    frames.next().unwrap().unwrap();
+    assert_io(&frames.next().unwrap().unwrap_err());
+    assert!(frames.next().is_none());
+}
+
+/// `--disconnect-after` closes the connection after complete frames
+#[test]
+fn disconnect() {
+    let mock = Mock::start(&["--disconnect-after", "2"]);
+    let mut session = mock.connect().login(PIN).unwrap();
+    let frames: Vec<_> = session.frames().collect();
+    assert_eq!(frames.len(), 3);
+    assert!(frames[..2].iter().all(Result::is_ok));
+    assert_io(frames[2].as_ref().unwrap_err());
+}